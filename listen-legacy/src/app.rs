@@ -23,6 +23,10 @@ pub struct Args {
 
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub tokio_console: Option<bool>,
+
+    /// never prompt for swap confirmation
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub auto_confirm: Option<bool>,
 }
 
 #[derive(Debug, Parser)]
@@ -114,12 +118,27 @@ pub enum Command {
     Price {
         #[arg(long)]
         amm_pool: String,
+
+        /// export the pool reserves and price on the metrics server
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        metrics: Option<bool>,
     },
     BenchRPC {
         #[arg(long)]
         rpc_url: String,
     },
-    PriorityFee {},
+    PriorityFee {
+        #[arg(long, default_value_t = 20)]
+        lookback_slots: usize,
+
+        #[arg(long, default_value_t = 50)]
+        percentile: u8,
+
+        /// also estimate the time-to-confirmation of this fee
+        /// (micro-lamports per CU)
+        #[arg(long)]
+        estimate_fee: Option<u64>,
+    },
     Tx {
         #[arg(short, long)]
         signature: String,
@@ -159,6 +178,33 @@ pub enum Command {
         #[arg(long)]
         amm_pool_id: Option<String>,
 
+        /// minimum output amount, overrides the slippage (raydium only)
+        #[arg(long)]
+        min_out: Option<u64>,
+
+        /// minimum output amount in ui units of the output mint, converted
+        /// with its decimals or the decimals override (raydium only)
+        #[arg(long, conflicts_with = "min_out")]
+        min_out_ui: Option<f64>,
+
+        /// abort if the RPC is more slots behind than this (raydium only),
+        /// compared against REFERENCE_RPC_URL when set
+        #[arg(long)]
+        max_rpc_lag: Option<u64>,
+
+        /// export the lag measured by --max-rpc-lag on the metrics server
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        metrics: Option<bool>,
+
+        /// abort if the output re-quoted right before signing is worse than
+        /// the initial quote by more than this many bps (raydium only)
+        #[arg(long)]
+        max_drift_bps: Option<u64>,
+
+        /// override the decimals of a mint, format <mint>:<decimals>
+        #[arg(long)]
+        decimals_override: Vec<String>,
+
         #[clap(short, long, action = clap::ArgAction::SetTrue)]
         yes: Option<bool>,
     },
@@ -0,0 +1,221 @@
+//! programmable approval of the swaps, an `ApprovalRequest` describing the
+//! trade is handed to the `ApprovalHook` of the `Raydium` instance before the
+//! tx is built, the hook approves, rejects or modifies it; without a hook the
+//! interactive confirmation prompt is the gate
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::constants;
+use crate::raydium::{FeeInfo, RaydiumError};
+use crate::router::Venue;
+use crate::TradeSide;
+
+/// ApprovalRequest is the swap about to be built, amounts are raw, min_out is
+/// the explicit one or the expected output less the slippage, impact is the
+/// price impact of the trade on the pool and fee_amount the pool fee taken
+/// of the input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApprovalRequest {
+    pub venue: Venue,
+    pub pool: String,
+    /// side is relative to the non-SOL token, None if neither side is SOL
+    pub side: Option<TradeSide>,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub amount: u64,
+    pub expected_out: u64,
+    pub min_out: u64,
+    pub slippage_bps: u64,
+    pub price_impact_bps: u64,
+    pub fee: FeeInfo,
+    pub fee_amount: u64,
+}
+
+/// side_of is the side of a swap from input_mint to output_mint
+pub fn side_of(
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+) -> Option<TradeSide> {
+    if *input_mint == constants::SOLANA_PROGRAM_ID {
+        Some(TradeSide::Buy)
+    } else if *output_mint == constants::SOLANA_PROGRAM_ID {
+        Some(TradeSide::Sell)
+    } else {
+        None
+    }
+}
+
+/// price_impact_bps of a constant product swap is
+/// amount_in / (reserve_in + amount_in)
+pub fn price_impact_bps(reserve_in: u64, amount_in: u64) -> u64 {
+    if reserve_in == 0 && amount_in == 0 {
+        return 0;
+    }
+    (amount_in as u128 * 10_000 / (reserve_in as u128 + amount_in as u128))
+        as u64
+}
+
+/// SwapTerms are the parts of a swap the hook can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapTerms {
+    pub amount: u64,
+    pub slippage_bps: u64,
+    /// min_out: explicit minimum output, overrides the slippage
+    pub min_out: Option<u64>,
+}
+
+/// SwapModification replaces the terms that are set, a modified amount
+/// keeps an explicit min_out unless that is modified too
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapModification {
+    pub amount: Option<u64>,
+    pub slippage_bps: Option<u64>,
+    pub min_out: Option<u64>,
+}
+
+/// Approval is the decision of the hook,
+/// e.g. `{"decision":"modify","amount":1000}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Approval {
+    Approve,
+    Reject { reason: String },
+    Modify(SwapModification),
+}
+
+impl Approval {
+    /// resolve returns the terms to swap with, a rejection or a
+    /// modification that makes no sense is `RaydiumError::Rejected`
+    pub fn resolve(self, terms: SwapTerms) -> Result<SwapTerms, RaydiumError> {
+        let modification = match self {
+            Approval::Approve => return Ok(terms),
+            Approval::Reject { reason } => {
+                return Err(RaydiumError::Rejected { reason })
+            }
+            Approval::Modify(modification) => modification,
+        };
+        let resolved = SwapTerms {
+            amount: modification.amount.unwrap_or(terms.amount),
+            slippage_bps: modification
+                .slippage_bps
+                .unwrap_or(terms.slippage_bps),
+            min_out: modification.min_out.or(terms.min_out),
+        };
+        let invalid = if resolved.amount == 0 {
+            Some("modified amount is 0")
+        } else if resolved.slippage_bps > 10_000 {
+            Some("modified slippage is above 10000bps")
+        } else {
+            None
+        };
+        match invalid {
+            Some(reason) => Err(RaydiumError::Rejected {
+                reason: reason.to_string(),
+            }),
+            None => Ok(resolved),
+        }
+    }
+}
+
+/// ApprovalHook decides on the swaps before they are built, any
+/// `Fn(&ApprovalRequest) -> Approval` is a hook, implement the trait
+/// directly to reach out to an external system
+pub trait ApprovalHook: Send + Sync {
+    fn review<'a>(
+        &'a self,
+        request: &'a ApprovalRequest,
+    ) -> BoxFuture<'a, Approval>;
+}
+
+impl<F> ApprovalHook for F
+where
+    F: Fn(&ApprovalRequest) -> Approval + Send + Sync,
+{
+    fn review<'a>(
+        &'a self,
+        request: &'a ApprovalRequest,
+    ) -> BoxFuture<'a, Approval> {
+        let approval = self(request);
+        Box::pin(async move { approval })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raydium::{apply_slippage, RaydiumProgram, SlippageRounding};
+
+    fn request() -> ApprovalRequest {
+        ApprovalRequest {
+            venue: Venue::Raydium,
+            pool: Pubkey::default().to_string(),
+            side: side_of(&constants::SOLANA_PROGRAM_ID, &Pubkey::default()),
+            input_mint: constants::SOLANA_PROGRAM_ID.to_string(),
+            output_mint: Pubkey::default().to_string(),
+            amount: 1_000,
+            expected_out: 50_000,
+            min_out: apply_slippage(50_000, 100, SlippageRounding::Floor),
+            slippage_bps: 100,
+            price_impact_bps: price_impact_bps(99_000, 1_000),
+            fee: FeeInfo::new(RaydiumProgram::AmmV4, 25, 10_000),
+            fee_amount: 2,
+        }
+    }
+
+    #[test]
+    fn test_approval_request() {
+        let request = request();
+        assert_eq!(request.side, Some(TradeSide::Buy));
+        assert_eq!(request.min_out, 49_500);
+        assert_eq!(request.price_impact_bps, 10);
+        assert_eq!(price_impact_bps(0, 0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_approval_hook() {
+        let hook = |request: &ApprovalRequest| {
+            if request.price_impact_bps > 5 {
+                Approval::Modify(SwapModification {
+                    amount: Some(request.amount / 2),
+                    ..Default::default()
+                })
+            } else {
+                Approval::Approve
+            }
+        };
+        let request = request();
+        let terms = SwapTerms {
+            amount: request.amount,
+            slippage_bps: request.slippage_bps,
+            min_out: None,
+        };
+        let approval = hook.review(&request).await;
+        assert_eq!(
+            approval.resolve(terms).unwrap(),
+            SwapTerms {
+                amount: 500,
+                ..terms
+            }
+        );
+        assert_eq!(Approval::Approve.resolve(terms).unwrap(), terms);
+
+        let rejected = Approval::Reject {
+            reason: "impact".to_string(),
+        };
+        assert!(matches!(
+            rejected.resolve(terms),
+            Err(RaydiumError::Rejected { reason }) if reason == "impact"
+        ));
+        let zeroed = Approval::Modify(SwapModification {
+            amount: Some(0),
+            ..Default::default()
+        });
+        assert!(zeroed.resolve(terms).is_err());
+
+        let decision: Approval =
+            serde_json::from_str(r#"{"decision":"modify","min_out":1}"#)
+                .unwrap();
+        assert_eq!(decision.resolve(terms).unwrap().min_out, Some(1),);
+    }
+}
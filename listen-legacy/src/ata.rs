@@ -13,8 +13,10 @@ use std::error::Error;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::constants;
 use crate::pump::TOKEN_PROGRAM;
 use crate::util::env;
+use crate::Provider;
 
 pub async fn close_all_atas(
     rpc_client: Arc<RpcClient>,
@@ -64,3 +66,24 @@ pub async fn close_all_atas(
 
     Ok(())
 }
+
+/// token_program_of returns the token program the mint belongs to (the
+/// owner of the mint account), the classic one or Token-2022, ATAs have to
+/// be derived and created under it; SOL resolves to the classic program
+pub async fn token_program_of(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Pubkey, Box<dyn Error>> {
+    if *mint == constants::SOLANA_PROGRAM_ID {
+        return Ok(spl_token::id());
+    }
+    let owner = Provider::mint_info(rpc_client, mint).await?.owner;
+    if owner != spl_token::id() && owner != spl_token_2022::id() {
+        return Err(format!(
+            "mint {} is owned by {}, not a token program",
+            mint, owner
+        )
+        .into());
+    }
+    Ok(owner)
+}
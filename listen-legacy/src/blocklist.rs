@@ -0,0 +1,178 @@
+//! blocklist of the accounts (deployers, authorities, LP owners) of known
+//! rugs, pools associated with any of them are rejected by `Raydium::swap`
+//! (through `RaydiumBuilder::blocklist`) and by the new pool checks (through
+//! `set_global`)
+//!
+//! the list is one pubkey per line, anything after a `#` is a comment, it
+//! can be loaded from a file or a http(s) URL
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use log::info;
+use serde::Serialize;
+use solana_sdk::program_option::COption;
+use solana_sdk::pubkey::Pubkey;
+
+/// BlocklistHit is the blocklisted account a pool is associated with, role
+/// is how, e.g. "mint authority"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BlocklistHit {
+    pub role: &'static str,
+    pub account: Pubkey,
+}
+
+impl std::fmt::Display for BlocklistHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} is blocklisted", self.role, self.account)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Blocklist {
+    accounts: HashSet<Pubkey>,
+}
+
+impl Blocklist {
+    pub fn new(accounts: impl IntoIterator<Item = Pubkey>) -> Self {
+        Blocklist {
+            accounts: accounts.into_iter().collect(),
+        }
+    }
+
+    /// parse reads the list, failing on the first line that is not a pubkey
+    pub fn parse(list: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut accounts = HashSet::new();
+        for (idx, line) in list.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let account = Pubkey::from_str(entry).map_err(|e| {
+                format!("blocklist line {}: {:?}: {}", idx + 1, entry, e)
+            })?;
+            accounts.insert(account);
+        }
+        Ok(Blocklist { accounts })
+    }
+
+    pub fn from_file(
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Blocklist::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub async fn from_url(
+        url: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let list = reqwest::get(url).await?.error_for_status()?.text().await?;
+        Blocklist::parse(&list)
+    }
+
+    /// load takes a http(s) URL or a path
+    pub async fn load(
+        source: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let blocklist = if source.starts_with("http://")
+            || source.starts_with("https://")
+        {
+            Blocklist::from_url(source).await?
+        } else {
+            Blocklist::from_file(source)?
+        };
+        info!("blocklist of {} accounts from {}", blocklist.len(), source);
+        Ok(blocklist)
+    }
+
+    pub fn insert(&mut self, account: Pubkey) -> bool {
+        self.accounts.insert(account)
+    }
+
+    pub fn contains(&self, account: &Pubkey) -> bool {
+        self.accounts.contains(account)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// check returns the first of the (role, account) pairs of a pool that
+    /// is blocklisted
+    pub fn check(
+        &self,
+        associated: &[(&'static str, Pubkey)],
+    ) -> Option<BlocklistHit> {
+        associated
+            .iter()
+            .find(|(_, account)| self.contains(account))
+            .map(|&(role, account)| BlocklistHit { role, account })
+    }
+}
+
+/// authorities of a mint as (role, account), the renounced ones are left
+/// out
+pub fn authorities(
+    mint_authority: COption<Pubkey>,
+    freeze_authority: COption<Pubkey>,
+) -> Vec<(&'static str, Pubkey)> {
+    [
+        ("mint authority", Option::from(mint_authority)),
+        ("freeze authority", Option::from(freeze_authority)),
+    ]
+    .into_iter()
+    .filter_map(|(role, account)| account.map(|account| (role, account)))
+    .collect()
+}
+
+static GLOBAL: OnceLock<Arc<Blocklist>> = OnceLock::new();
+
+/// set_global sets the blocklist of the new pool checks, once per process,
+/// returns false if it was already set
+pub fn set_global(blocklist: Arc<Blocklist>) -> bool {
+    GLOBAL.set(blocklist).is_ok()
+}
+
+pub fn global() -> Option<&'static Blocklist> {
+    GLOBAL.get().map(|blocklist| blocklist.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist() {
+        let (deployer, authority) =
+            (Pubkey::new_unique(), Pubkey::new_unique());
+        let list = format!(
+            "# known rugs\n{}\n\n  {}  # reused mint authority\n",
+            deployer, authority
+        );
+        let blocklist = Blocklist::parse(&list).unwrap();
+        assert_eq!(blocklist.len(), 2);
+
+        let clean = Pubkey::new_unique();
+        assert_eq!(blocklist.check(&[("deployer", clean)]), None);
+        let hit = blocklist
+            .check(&[("deployer", clean), ("mint authority", authority)])
+            .unwrap();
+        assert_eq!(hit.role, "mint authority");
+        assert_eq!(hit.account, authority);
+        assert_eq!(
+            hit.to_string(),
+            format!("mint authority {} is blocklisted", authority)
+        );
+
+        let associated = authorities(COption::Some(authority), COption::None);
+        assert_eq!(associated, vec![("mint authority", authority)]);
+        assert!(blocklist.check(&associated).is_some());
+
+        assert!(Blocklist::parse("not-a-pubkey").is_err());
+        assert!(Blocklist::parse("# nothing\n").unwrap().is_empty());
+    }
+}
@@ -21,6 +21,7 @@ use solana_transaction_status::{
 use spl_token::state::Mint;
 
 use crate::{
+    blocklist,
     buyer::check_if_pump_fun,
     constants,
     util::{env, pubkey_to_string, string_to_pubkey},
@@ -41,11 +42,15 @@ pub struct Checklist {
         deserialize_with = "string_to_pubkey"
     )]
     pub mint: Pubkey,
+    /// blocklisted: the blocklisted account the pool is associated with
+    #[serde(default)]
+    pub blocklisted: Option<String>,
 }
 
 impl Checklist {
     pub fn all_clear(&self) -> bool {
-        !self.is_pump_fun
+        self.blocklisted.is_none()
+            && !self.is_pump_fun
             && self.lp_burnt
             && self.mint_authority_renounced
             && self.freeze_authority_renounced
@@ -165,6 +170,16 @@ pub async fn _run_checks(
         ..Default::default()
     };
 
+    // a pool deployed by a blocklisted wallet is skipped, pump funs included
+    let deployer = [("deployer", accounts.user_wallet)];
+    if let Some(hit) =
+        blocklist::global().and_then(|blocklist| blocklist.check(&deployer))
+    {
+        warn!("{}: {}", mint, hit);
+        checklist.blocklisted = Some(hit.to_string());
+        return Ok((false, checklist));
+    }
+
     // could be insta-sniping the pump fun launches, generally I am pretty fast
     // (~10 slots) so sniping pumpfuns since they pass all checks is ok
     let is_pump_fun = check_if_pump_fun(&mint).await?;
@@ -241,6 +256,20 @@ pub async fn _run_checks(
             }
         };
         let mint_account = Mint::unpack(&account.data).unwrap();
+        // same roles as `Raydium::pool_associates`, the deployer is checked
+        // above
+        let mut associates = blocklist::authorities(
+            mint_account.mint_authority,
+            mint_account.freeze_authority,
+        );
+        associates.push(("LP owner", lp_account.owner));
+        if let Some(hit) = blocklist::global()
+            .and_then(|blocklist| blocklist.check(&associates))
+        {
+            warn!("{}: {}", mint, hit);
+            checklist.blocklisted = Some(hit.to_string());
+            return Ok((false, checklist));
+        }
         if mint_account.mint_authority.is_none() {
             checklist.mint_authority_renounced = true;
         }
@@ -0,0 +1,198 @@
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{constants, raydium::Raydium};
+
+/// QuoteTokens decides which side of a pool is the quote, the known quote
+/// mints are tried in order (so USDC wins over SOL in a SOL/USDC pool), if
+/// neither side is known the fallback reference is tried, and if that does
+/// not match either the pc side is taken as the quote
+#[derive(Debug, Clone)]
+pub struct QuoteTokens {
+    pub known: Vec<Pubkey>,
+    pub fallback: Option<Pubkey>,
+}
+
+impl Default for QuoteTokens {
+    fn default() -> Self {
+        QuoteTokens {
+            known: vec![
+                constants::USDC_TOKEN_PUBKEY,
+                constants::USDT_TOKEN_PUBKEY,
+                constants::SOLANA_PROGRAM_ID,
+            ],
+            fallback: None,
+        }
+    }
+}
+
+impl QuoteTokens {
+    pub fn with_fallback(mut self, fallback: Pubkey) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// detect picks the quote out of the coin and pc mints of a pool
+    pub fn detect(&self, coin_mint: Pubkey, pc_mint: Pubkey) -> Denomination {
+        let known = self
+            .known
+            .iter()
+            .chain(self.fallback.iter())
+            .find(|mint| **mint == coin_mint || **mint == pc_mint);
+        let (quote_is_coin, recognized) = match known {
+            Some(mint) => (*mint == coin_mint, true),
+            None => (false, false),
+        };
+        let (base_mint, quote_mint) = if quote_is_coin {
+            (pc_mint, coin_mint)
+        } else {
+            (coin_mint, pc_mint)
+        };
+        Denomination {
+            base_mint,
+            quote_mint,
+            quote_is_coin,
+            recognized,
+        }
+    }
+}
+
+/// Denomination is the split of a pool into base and quote, recognized is
+/// false if the quote was picked by the pc side convention only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Denomination {
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub quote_is_coin: bool,
+    pub recognized: bool,
+}
+
+/// QuotedPool converts between the base and the quote of a pool at its
+/// spot price, all of the ui amounts are decimal-adjusted
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QuotedPool {
+    pub denomination: Denomination,
+    pub base_reserve: u64,
+    pub quote_reserve: u64,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+}
+
+impl QuotedPool {
+    /// price is the spot price of one base token in the quote
+    pub fn price(&self) -> Option<f64> {
+        if self.base_reserve == 0 {
+            return None;
+        }
+        Some(
+            to_ui(self.quote_reserve, self.quote_decimals)
+                / to_ui(self.base_reserve, self.base_decimals),
+        )
+    }
+
+    /// to_quote values a raw base amount in the quote (ui)
+    pub fn to_quote(&self, base_amount: u64) -> Option<f64> {
+        Some(to_ui(base_amount, self.base_decimals) * self.price()?)
+    }
+
+    /// to_base values a raw quote amount in the base (ui)
+    pub fn to_base(&self, quote_amount: u64) -> Option<f64> {
+        let price = self.price().filter(|price| *price > 0.)?;
+        Some(to_ui(quote_amount, self.quote_decimals) / price)
+    }
+
+    /// pnl is the profit in the quote of holding base_amount (raw) bought
+    /// for cost (raw quote)
+    pub fn pnl(&self, base_amount: u64, cost: u64) -> Option<f64> {
+        Some(self.to_quote(base_amount)? - to_ui(cost, self.quote_decimals))
+    }
+}
+
+fn to_ui(amount: u64, decimals: u8) -> f64 {
+    amount as f64 / 10f64.powi(decimals as i32)
+}
+
+impl Raydium {
+    /// quoted_pool reads the reserves of the pool and splits it into base
+    /// and quote with quote_tokens
+    pub async fn quoted_pool(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        quote_tokens: &QuoteTokens,
+    ) -> Result<QuotedPool, Box<dyn std::error::Error>> {
+        let (result, _, keys) =
+            crate::raydium::get_calc_result(rpc_client, amm_pool).await?;
+        let denomination =
+            quote_tokens.detect(keys.amm_coin_mint, keys.amm_pc_mint);
+        let (base_reserve, quote_reserve) = if denomination.quote_is_coin {
+            (result.pool_pc_vault_amount, result.pool_coin_vault_amount)
+        } else {
+            (result.pool_coin_vault_amount, result.pool_pc_vault_amount)
+        };
+        Ok(QuotedPool {
+            denomination,
+            base_reserve,
+            quote_reserve,
+            base_decimals: self
+                .decimals(rpc_client, &denomination.base_mint)
+                .await?,
+            quote_decimals: self
+                .decimals(rpc_client, &denomination.quote_mint)
+                .await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_quote() {
+        let token = Pubkey::new_unique();
+        let quote_tokens = QuoteTokens::default();
+
+        let sol_pool =
+            quote_tokens.detect(constants::SOLANA_PROGRAM_ID, token);
+        assert_eq!(sol_pool.quote_mint, constants::SOLANA_PROGRAM_ID);
+        assert!(sol_pool.quote_is_coin && sol_pool.recognized);
+
+        // stables take precedence over SOL
+        let sol_usdc = quote_tokens.detect(
+            constants::SOLANA_PROGRAM_ID,
+            constants::USDC_TOKEN_PUBKEY,
+        );
+        assert_eq!(sol_usdc.quote_mint, constants::USDC_TOKEN_PUBKEY);
+
+        let other = Pubkey::new_unique();
+        let unknown = quote_tokens.detect(token, other);
+        assert_eq!(unknown.quote_mint, other);
+        assert!(!unknown.recognized);
+
+        let reference = quote_tokens.with_fallback(token).detect(token, other);
+        assert_eq!(reference.quote_mint, token);
+        assert!(reference.recognized);
+    }
+
+    #[test]
+    fn test_quoted_pool_conversions() {
+        let pool = QuotedPool {
+            denomination: QuoteTokens::default()
+                .detect(Pubkey::new_unique(), constants::SOLANA_PROGRAM_ID),
+            // 1M tokens (6 decimals) against 100 SOL
+            base_reserve: 1_000_000_000_000,
+            quote_reserve: 100_000_000_000,
+            base_decimals: 6,
+            quote_decimals: 9,
+        };
+        assert!((pool.price().unwrap() - 0.0001).abs() < 1e-12);
+        assert!((pool.to_quote(10_000_000_000).unwrap() - 1.).abs() < 1e-9);
+        assert!((pool.to_base(1_000_000_000).unwrap() - 10_000.).abs() < 1e-6);
+        assert!(
+            (pool.pnl(10_000_000_000, 500_000_000).unwrap() - 0.5).abs()
+                < 1e-9
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use std::str::FromStr;
+use tokio::sync::mpsc::{
+    unbounded_channel, UnboundedReceiver, UnboundedSender,
+};
+
+use crate::raydium::{Raydium, SwapArgs, SwapEvent};
+
+/// SwapIntent is a swap submitted to the engine, amounts are raw
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapIntent {
+    /// id is echoed back in the events of the intent
+    pub id: String,
+    pub amm_pool: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub amount: u64,
+    pub slippage_bps: u64,
+    pub min_out: Option<u64>,
+}
+
+/// SwapUpdate is a SwapEvent of the intent with the given id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapUpdate {
+    pub id: String,
+    #[serde(flatten)]
+    pub event: SwapEvent,
+}
+
+type Job = (SwapIntent, UnboundedSender<SwapUpdate>);
+
+/// SwapEngine executes swap intents one at a time with a single wallet, on
+/// its own thread, the handle can be cloned and shared across tasks
+#[derive(Clone)]
+pub struct SwapEngine {
+    jobs: UnboundedSender<Job>,
+}
+
+impl SwapEngine {
+    pub fn spawn(raydium: Raydium, rpc_url: String, wallet: Keypair) -> Self {
+        let (jobs, receiver) = unbounded_channel();
+        let raydium = Arc::new(raydium);
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("engine runtime");
+            runtime.block_on(run(raydium, rpc_url, wallet, receiver));
+        });
+        SwapEngine { jobs }
+    }
+
+    /// submit queues the intent, its updates arrive on the returned channel
+    /// which closes once the intent is done
+    pub fn submit(
+        &self,
+        intent: SwapIntent,
+    ) -> Result<UnboundedReceiver<SwapUpdate>, Box<dyn std::error::Error>>
+    {
+        let (updates, receiver) = unbounded_channel();
+        self.submit_to(intent, updates)?;
+        Ok(receiver)
+    }
+
+    /// submit_to is submit that reports to an existing channel, so that the
+    /// updates of many intents can be multiplexed over one stream
+    pub fn submit_to(
+        &self,
+        intent: SwapIntent,
+        updates: UnboundedSender<SwapUpdate>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.jobs
+            .send((intent, updates))
+            .map_err(|_| "swap engine stopped".into())
+    }
+}
+
+async fn run(
+    raydium: Arc<Raydium>,
+    rpc_url: String,
+    wallet: Keypair,
+    mut jobs: UnboundedReceiver<Job>,
+) {
+    while let Some((intent, updates)) = jobs.recv().await {
+        let id = intent.id.clone();
+        info!("swap intent {}: {:?}", id, intent);
+        let (events, mut received) = unbounded_channel();
+        let swap = async {
+            let args = swap_args(&intent, &rpc_url, &wallet)
+                .map_err(|e| e.to_string())?;
+            // events is moved in so that the forwarding below stops once the
+            // swap is done
+            let events = events;
+            raydium
+                .execute_swap_with_events(&args, Some(&events))
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        let forward = async {
+            while let Some(event) = received.recv().await {
+                let _ = updates.send(SwapUpdate {
+                    id: id.clone(),
+                    event,
+                });
+            }
+        };
+        let (result, ()) = tokio::join!(swap, forward);
+        if let Err(error) = result {
+            warn!("swap intent {} failed: {}", id, error);
+            let _ = updates.send(SwapUpdate {
+                id,
+                event: SwapEvent::Failed { error },
+            });
+        }
+    }
+}
+
+fn swap_args(
+    intent: &SwapIntent,
+    rpc_url: &str,
+    wallet: &Keypair,
+) -> Result<SwapArgs, Box<dyn std::error::Error>> {
+    // there is no prompt, an intent has to bound its output on its own
+    if !intent.min_out.is_some_and(|min_out| min_out > 0)
+        && !(1..10_000).contains(&intent.slippage_bps)
+    {
+        return Err(format!(
+            "intent {} needs a min_out or a slippage_bps within 1..10000",
+            intent.id
+        )
+        .into());
+    }
+    Ok(SwapArgs {
+        amm_pool: Pubkey::from_str(&intent.amm_pool)?,
+        input_token_mint: Pubkey::from_str(&intent.input_mint)?,
+        output_token_mint: Pubkey::from_str(&intent.output_mint)?,
+        amount: intent.amount,
+        slippage: intent.slippage_bps,
+        wallet: Keypair::from_bytes(&wallet.to_bytes())?,
+        rpc_client: RpcClient::new(rpc_url.to_string()),
+        // the submitter is the one approving the intent
+        confirmed: true,
+        // the quick path still derives min_out from slippage_bps
+        no_sanity: true,
+        min_out: intent.min_out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_args_bounds_the_output() {
+        let wallet = Keypair::new();
+        let intent = |slippage_bps, min_out| SwapIntent {
+            id: "intent".to_string(),
+            amm_pool: Pubkey::new_unique().to_string(),
+            input_mint: Pubkey::new_unique().to_string(),
+            output_mint: Pubkey::new_unique().to_string(),
+            amount: 1_000,
+            slippage_bps,
+            min_out,
+        };
+        let rpc_url = "http://127.0.0.1:1";
+        assert!(swap_args(&intent(0, None), rpc_url, &wallet).is_err());
+        assert!(swap_args(&intent(0, Some(0)), rpc_url, &wallet).is_err());
+        assert!(swap_args(&intent(10_000, None), rpc_url, &wallet).is_err());
+        let args = swap_args(&intent(50, None), rpc_url, &wallet).unwrap();
+        assert_eq!((args.slippage, args.min_out), (50, None));
+        let args = swap_args(&intent(0, Some(900)), rpc_url, &wallet).unwrap();
+        assert_eq!(args.min_out, Some(900));
+    }
+}
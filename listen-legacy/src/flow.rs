@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{Swap, TradeSide};
+
+/// Flow is the SOL volume on either side of a pool within a window, net is
+/// buys minus sells, positive under buying pressure
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct Flow {
+    pub window_secs: u64,
+    pub buys: usize,
+    pub sells: usize,
+    pub buy_volume_sol: f64,
+    pub sell_volume_sol: f64,
+    pub net_flow_sol: f64,
+}
+
+impl Flow {
+    /// imbalance is net flow over the total volume (-1 to 1), 0 if there
+    /// was no volume
+    pub fn imbalance(&self) -> f64 {
+        let total = self.buy_volume_sol + self.sell_volume_sol;
+        if total > 0. {
+            self.net_flow_sol / total
+        } else {
+            0.
+        }
+    }
+}
+
+struct Trade {
+    at: Instant,
+    side: TradeSide,
+    sol_amount: f64,
+}
+
+/// FlowAggregator keeps rolling buy and sell volumes of a pool over the
+/// configured windows, every window only counts the swaps within its own
+/// span, so a window "resets" as its oldest swaps fall out; safe to share
+/// between the listener and the readers behind an Arc
+pub struct FlowAggregator {
+    windows: Vec<Duration>,
+    trades: Mutex<VecDeque<Trade>>,
+}
+
+impl FlowAggregator {
+    pub fn new(windows: Vec<Duration>) -> Self {
+        let mut windows = windows;
+        windows.sort();
+        windows.dedup();
+        FlowAggregator {
+            windows,
+            trades: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn windows(&self) -> &[Duration] {
+        &self.windows
+    }
+
+    /// record adds a parsed swap, swaps without a known side are skipped
+    pub fn record(&self, swap: &Swap) {
+        if let Some(side) = swap.side {
+            self.record_at(Instant::now(), side, swap.sol_amount_ui);
+        }
+    }
+
+    pub fn record_at(&self, at: Instant, side: TradeSide, sol_amount: f64) {
+        let mut trades = self.trades.lock().expect("flow poisoned");
+        trades.push_back(Trade {
+            at,
+            side,
+            sol_amount,
+        });
+        self.prune(&mut trades, at);
+    }
+
+    /// flow sums the swaps of the last window, which does not have to be
+    /// one of the configured ones as long as it is not longer than them
+    pub fn flow(&self, window: Duration) -> Flow {
+        self.flow_at(Instant::now(), window)
+    }
+
+    pub fn flow_at(&self, now: Instant, window: Duration) -> Flow {
+        let mut trades = self.trades.lock().expect("flow poisoned");
+        self.prune(&mut trades, now);
+        let mut flow = Flow {
+            window_secs: window.as_secs(),
+            ..Default::default()
+        };
+        for trade in trades.iter().rev() {
+            if now.saturating_duration_since(trade.at) > window {
+                break;
+            }
+            match trade.side {
+                TradeSide::Buy => {
+                    flow.buys += 1;
+                    flow.buy_volume_sol += trade.sol_amount;
+                }
+                TradeSide::Sell => {
+                    flow.sells += 1;
+                    flow.sell_volume_sol += trade.sol_amount;
+                }
+            }
+        }
+        flow.net_flow_sol = flow.buy_volume_sol - flow.sell_volume_sol;
+        flow
+    }
+
+    /// snapshot is the flow of every configured window, shortest first
+    pub fn snapshot(&self) -> Vec<Flow> {
+        let now = Instant::now();
+        self.windows
+            .iter()
+            .map(|window| self.flow_at(now, *window))
+            .collect()
+    }
+
+    /// reset drops all of the recorded swaps
+    pub fn reset(&self) {
+        self.trades.lock().expect("flow poisoned").clear();
+    }
+
+    fn prune(&self, trades: &mut VecDeque<Trade>, now: Instant) {
+        let Some(longest) = self.windows.last() else {
+            trades.clear();
+            return;
+        };
+        while trades.front().is_some_and(|trade| {
+            now.saturating_duration_since(trade.at) > *longest
+        }) {
+            trades.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_windows() {
+        let aggregator = FlowAggregator::new(vec![
+            Duration::from_secs(60),
+            Duration::from_secs(10),
+        ]);
+        let start = Instant::now();
+        aggregator.record_at(start, TradeSide::Buy, 5.);
+        aggregator.record_at(
+            start + Duration::from_secs(55),
+            TradeSide::Sell,
+            2.,
+        );
+        aggregator.record_at(
+            start + Duration::from_secs(58),
+            TradeSide::Buy,
+            1.,
+        );
+
+        let now = start + Duration::from_secs(59);
+        let short = aggregator.flow_at(now, Duration::from_secs(10));
+        assert_eq!((short.buys, short.sells), (1, 1));
+        assert_eq!(short.net_flow_sol, -1.);
+        let long = aggregator.flow_at(now, Duration::from_secs(60));
+        assert_eq!(long.net_flow_sol, 4.);
+        assert_eq!(long.imbalance(), 0.5);
+
+        // the first buy falls out of the longest window
+        let later = start + Duration::from_secs(61);
+        let long = aggregator.flow_at(later, Duration::from_secs(60));
+        assert_eq!((long.buys, long.sells), (1, 1));
+
+        aggregator.reset();
+        assert_eq!(aggregator.flow_at(later, Duration::from_secs(60)).buys, 0);
+    }
+}
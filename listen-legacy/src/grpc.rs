@@ -0,0 +1,214 @@
+//! gRPC front of the `SwapEngine`, a single bidirectional streaming method
+//! `/listen.SwapEngine/Swap` taking a stream of `SwapIntent`s and returning
+//! the stream of `SwapUpdate`s of all of them; messages are JSON encoded
+//! (content-type application/grpc+json), so no protobuf codegen is needed
+//!
+//! anyone reaching the server swaps with the engine wallet, `serve` only
+//! binds loopback addresses
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+use bytes::{Buf, BufMut};
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::mpsc::unbounded_channel;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::{
+    empty_body, http, Body, BoxFuture, Context, Poll, Service, StdError,
+};
+use tonic::server::{Grpc, NamedService, StreamingService};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::engine::{SwapEngine, SwapIntent, SwapUpdate};
+
+const SWAP_PATH: &str = "/listen.SwapEngine/Swap";
+
+pub struct JsonCodec<E, D>(PhantomData<fn() -> (E, D)>);
+
+impl<E, D> Default for JsonCodec<E, D> {
+    fn default() -> Self {
+        JsonCodec(PhantomData)
+    }
+}
+
+impl<E, D> Codec for JsonCodec<E, D>
+where
+    E: Serialize + Send + 'static,
+    D: DeserializeOwned + Send + 'static,
+{
+    type Encode = E;
+    type Decode = D;
+    type Encoder = JsonEncoder<E>;
+    type Decoder = JsonDecoder<D>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        JsonEncoder(PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        JsonDecoder(PhantomData)
+    }
+}
+
+pub struct JsonEncoder<E>(PhantomData<fn() -> E>);
+
+impl<E: Serialize> Encoder for JsonEncoder<E> {
+    type Item = E;
+    type Error = Status;
+
+    fn encode(
+        &mut self,
+        item: Self::Item,
+        dst: &mut EncodeBuf<'_>,
+    ) -> Result<(), Self::Error> {
+        serde_json::to_writer(dst.writer(), &item)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+pub struct JsonDecoder<D>(PhantomData<fn() -> D>);
+
+impl<D: DeserializeOwned> Decoder for JsonDecoder<D> {
+    type Item = D;
+    type Error = Status;
+
+    fn decode(
+        &mut self,
+        src: &mut DecodeBuf<'_>,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        if !src.has_remaining() {
+            return Ok(None);
+        }
+        serde_json::from_reader(src.reader())
+            .map(Some)
+            .map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+}
+
+type UpdateStream = std::pin::Pin<
+    Box<dyn futures_util::Stream<Item = Result<SwapUpdate, Status>> + Send>,
+>;
+
+/// SwapEngineServer is the tonic service, mount it with
+/// `tonic::transport::Server::builder().add_service(..)` or use `serve`
+#[derive(Clone)]
+pub struct SwapEngineServer {
+    engine: SwapEngine,
+}
+
+impl SwapEngineServer {
+    pub fn new(engine: SwapEngine) -> Self {
+        SwapEngineServer { engine }
+    }
+}
+
+impl NamedService for SwapEngineServer {
+    const NAME: &'static str = "listen.SwapEngine";
+}
+
+struct SwapSvc(SwapEngine);
+
+impl StreamingService<SwapIntent> for SwapSvc {
+    type Response = SwapUpdate;
+    type ResponseStream = UpdateStream;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(
+        &mut self,
+        request: Request<Streaming<SwapIntent>>,
+    ) -> Self::Future {
+        let engine = self.0.clone();
+        Box::pin(async move {
+            let mut intents = request.into_inner();
+            let (updates, mut received) = unbounded_channel();
+            tokio::spawn(async move {
+                loop {
+                    match intents.message().await {
+                        Ok(Some(intent)) => {
+                            if let Err(e) =
+                                engine.submit_to(intent, updates.clone())
+                            {
+                                warn!("grpc swap: {}", e);
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(status) => {
+                            warn!("grpc swap stream: {}", status);
+                            break;
+                        }
+                    }
+                }
+            });
+            // the stream ends once the client is done sending and all of
+            // the submitted intents have finished
+            let stream = futures_util::stream::unfold(
+                received,
+                |mut received| async move {
+                    received.recv().await.map(|update| (Ok(update), received))
+                },
+            );
+            Ok(Response::new(Box::pin(stream) as UpdateStream))
+        })
+    }
+}
+
+impl<B> Service<http::Request<B>> for SwapEngineServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != SWAP_PATH {
+            return Box::pin(async move {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", "12")
+                    .header("content-type", "application/grpc")
+                    .body(empty_body())
+                    .expect("unimplemented response"))
+            });
+        }
+        let svc = SwapSvc(self.engine.clone());
+        Box::pin(async move {
+            let mut grpc =
+                Grpc::new(JsonCodec::<SwapUpdate, SwapIntent>::default());
+            Ok(grpc.streaming(svc, req).await)
+        })
+    }
+}
+
+/// serve runs the gRPC server on addr until it fails. There is no auth and
+/// the intents are signed with the engine wallet, so addr has to be a
+/// loopback one; to expose the engine, mount `SwapEngineServer` behind an
+/// auth layer instead
+pub async fn serve(
+    addr: SocketAddr,
+    engine: SwapEngine,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !addr.ip().is_loopback() {
+        return Err(format!(
+            "not serving the swap engine on {}, it has no auth, bind a \
+             loopback address",
+            addr
+        )
+        .into());
+    }
+    info!("swap engine gRPC on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(SwapEngineServer::new(engine))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
@@ -14,7 +14,7 @@ use serde_json::json;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
-use solana_sdk::system_instruction::transfer;
+use solana_sdk::system_instruction::{transfer, SystemInstruction};
 use solana_sdk::transaction::Transaction;
 use solana_sdk::{
     instruction::Instruction, transaction::VersionedTransaction,
@@ -165,18 +165,114 @@ pub async fn send_jito_tx(
     Ok(jito_response.result)
 }
 
+/// tip_amount returns the lamports the tx transfers to the jito tip
+/// accounts through the system program
+pub fn tip_amount(tx: &VersionedTransaction) -> u64 {
+    let keys = tx.message.static_account_keys();
+    tx.message
+        .instructions()
+        .iter()
+        .filter(|ix| {
+            keys.get(ix.program_id_index as usize)
+                == Some(&solana_sdk::system_program::id())
+        })
+        .filter(|ix| {
+            ix.accounts
+                .get(1)
+                .and_then(|i| keys.get(*i as usize))
+                .is_some_and(|to| constants::JITO_TIP_ACCOUNTS.contains(to))
+        })
+        .filter_map(|ix| {
+            bincode::deserialize::<SystemInstruction>(&ix.data).ok()
+        })
+        .map(|ix| match ix {
+            SystemInstruction::Transfer { lamports } => lamports,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// validate_bundle checks the bundle fits the jito limits and that the last
+/// tx pays at least `tip` to one of the tip accounts
+pub fn validate_bundle(
+    txs: &[VersionedTransaction],
+    tip: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if txs.is_empty() {
+        return Err("bundle is empty".into());
+    }
+    if txs.len() > constants::JITO_MAX_BUNDLE_SIZE {
+        return Err(format!(
+            "bundle has {} txs, max is {}",
+            txs.len(),
+            constants::JITO_MAX_BUNDLE_SIZE
+        )
+        .into());
+    }
+    let last = txs.last().expect("non-empty");
+    let paid = tip_amount(last);
+    if paid < tip {
+        return Err(format!(
+            "last tx of the bundle tips {} lamports, expected {}",
+            paid, tip
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// send_jito_bundle submits the txs as a single atomic bundle, the tip has
+/// to be paid in the last tx, returns the bundle id
+#[timed::timed(duration(printer = "info!"))]
+pub async fn send_jito_bundle(
+    txs: Vec<VersionedTransaction>,
+    tip: u64,
+) -> Result<String, Box<dyn std::error::Error>> {
+    validate_bundle(&txs, tip)?;
+
+    let encoded_txs = txs
+        .iter()
+        .map(|tx| Ok(bs58::encode(bincode::serialize(tx)?).into_string()))
+        .collect::<Result<Vec<String>, Box<dyn std::error::Error>>>()?;
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post("https://mainnet.block-engine.jito.wtf/api/v1/bundles")
+        .header("content-type", "application/json")
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "sendBundle",
+            "params": [encoded_txs]
+        }))
+        .send()
+        .await?;
+
+    let jito_response = res.json::<JitoResponse>().await?;
+
+    info!(
+        "sent jito bundle of {} txs: {}",
+        txs.len(),
+        jito_response.result
+    );
+
+    Ok(jito_response.result)
+}
+
 #[cfg(test)]
 mod tests {
     use solana_client::nonblocking::rpc_client::RpcClient;
     use solana_sdk::{
+        hash::Hash,
         message::Message,
+        pubkey::Pubkey,
         signature::Keypair,
         signer::{EncodableKey, Signer},
         system_instruction,
-        transaction::Transaction,
+        transaction::{Transaction, VersionedTransaction},
     };
 
-    use crate::util::env;
+    use crate::{constants, util::env};
 
     #[tokio::test]
     async fn test_send_jito_tx() {
@@ -201,4 +297,48 @@ mod tests {
 
         super::send_jito_tx(tx).await.unwrap();
     }
+
+    fn make_tx(keypair: &Keypair, tip: Option<u64>) -> VersionedTransaction {
+        let mut ixs = vec![system_instruction::transfer(
+            &keypair.pubkey(),
+            &Pubkey::new_unique(),
+            1_000,
+        )];
+        if let Some(tip) = tip {
+            ixs.push(system_instruction::transfer(
+                &keypair.pubkey(),
+                &constants::JITO_TIP_ACCOUNTS[0],
+                tip,
+            ));
+        }
+        let message = Message::new(&ixs, Some(&keypair.pubkey()));
+        VersionedTransaction::from(Transaction::new(
+            &[keypair],
+            message,
+            Hash::default(),
+        ))
+    }
+
+    #[test]
+    fn test_validate_bundle() {
+        let keypair = Keypair::new();
+        let bundle = vec![
+            make_tx(&keypair, None),
+            make_tx(&keypair, None),
+            make_tx(&keypair, Some(10_000)),
+        ];
+        assert!(super::validate_bundle(&bundle, 10_000).is_ok());
+        assert!(super::validate_bundle(&bundle, 10_001).is_err());
+        assert!(super::validate_bundle(&[], 0).is_err());
+
+        // tip in the first tx instead of the last
+        let bundle =
+            vec![make_tx(&keypair, Some(10_000)), make_tx(&keypair, None)];
+        assert!(super::validate_bundle(&bundle, 10_000).is_err());
+
+        let bundle = (0..6)
+            .map(|_| make_tx(&keypair, Some(10_000)))
+            .collect::<Vec<_>>();
+        assert!(super::validate_bundle(&bundle, 10_000).is_err());
+    }
 }
@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
@@ -143,6 +144,8 @@ pub struct AccountMeta {
     pub is_writable: bool,
 }
 
+pub const JUP_API_URL: &str = "https://quote-api.jup.ag/v6";
+
 pub struct Jupiter;
 
 impl Jupiter {
@@ -151,18 +154,112 @@ impl Jupiter {
         output_mint: &str,
         amount: u64,
         slippage: u16,
+    ) -> Result<QuoteResponse, Box<dyn std::error::Error>> {
+        JupClient::shared()
+            .fetch_quote(input_mint, output_mint, amount, slippage)
+            .await
+    }
+
+    pub async fn swap(
+        quote_response: QuoteResponse,
+        signer: &Keypair,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        JupClient::shared().swap(quote_response, signer).await
+    }
+
+    fn convert_instruction_data(
+        ix_data: InstructionData,
+    ) -> Result<solana_sdk::instruction::Instruction, Box<dyn std::error::Error>>
+    {
+        let program_id = Pubkey::from_str(&ix_data.program_id)?;
+
+        let accounts = ix_data
+            .accounts
+            .into_iter()
+            .map(|acc| {
+                Ok(solana_sdk::instruction::AccountMeta {
+                    pubkey: Pubkey::from_str(&acc.pubkey)?,
+                    is_signer: acc.is_signer,
+                    is_writable: acc.is_writable,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        let data = BASE64_STANDARD.decode(ix_data.data)?;
+
+        Ok(solana_sdk::instruction::Instruction {
+            program_id,
+            accounts,
+            data,
+        })
+    }
+}
+
+/// JupClient talks to the Jupiter API over the given reqwest::Client, so
+/// that proxies, TLS, timeouts and the connection pool are set up once and
+/// reused across calls
+#[derive(Clone)]
+pub struct JupClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Default for JupClient {
+    fn default() -> Self {
+        Self::new(reqwest::Client::new())
+    }
+}
+
+static SHARED_CLIENT: OnceLock<JupClient> = OnceLock::new();
+
+impl JupClient {
+    pub fn new(http: reqwest::Client) -> Self {
+        JupClient {
+            http,
+            base_url: JUP_API_URL.to_string(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// shared is the client behind the `Jupiter` functions
+    pub fn shared() -> &'static JupClient {
+        SHARED_CLIENT.get_or_init(JupClient::default)
+    }
+
+    /// set_shared makes the `Jupiter` functions use client, fails if the
+    /// shared client has already been used or set
+    pub fn set_shared(client: JupClient) -> Result<(), JupClient> {
+        SHARED_CLIENT.set(client)
+    }
+
+    pub async fn fetch_quote(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage: u16,
     ) -> Result<QuoteResponse, Box<dyn std::error::Error>> {
         let url = format!(
-            "https://quote-api.jup.ag/v6/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}",
-            input_mint, output_mint, amount, slippage
+            "{}/quote?inputMint={}&outputMint={}&amount={}&slippageBps={}",
+            self.base_url, input_mint, output_mint, amount, slippage
         );
 
-        let response =
-            reqwest::get(&url).await?.json::<QuoteResponse>().await?;
+        let response = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .json::<QuoteResponse>()
+            .await?;
         Ok(response)
     }
 
     pub async fn swap(
+        &self,
         quote_response: QuoteResponse,
         signer: &Keypair,
     ) -> Result<String, Box<dyn std::error::Error>> {
@@ -183,9 +280,9 @@ impl Jupiter {
             quote_response,
         };
 
-        let client = reqwest::Client::new();
-        let raw_res = client
-            .post("https://quote-api.jup.ag/v6/swap-instructions")
+        let raw_res = self
+            .http
+            .post(format!("{}/swap-instructions", self.base_url))
             .json(&swap_request)
             .send()
             .await?;
@@ -203,29 +300,30 @@ impl Jupiter {
         // Add token ledger instruction if present
         if let Some(token_ledger_ix) = response.token_ledger_instruction {
             instructions
-                .push(Self::convert_instruction_data(token_ledger_ix)?);
+                .push(Jupiter::convert_instruction_data(token_ledger_ix)?);
         }
 
         if let Some(compute_budget_instructions) =
             response.compute_budget_instructions
         {
             for ix_data in compute_budget_instructions {
-                instructions.push(Self::convert_instruction_data(ix_data)?);
+                instructions.push(Jupiter::convert_instruction_data(ix_data)?);
             }
         }
 
         // Add setup instructions
         for ix_data in response.setup_instructions {
-            instructions.push(Self::convert_instruction_data(ix_data)?);
+            instructions.push(Jupiter::convert_instruction_data(ix_data)?);
         }
 
         // Add swap instruction
-        instructions
-            .push(Self::convert_instruction_data(response.swap_instruction)?);
+        instructions.push(Jupiter::convert_instruction_data(
+            response.swap_instruction,
+        )?);
 
         // Add cleanup instruction if present
         if let Some(cleanup_ix) = response.cleanup_instruction {
-            instructions.push(Self::convert_instruction_data(cleanup_ix)?);
+            instructions.push(Jupiter::convert_instruction_data(cleanup_ix)?);
         }
 
         // Create and sign transaction
@@ -237,31 +335,4 @@ impl Jupiter {
 
         Ok(result)
     }
-
-    fn convert_instruction_data(
-        ix_data: InstructionData,
-    ) -> Result<solana_sdk::instruction::Instruction, Box<dyn std::error::Error>>
-    {
-        let program_id = Pubkey::from_str(&ix_data.program_id)?;
-
-        let accounts = ix_data
-            .accounts
-            .into_iter()
-            .map(|acc| {
-                Ok(solana_sdk::instruction::AccountMeta {
-                    pubkey: Pubkey::from_str(&acc.pubkey)?,
-                    is_signer: acc.is_signer,
-                    is_writable: acc.is_writable,
-                })
-            })
-            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
-
-        let data = BASE64_STANDARD.decode(ix_data.data)?;
-
-        Ok(solana_sdk::instruction::Instruction {
-            program_id,
-            accounts,
-            data,
-        })
-    }
 }
@@ -201,15 +201,22 @@ pub mod address;
 pub mod agent;
 pub mod api_docs;
 pub mod app;
+pub mod approval;
 pub mod ata;
 pub mod blockhash;
+pub mod blocklist;
 pub mod buyer;
 pub mod buyer_service;
 pub mod checker;
 pub mod checker_service;
 pub mod collector;
 pub mod constants;
+pub mod denomination;
+pub mod engine;
 pub mod execute;
+pub mod flow;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handlers;
 pub mod http_client;
 pub mod jito;
@@ -217,19 +224,25 @@ pub mod jup;
 pub mod listener;
 pub mod listener_service;
 pub mod orca;
+pub mod positions;
 pub mod prometheus;
 pub mod provider;
 pub mod pump;
 pub mod pump_service;
 pub mod raydium;
+pub mod router;
 pub mod rpc;
 pub mod seller;
 pub mod seller_service;
 pub mod service;
+pub mod sink;
 pub mod state;
+pub mod stats;
+pub mod throttle;
 pub mod tx_parser;
 pub mod types;
 pub mod util;
+pub mod wash;
 pub mod ws;
 
 mod tests;
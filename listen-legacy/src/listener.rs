@@ -1,26 +1,50 @@
-use crate::constants;
+use crate::{constants, tx_parser::TokenTransfer};
 
-use log::info;
-use serde::Serialize;
+use crossbeam::channel::{unbounded, Receiver};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
 use solana_account_decoder::UiAccountEncoding;
 use solana_client::{
-    pubsub_client::{LogsSubscription, PubsubClient},
+    pubsub_client::{
+        LogsSubscription, PubsubClient, PubsubLogsClientSubscription,
+    },
     rpc_config::{
         RpcAccountInfoConfig, RpcBlockSubscribeConfig,
         RpcBlockSubscribeFilter, RpcProgramAccountsConfig,
         RpcTransactionLogsConfig, RpcTransactionLogsFilter,
     },
+    rpc_response::{Response, RpcLogsResponse},
 };
 use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
-use std::time::Duration;
+use std::{path::Path, str::FromStr, sync::Arc, time::Duration};
+
+/// LogParser turns the logs of a tx into a payload, None to skip the tx
+pub type LogParser =
+    Arc<dyn Fn(&RpcLogsResponse) -> Option<serde_json::Value> + Send + Sync>;
+
+/// LogsReceiver is the receiving end of a logs subscription
+pub type LogsReceiver = Receiver<Response<RpcLogsResponse>>;
 
 pub struct Listener {
     ws_url: String,
+    parsers: Vec<(Pubkey, LogParser)>,
+}
+
+/// CustomEvent is emitted for the txs handled by a registered LogParser
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomEvent {
+    pub program_id: String,
+    pub signature: String,
+    pub slot: u64,
+    pub payload: serde_json::Value,
 }
 
 #[derive(Debug, Serialize, Default)]
 pub struct Swap {
     pub signature: String,
+    pub signer: String,
+    /// side is relative to the base token, None if it is not known
+    pub side: Option<TradeSide>,
 
     pub quote_amount: f64,
     pub quote_mint: String,
@@ -29,6 +53,17 @@ pub struct Swap {
     pub base_mint: String,
 
     pub sol_amount_ui: f64,
+
+    /// transfers are all of the SPL token transfers of the swap tx,
+    /// including fee, referral and intermediate multi-hop movements
+    pub transfers: Vec<TokenTransfer>,
+}
+
+/// TradeSide is the direction of a trade relative to the non-SOL token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradeSide {
+    Buy,
+    Sell,
 }
 
 pub trait BlockAndProgramSubscribable {
@@ -39,7 +74,61 @@ pub trait BlockAndProgramSubscribable {
 
 impl Listener {
     pub fn new(ws_url: String) -> Listener {
-        Listener { ws_url }
+        Listener {
+            ws_url,
+            parsers: vec![],
+        }
+    }
+
+    /// register_parser routes the logs of txs mentioning `program_id` to
+    /// `parser`, see `custom_subscribe`
+    pub fn register_parser<F>(&mut self, program_id: Pubkey, parser: F)
+    where
+        F: Fn(&RpcLogsResponse) -> Option<serde_json::Value>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.parsers.push((program_id, Arc::new(parser)));
+    }
+
+    /// custom_subscribe subscribes to the logs of every program with a
+    /// registered parser and emits the parsed payloads as CustomEvents,
+    /// dropping the subscriptions stops the events
+    pub fn custom_subscribe(
+        &self,
+    ) -> Result<
+        (Vec<PubsubLogsClientSubscription>, Receiver<CustomEvent>),
+        Box<dyn std::error::Error>,
+    > {
+        let (sender, events) = unbounded();
+        let mut subs = vec![];
+        for (program_id, parser) in &self.parsers {
+            let (sub, receiver) = self.account_subscribe(program_id)?;
+            subs.push(sub);
+            let program_id = *program_id;
+            let parser = parser.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                while let Ok(log) = receiver.recv() {
+                    let Some(payload) = parser(&log.value) else {
+                        continue;
+                    };
+                    let event = CustomEvent {
+                        program_id: program_id.to_string(),
+                        signature: log.value.signature.clone(),
+                        slot: log.context.slot,
+                        payload,
+                    };
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
+                debug!("custom parser for {} stopped", program_id);
+            });
+            info!("listening to logs for {} (custom parser)", program_id);
+        }
+        Ok((subs, events))
     }
 
     pub fn account_subscribe(
@@ -91,6 +180,154 @@ impl Listener {
     }
 }
 
+/// SUBSCRIPTION_STATE_VERSION is bumped on breaking changes to
+/// SubscriptionState, older files are still restored
+pub const SUBSCRIPTION_STATE_VERSION: u32 = 1;
+
+/// SubscriptionSpec is everything needed to re-establish a subscription
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SubscriptionSpec {
+    /// Pool: logs mentioning the amm pool
+    Pool { amm_pool: String },
+    /// Account: logs mentioning the account
+    Account { pubkey: String },
+    /// Program: logs of a program with a registered parser
+    Program { program_id: String },
+    /// Raydium: logs of the Raydium AMM v4 program
+    Raydium,
+}
+
+/// SubscriptionState is the JSON representation of the active
+/// subscriptions of a SubscriptionManager
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionState {
+    pub version: u32,
+    /// last_slot: the last slot processed before saving, to replay from
+    pub last_slot: Option<u64>,
+    pub subscriptions: Vec<SubscriptionSpec>,
+}
+
+impl SubscriptionState {
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let state: SubscriptionState = serde_json::from_str(json)?;
+        if state.version > SUBSCRIPTION_STATE_VERSION {
+            return Err(format!(
+                "subscription state version {} is newer than {}",
+                state.version, SUBSCRIPTION_STATE_VERSION
+            )
+            .into());
+        }
+        Ok(state)
+    }
+}
+
+/// SubscriptionManager keeps track of the subscriptions made through it, so
+/// that they can be saved and re-established after a restart
+pub struct SubscriptionManager {
+    listener: Listener,
+    last_slot: Option<u64>,
+    active: Vec<(SubscriptionSpec, PubsubLogsClientSubscription)>,
+}
+
+impl SubscriptionManager {
+    pub fn new(listener: Listener) -> Self {
+        Self {
+            listener,
+            last_slot: None,
+            active: vec![],
+        }
+    }
+
+    pub fn listener(&self) -> &Listener {
+        &self.listener
+    }
+
+    /// observe_slot records the progress that gets saved with the state
+    pub fn observe_slot(&mut self, slot: u64) {
+        self.last_slot = Some(self.last_slot.map_or(slot, |s| s.max(slot)));
+    }
+
+    pub fn subscribe(
+        &mut self,
+        spec: SubscriptionSpec,
+    ) -> Result<LogsReceiver, Box<dyn std::error::Error>> {
+        let (sub, receiver) = match &spec {
+            SubscriptionSpec::Pool { amm_pool } => {
+                self.listener.pool_subscribe(&Pubkey::from_str(amm_pool)?)?
+            }
+            SubscriptionSpec::Account { pubkey } => self
+                .listener
+                .account_subscribe(&Pubkey::from_str(pubkey)?)?,
+            SubscriptionSpec::Program { program_id } => self
+                .listener
+                .account_subscribe(&Pubkey::from_str(program_id)?)?,
+            SubscriptionSpec::Raydium => self.listener.logs_subscribe()?,
+        };
+        self.active.push((spec, sub));
+        Ok(receiver)
+    }
+
+    /// unsubscribe shuts down every active subscription matching spec
+    pub fn unsubscribe(&mut self, spec: &SubscriptionSpec) {
+        let (matching, rest) = std::mem::take(&mut self.active)
+            .into_iter()
+            .partition(|(active, _)| active == spec);
+        self.active = rest;
+        for (spec, mut sub) in matching {
+            if let Err(e) = sub.shutdown() {
+                debug!("unsubscribe {:?}: {:?}", spec, e);
+            }
+        }
+    }
+
+    pub fn state(&self) -> SubscriptionState {
+        SubscriptionState {
+            version: SUBSCRIPTION_STATE_VERSION,
+            last_slot: self.last_slot,
+            subscriptions: self
+                .active
+                .iter()
+                .map(|(spec, _)| spec.clone())
+                .collect(),
+        }
+    }
+
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_string_pretty(&self.state())?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// restore re-establishes the subscriptions saved under path, returns
+    /// the receivers in the saved order
+    pub fn restore(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<
+        Vec<(SubscriptionSpec, LogsReceiver)>,
+        Box<dyn std::error::Error>,
+    > {
+        let state =
+            SubscriptionState::from_json(&std::fs::read_to_string(path)?)?;
+        self.last_slot = state.last_slot;
+        let mut receivers = vec![];
+        for spec in state.subscriptions {
+            let receiver = self.subscribe(spec.clone())?;
+            receivers.push((spec, receiver));
+        }
+        info!(
+            "restored {} subscriptions, last slot {:?}",
+            receivers.len(),
+            self.last_slot
+        );
+        Ok(receivers)
+    }
+}
+
 impl BlockAndProgramSubscribable for Listener {
     fn slot_subscribe(&self) -> Result<(), Box<dyn std::error::Error>> {
         let (mut subs, receiver) =
@@ -131,7 +368,7 @@ impl BlockAndProgramSubscribable for Listener {
         info!("Filtering for mentions of {:?}", raydium_pubkey);
 
         while let Ok(block) = receiver.recv_timeout(Duration::from_secs(1)) {
-            info!("Received block: {:?}", block);
+            crate::throttled!(info, "Received block: {:?}", block);
         }
 
         subs.shutdown().unwrap();
@@ -171,3 +408,34 @@ impl BlockAndProgramSubscribable for Listener {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_state_roundtrip() {
+        let state = SubscriptionState {
+            version: SUBSCRIPTION_STATE_VERSION,
+            last_slot: Some(42),
+            subscriptions: vec![
+                SubscriptionSpec::Pool {
+                    amm_pool: Pubkey::new_unique().to_string(),
+                },
+                SubscriptionSpec::Raydium,
+            ],
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains("\"kind\":\"pool\""));
+        assert_eq!(SubscriptionState::from_json(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn test_subscription_state_rejects_newer_version() {
+        let json = format!(
+            r#"{{"version":{},"last_slot":null,"subscriptions":[]}}"#,
+            SUBSCRIPTION_STATE_VERSION + 1
+        );
+        assert!(SubscriptionState::from_json(&json).is_err());
+    }
+}
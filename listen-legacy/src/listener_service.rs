@@ -3,7 +3,10 @@ use crate::{
     checker_service::ChecksRequest,
     collector, constants,
     http_client::HttpClient,
+    sink::{self, ListenerEvent},
+    tx_parser::{self, NewPool},
     util::{env, healthz},
+    Provider,
 };
 use actix_web::{
     error, post, web, App, Error, HttpRequest, HttpResponse, HttpServer,
@@ -12,7 +15,7 @@ use futures_util::StreamExt;
 use log::{debug, info, warn};
 use serde_json::Value;
 use solana_client::{
-    nonblocking::pubsub_client::PubsubClient,
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
     rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter},
 };
 use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
@@ -23,6 +26,7 @@ pub async fn run_listener_pubsub_service(
     info!("{}", env("WS_URL"));
     tokio::spawn(async move {
         let collector = Arc::new(collector::new().await.expect("collector"));
+        let rpc_client = Arc::new(RpcClient::new(env("RPC_URL")));
         let client = PubsubClient::new(&env("WS_URL"))
             .await
             .expect("pubsub client async");
@@ -39,12 +43,21 @@ pub async fn run_listener_pubsub_service(
             .expect("subscribe to logs");
         info!("Listening for LP events");
         while let Some(log) = notifications.next().await {
-            debug!("{:?}", log);
+            crate::throttled!(debug, "{:?}", log);
             let collector = Arc::clone(&collector);
+            let rpc_client = Arc::clone(&rpc_client);
             if log.value.err.is_none() {
                 tokio::spawn(async move {
+                    if sink::global().is_some() {
+                        publish_new_pool(&rpc_client, &log.value.signature)
+                            .await;
+                    }
                     for _ in 0..3 {
-                        info!("passing log {}", log.value.signature);
+                        crate::throttled!(
+                            info,
+                            "passing log {}",
+                            log.value.signature
+                        );
                         match reqwest::get(format!(
                             "http://localhost:8080/new_pair/{}",
                             log.value.signature
@@ -99,6 +112,19 @@ pub async fn run_listener_pubsub_service(
     Ok(())
 }
 
+/// publish_new_pool fetches the tx of the new pool for the sink, the
+/// checks fetch it on their own
+async fn publish_new_pool(rpc_client: &RpcClient, signature: &str) {
+    let new_pool = match Provider::get_tx(rpc_client, signature).await {
+        Ok(tx) => tx_parser::parse_new_pool(&tx).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match new_pool {
+        Ok(new_pool) => sink::publish(&ListenerEvent::from(&new_pool)).await,
+        Err(e) => warn!("new pool {}: {}", signature, e),
+    }
+}
+
 #[post("/")]
 async fn receive_webhook(
     req: HttpRequest,
@@ -177,6 +203,20 @@ async fn handle_webhook(
                 Pubkey::from_str(accounts[19].as_str().unwrap()).unwrap();
             let user_lp_token =
                 Pubkey::from_str(accounts[20].as_str().unwrap()).unwrap();
+            // SOL goes in, like tx_parser::parse_new_pool
+            let (input_mint, output_mint) =
+                if pc_mint == constants::SOLANA_PROGRAM_ID {
+                    (pc_mint, coin_mint)
+                } else {
+                    (coin_mint, pc_mint)
+                };
+            sink::publish(&ListenerEvent::from(&NewPool {
+                amm_pool_id: amm_pool,
+                input_mint,
+                output_mint,
+                creator: user_wallet,
+            }))
+            .await;
             let pool_accounts = PoolAccounts {
                 amm_pool,
                 lp_mint,
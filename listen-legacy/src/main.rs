@@ -22,6 +22,7 @@ use listen::{
     raydium::{self, Raydium, SwapArgs},
     rpc, seller, seller_service,
     service::run_listen_service,
+    sink::{self, ListenerEvent},
     tx_parser, util, BlockAndProgramSubscribable, Listener, Provider,
 };
 use solana_client::{
@@ -54,6 +55,39 @@ async fn main() -> Result<(), Box<dyn Error>> {
         console_subscriber::init();
     }
 
+    if app.args.auto_confirm.unwrap_or(false) {
+        raydium::set_auto_confirm(true);
+    }
+
+    // warm start of the fee tuning, saved again once the command is done
+    let fee_learning_path = std::env::var("LISTEN_FEE_LEARNING_PATH").ok();
+    if let Some(path) = &fee_learning_path {
+        if let Err(e) =
+            listen::FeeLearning::load(path, listen::FEE_LEARNING_MAX_AGE)
+        {
+            warn!("fee learning {}: {}", path, e);
+        }
+    }
+    let _save_fee_learning = SaveFeeLearning(fee_learning_path);
+    // LISTEN_BLOCKLIST is a path or a URL of the accounts of known rugs, only
+    // loaded (and fatal if it fails to) for the commands checking pools
+    let uses_blocklist = matches!(
+        app.command,
+        Command::Swap { .. }
+            | Command::Checks { .. }
+            | Command::Snipe {}
+            | Command::CheckerService {}
+    );
+    let blocklist = match std::env::var("LISTEN_BLOCKLIST") {
+        Ok(source) if uses_blocklist => {
+            let blocklist =
+                Arc::new(listen::blocklist::Blocklist::load(&source).await?);
+            listen::blocklist::set_global(blocklist.clone());
+            Some(blocklist)
+        }
+        _ => None,
+    };
+
     let sol_price = 210.;
 
     match app.command {
@@ -437,28 +471,59 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .await;
         }
         Command::BenchRPC { rpc_url } => rpc::eval_rpc(rpc_url.as_str()),
-        Command::PriorityFee {} => {
+        Command::PriorityFee {
+            lookback_slots,
+            percentile,
+            estimate_fee,
+        } => {
             let rpc_client = RpcClient::new(env("RPC_URL"));
+            let config = raydium::PriorityFeeConfig {
+                lookback_slots,
+                percentile,
+                ..Default::default()
+            };
             println!(
                 "{:?}",
-                rpc_client
-                    .get_recent_prioritization_fees(
-                        vec![constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY]
-                            .as_slice()
-                    )
-                    .await
+                raydium::estimate_priority_fee(
+                    &rpc_client,
+                    &[constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY],
+                    &config,
+                )
+                .await
             );
+            if let Some(fee) = estimate_fee {
+                let estimate = Provider::estimate_confirmation_time(
+                    &rpc_client,
+                    fee,
+                    &[constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY],
+                )
+                .await?;
+                println!("{}", serde_json::to_string_pretty(&estimate)?);
+            }
         }
-        Command::Price { amm_pool } => {
+        Command::Price { amm_pool, metrics } => {
             let rpc_client = RpcClient::new(env("RPC_URL"));
             let pubsub_client = nonblocking::pubsub_client::PubsubClient::new(
                 env("WS_URL").as_str(),
             )
             .await?;
             let amm_pool = Pubkey::from_str(amm_pool.as_str())?;
-            seller::listen_price(&amm_pool, &rpc_client, &pubsub_client)
-                .await
-                .expect("listen price");
+            let pool_metrics = if metrics.unwrap_or(false) {
+                let registry = ::prometheus::Registry::new();
+                let pool_metrics = prometheus::PoolMetrics::new(&registry)?;
+                tokio::spawn(prometheus::run_metrics_server(registry));
+                Some(pool_metrics)
+            } else {
+                None
+            };
+            seller::listen_price_with_metrics(
+                &amm_pool,
+                &rpc_client,
+                &pubsub_client,
+                pool_metrics.as_ref(),
+            )
+            .await
+            .expect("listen price");
         }
         Command::CheckerService {} => {
             checker_service::run_checker_service().await?;
@@ -470,6 +535,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             seller_service::run_seller_service().await?;
         }
         Command::ListenerService { webhook } => {
+            sink::init_from_env().await?;
             let webhook = webhook.unwrap_or(false);
             if webhook {
                 listener_service::run_listener_webhook_service().await?;
@@ -485,9 +551,51 @@ async fn main() -> Result<(), Box<dyn Error>> {
             yes,
             dex,
             amm_pool_id,
+            decimals_override,
+            min_out,
+            min_out_ui,
+            max_rpc_lag,
+            metrics,
+            max_drift_bps,
         } => {
             let rpc_client = RpcClient::new(env("RPC_URL"));
-            let raydium = Raydium::new();
+            let mut builder = Raydium::builder();
+            for decimals_override in decimals_override {
+                let (mint, decimals) = decimals_override
+                    .split_once(':')
+                    .ok_or("decimals override must be <mint>:<decimals>")?;
+                builder = builder.decimals_override(
+                    Pubkey::from_str(mint)?,
+                    decimals.parse()?,
+                );
+            }
+            if let Some(max_lag) = max_rpc_lag {
+                let lag_metrics = if metrics.unwrap_or(false) {
+                    let registry = ::prometheus::Registry::new();
+                    let lag_metrics =
+                        prometheus::RpcLagMetrics::new(&registry)?;
+                    tokio::spawn(prometheus::run_metrics_server(registry));
+                    Some(lag_metrics)
+                } else {
+                    None
+                };
+                builder = builder.lag_check(listen::LagCheck {
+                    max_lag,
+                    reference_url: std::env::var("REFERENCE_RPC_URL").ok(),
+                    fail: true,
+                    metrics: lag_metrics,
+                });
+            }
+            if let Some(max_drift_bps) = max_drift_bps {
+                builder = builder.max_drift_bps(max_drift_bps);
+            }
+            if let Some(confirm_quorum) = listen::ConfirmQuorum::from_env()? {
+                builder = builder.confirm_quorum(confirm_quorum);
+            }
+            if let Some(blocklist) = blocklist {
+                builder = builder.blocklist(blocklist);
+            }
+            let raydium = builder.build();
             let start = std::time::Instant::now();
             if input_mint == "sol" {
                 input_mint = constants::SOLANA_PROGRAM_ID.to_string();
@@ -516,6 +624,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     Provider::get_balance(&rpc_client, &wallet.pubkey())
                         .await?
                 );
+                let min_out = match min_out_ui {
+                    Some(min_out_ui) => Some(
+                        raydium
+                            .to_raw(
+                                &rpc_client,
+                                &output_token_mint,
+                                min_out_ui,
+                            )
+                            .await?,
+                    ),
+                    None => min_out,
+                };
                 let amount_specified = if amount.is_some() {
                     amount.unwrap() as u64
                 } else {
@@ -537,6 +657,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         rpc_client,
                         confirmed: yes.unwrap_or(false),
                         no_sanity: true,
+                        min_out,
                     })
                     .await?;
                 return Ok(());
@@ -583,6 +704,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let swap = tx_parser::parse_swap(&tx)?;
             info!("Swap: {}", serde_json::to_string_pretty(&swap)?);
 
+            for event in tx_parser::parse_liquidity_events(&tx)? {
+                info!("Liquidity: {}", serde_json::to_string_pretty(&event)?);
+            }
+
             let sol_notional =
                 listen::util::lamports_to_sol(swap.quote_amount as u64);
             let usd_notional = sol_notional * sol_price;
@@ -594,6 +719,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             worker_count,
             buffer_size,
         } => {
+            sink::init_from_env().await?;
             run_listener(worker_count as usize, buffer_size as usize).await?;
             return Ok(());
         }
@@ -601,12 +727,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// SaveFeeLearning saves the fee learning to its path when dropped, so that
+/// it is saved however the command returns
+struct SaveFeeLearning(Option<String>);
+
+impl Drop for SaveFeeLearning {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            if let Err(e) = listen::FeeLearning::save(path) {
+                warn!("fee learning {}: {}", path, e);
+            }
+        }
+    }
+}
+
 pub async fn run_listener(
     worker_count: usize,
     buffer_size: usize,
 ) -> Result<(), Box<dyn Error>> {
     // let blocklist = vec![];
     let listener = Listener::new(env("WS_URL"));
+    let drop_bps = util::env_or("LISTEN_LIQUIDITY_DROP_BPS", 1_000u64);
     let (
         transactions_received,
         transactions_processed,
@@ -655,6 +796,11 @@ pub async fn run_listener(
                         }
                     };
                     requests_sent.inc();
+                    if sink::global().is_some() {
+                        for event in ListenerEvent::from_tx(&tx, drop_bps) {
+                            sink::publish(&event).await;
+                        }
+                    }
                     let lamports =
                         tx_parser::parse_notional(&tx).ok().unwrap();
                     let sol_notional = util::lamports_to_sol(lamports);
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use futures_util::future::BoxFuture;
+use log::warn;
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::constants;
+use crate::denomination::{QuoteTokens, QuotedPool};
+use crate::raydium::Raydium;
+use crate::sink::{EventSink, ListenerEvent, SinkError};
+use crate::{Swap, TradeSide};
+
+/// Position is a token held by the wallet, cost is the SOL paid for the
+/// size still held (average cost), prices are in SOL per token (ui) and the
+/// current ones are None if the pool of the token is not known or could
+/// not be read
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Position {
+    pub mint: String,
+    pub pool: Option<String>,
+    /// size is raw, size_ui needs the decimals from the pool
+    pub size: u64,
+    pub size_ui: Option<f64>,
+    pub cost_sol: f64,
+    pub entry_price_sol: Option<f64>,
+    pub current_price_sol: Option<f64>,
+    pub value_sol: Option<f64>,
+    pub unrealized_pnl_sol: Option<f64>,
+    /// realized_pnl_sol is what the sells of the token made over their cost
+    pub realized_pnl_sol: f64,
+    pub buys: usize,
+    pub sells: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Lot {
+    size: u64,
+    cost_sol: f64,
+    realized_pnl_sol: f64,
+    buys: usize,
+    sells: usize,
+}
+
+impl Lot {
+    fn position(
+        &self,
+        mint: &str,
+        pool: Option<&Pubkey>,
+        quoted: Option<&QuotedPool>,
+    ) -> Position {
+        // only a SOL quoted pool prices the position in SOL
+        let quoted = quoted.filter(|quoted| {
+            quoted.denomination.quote_mint == constants::SOLANA_PROGRAM_ID
+        });
+        let size_ui = quoted.map(|quoted| {
+            self.size as f64 / 10f64.powi(quoted.base_decimals as i32)
+        });
+        let value_sol = quoted.and_then(|quoted| quoted.to_quote(self.size));
+        Position {
+            mint: mint.to_string(),
+            pool: pool.map(|pool| pool.to_string()),
+            size: self.size,
+            size_ui,
+            cost_sol: self.cost_sol,
+            entry_price_sol: size_ui
+                .filter(|size_ui| *size_ui > 0.)
+                .map(|size_ui| self.cost_sol / size_ui),
+            current_price_sol: quoted.and_then(|quoted| quoted.price()),
+            value_sol,
+            unrealized_pnl_sol: value_sol.map(|value| value - self.cost_sol),
+            realized_pnl_sol: self.realized_pnl_sol,
+            buys: self.buys,
+            sells: self.sells,
+        }
+    }
+}
+
+/// PositionTracker follows the open positions of a wallet from its swaps
+/// (of the listener feed, `Provider::recent_swaps` or its own swaps through
+/// `RaydiumBuilder::position_tracker`), swaps of other signers are ignored;
+/// it is an `EventSink` so it can be attached to the listener to stay up to
+/// date as the swaps confirm. Register the pool of a token with
+/// `track_pool` to have it priced
+pub struct PositionTracker {
+    wallet: String,
+    lots: Mutex<HashMap<String, Lot>>,
+    pools: Mutex<HashMap<String, Pubkey>>,
+}
+
+impl PositionTracker {
+    pub fn new(wallet: &Pubkey) -> Self {
+        PositionTracker {
+            wallet: wallet.to_string(),
+            lots: Mutex::new(HashMap::new()),
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// track_pool sets the pool the token is priced through
+    pub fn track_pool(&self, mint: &Pubkey, amm_pool: Pubkey) {
+        self.pools
+            .lock()
+            .expect("pools poisoned")
+            .insert(mint.to_string(), amm_pool);
+    }
+
+    /// record applies a confirmed swap, sells take their cost off the
+    /// position at the average entry and the difference is realized, a
+    /// position sold down to 0 is closed
+    pub fn record(&self, swap: &Swap) -> bool {
+        if swap.signer != self.wallet || swap.base_mint.is_empty() {
+            return false;
+        }
+        let Some(side) = swap.side else {
+            return false;
+        };
+        let base_amount = swap.base_amount as u64;
+        let mut lots = self.lots.lock().expect("positions poisoned");
+        let lot = lots.entry(swap.base_mint.clone()).or_default();
+        match side {
+            TradeSide::Buy => {
+                lot.buys += 1;
+                lot.size += base_amount;
+                lot.cost_sol += swap.sol_amount_ui;
+            }
+            TradeSide::Sell => {
+                lot.sells += 1;
+                let sold = base_amount.min(lot.size);
+                let cost_sol = if lot.size > 0 {
+                    lot.cost_sol * sold as f64 / lot.size as f64
+                } else {
+                    0.
+                };
+                lot.size -= sold;
+                lot.cost_sol -= cost_sol;
+                lot.realized_pnl_sol += swap.sol_amount_ui - cost_sol;
+            }
+        }
+        true
+    }
+
+    /// held is the tokens currently held, unpriced
+    pub fn held(&self) -> Vec<Position> {
+        self.lots
+            .lock()
+            .expect("positions poisoned")
+            .iter()
+            .filter(|(_, lot)| lot.size > 0)
+            .map(|(mint, lot)| lot.position(mint, None, None))
+            .collect()
+    }
+
+    /// positions is the live view of the held tokens, each priced off the
+    /// current reserves of its registered pool, largest value first
+    pub async fn positions(
+        &self,
+        raydium: &Raydium,
+        rpc_client: &RpcClient,
+    ) -> Vec<Position> {
+        let held = self
+            .lots
+            .lock()
+            .expect("positions poisoned")
+            .iter()
+            .filter(|(_, lot)| lot.size > 0)
+            .map(|(mint, lot)| (mint.clone(), *lot))
+            .collect::<Vec<(String, Lot)>>();
+        let pools = self.pools.lock().expect("pools poisoned").clone();
+        let quote_tokens = QuoteTokens::default();
+        let mut positions =
+            futures_util::future::join_all(held.iter().map(|(mint, lot)| {
+                let pool = pools.get(mint);
+                let quote_tokens = &quote_tokens;
+                async move {
+                    let quoted = match pool {
+                        Some(pool) => raydium
+                            .quoted_pool(rpc_client, pool, quote_tokens)
+                            .await
+                            .map_err(|e| warn!("position {}: {}", mint, e))
+                            .ok(),
+                        None => None,
+                    };
+                    lot.position(mint, pool, quoted.as_ref())
+                }
+            }))
+            .await;
+        positions.sort_by(|a, b| {
+            b.value_sol
+                .unwrap_or(0.)
+                .total_cmp(&a.value_sol.unwrap_or(0.))
+        });
+        positions
+    }
+}
+
+impl EventSink for PositionTracker {
+    fn publish<'a>(
+        &'a self,
+        event: &'a ListenerEvent,
+    ) -> BoxFuture<'a, Result<(), SinkError>> {
+        if let ListenerEvent::Swap(swap) = event {
+            self.record(swap);
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap(
+        signer: &str,
+        mint: &str,
+        side: TradeSide,
+        base: f64,
+        sol: f64,
+    ) -> Swap {
+        Swap {
+            signer: signer.to_string(),
+            side: Some(side),
+            base_amount: base,
+            base_mint: mint.to_string(),
+            sol_amount_ui: sol,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_position_tracker() {
+        let wallet = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let tracker = PositionTracker::new(&wallet);
+        let (signer, token) = (wallet.to_string(), mint.to_string());
+        let trade = |side, base, sol| swap(&signer, &token, side, base, sol);
+
+        assert!(tracker.record(&trade(TradeSide::Buy, 1e9, 1.)));
+        assert!(tracker.record(&trade(TradeSide::Buy, 1e9, 3.)));
+        let other = swap("other", &token, TradeSide::Buy, 1e9, 1.);
+        assert!(!tracker.record(&other));
+        // half of the 2e9 at an average cost of 2 SOL per 1e9
+        tracker.record(&trade(TradeSide::Sell, 1e9, 2.5));
+
+        let lot = *tracker.lots.lock().unwrap().get(&token).unwrap();
+        assert_eq!(lot.size, 1_000_000_000);
+        assert_eq!(lot.cost_sol, 2.);
+        assert_eq!(lot.realized_pnl_sol, 0.5);
+
+        let pool = QuotedPool {
+            denomination: QuoteTokens::default()
+                .detect(mint, constants::SOLANA_PROGRAM_ID),
+            // 3 SOL per token (6 decimals)
+            base_reserve: 10_000_000,
+            quote_reserve: 30_000_000_000,
+            base_decimals: 6,
+            quote_decimals: 9,
+        };
+        let position = lot.position(&token, None, Some(&pool));
+        assert_eq!(position.size_ui, Some(1_000.));
+        assert!((position.entry_price_sol.unwrap() - 0.002).abs() < 1e-12);
+        assert!((position.current_price_sol.unwrap() - 3.).abs() < 1e-9);
+        assert!((position.unrealized_pnl_sol.unwrap() - 2_998.).abs() < 1e-6);
+
+        tracker.record(&trade(TradeSide::Sell, 2e9, 1.));
+        assert!(tracker.held().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_position_tracker_from_listener() {
+        let tx = serde_json::from_reader(
+            std::fs::File::open("mock/tx.json").unwrap(),
+        )
+        .unwrap();
+        let swap = crate::tx_parser::parse_swap(&tx).unwrap();
+        assert_eq!(swap.side, Some(TradeSide::Buy));
+        // the signer of the mock buy
+        let wallet: Pubkey = "HX7M14ZLfsGhwEkA9xe5LHNXNG6V8KGJ2wGq1mG4UNgV"
+            .parse()
+            .unwrap();
+        let tracker = PositionTracker::new(&wallet);
+        tracker.publish(&ListenerEvent::Swap(swap)).await.unwrap();
+
+        let held = tracker.held();
+        assert_eq!(held.len(), 1);
+        assert_eq!(
+            held[0].mint,
+            "Xq6eQL1rqKXPKrYq2QMFHWjyJX5JaKrdtqkQ7JCfsE9"
+        );
+        assert_eq!(held[0].size, 11_784_791_459_061);
+        assert!((held[0].cost_sol - 1.5).abs() < 1e-9);
+    }
+}
@@ -1,11 +1,18 @@
 use log::info;
-use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, IntCounter, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use solana_sdk::pubkey::Pubkey;
 use std::sync::Arc;
 use warp::Filter;
 
 static TRANSACTIONS_RECEIVED: &str = "transactions_received";
 static TRANSACTIONS_PROCESSED: &str = "transactions_processed";
 static REQUESTS_SENT: &str = "requests_sent";
+static POOL_RESERVES: &str = "pool_reserves";
+static POOL_PRICE: &str = "pool_price";
+static RPC_LAG_SLOTS: &str = "rpc_lag_slots";
 
 pub fn setup_metrics(
 ) -> (Arc<IntCounter>, Arc<IntCounter>, Arc<IntCounter>, Registry) {
@@ -43,6 +50,100 @@ pub fn setup_metrics(
     )
 }
 
+/// PoolMetrics exports the reserves and spot price of the watched pools as
+/// gauges labeled with the pool id, the series for a given pool only appear
+/// once it is tracked and are removed when its PoolGauges are dropped
+#[derive(Clone)]
+pub struct PoolMetrics {
+    reserves: GaugeVec,
+    price: GaugeVec,
+}
+
+impl PoolMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let reserves = GaugeVec::new(
+            Opts::new(POOL_RESERVES, "Raw vault reserves of watched pools"),
+            &["pool", "vault"],
+        )?;
+        let price = GaugeVec::new(
+            Opts::new(
+                POOL_PRICE,
+                "Spot price (SOL per token) of watched pools",
+            ),
+            &["pool"],
+        )?;
+        registry.register(Box::new(reserves.clone()))?;
+        registry.register(Box::new(price.clone()))?;
+        Ok(PoolMetrics { reserves, price })
+    }
+
+    pub fn track(&self, amm_pool: &Pubkey) -> PoolGauges {
+        let pool = amm_pool.to_string();
+        PoolGauges {
+            token_reserve: self.reserves.with_label_values(&[&pool, "token"]),
+            sol_reserve: self.reserves.with_label_values(&[&pool, "sol"]),
+            price: self.price.with_label_values(&[&pool]),
+            pool,
+            metrics: self.clone(),
+        }
+    }
+}
+
+pub struct PoolGauges {
+    pool: String,
+    metrics: PoolMetrics,
+    token_reserve: Gauge,
+    sol_reserve: Gauge,
+    price: Gauge,
+}
+
+impl PoolGauges {
+    pub fn set_reserves(&self, token_amount: u64, sol_amount: u64) {
+        self.token_reserve.set(token_amount as f64);
+        self.sol_reserve.set(sol_amount as f64);
+    }
+
+    pub fn set_price(&self, price: f64) {
+        self.price.set(price);
+    }
+}
+
+impl Drop for PoolGauges {
+    fn drop(&mut self) {
+        // the series might have been removed already, nothing to do then
+        let _ = self
+            .metrics
+            .reserves
+            .remove_label_values(&[&self.pool, "token"]);
+        let _ = self
+            .metrics
+            .reserves
+            .remove_label_values(&[&self.pool, "sol"]);
+        let _ = self.metrics.price.remove_label_values(&[&self.pool]);
+    }
+}
+
+/// RpcLagMetrics exports the last measured lag of the RPC node in slots
+#[derive(Clone)]
+pub struct RpcLagMetrics {
+    lag: IntGauge,
+}
+
+impl RpcLagMetrics {
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let lag = IntGauge::new(
+            RPC_LAG_SLOTS,
+            "Slots the RPC node is behind the reference",
+        )?;
+        registry.register(Box::new(lag.clone()))?;
+        Ok(RpcLagMetrics { lag })
+    }
+
+    pub fn set(&self, lag: u64) {
+        self.lag.set(lag as i64);
+    }
+}
+
 pub async fn run_metrics_server(registry: Registry) {
     // Metrics endpoint
     let metrics_route = warp::path!("metrics").map(move || {
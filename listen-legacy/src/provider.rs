@@ -1,18 +1,33 @@
 use crate::{
+    constants,
+    prometheus::RpcLagMetrics,
     raydium::{parse_holding, Holding},
+    tx_parser::{self, BalanceDelta},
     types,
-    util::env,
+    util::{self, env},
+    Swap, TradeSide,
+};
+use raydium_library::amm;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{OnceLock, RwLock},
 };
-use std::str::FromStr;
 
+use futures_util::StreamExt;
 use log::{debug, info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use solana_client::{
-    nonblocking::rpc_client::RpcClient, rpc_client::SerializableTransaction,
-    rpc_config::RpcTransactionConfig, rpc_request::TokenAccountsFilter,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_client::SerializableTransaction,
+    rpc_config::{RpcSendTransactionConfig, RpcTransactionConfig},
+    rpc_request::TokenAccountsFilter,
 };
 use solana_sdk::{
     commitment_config::CommitmentConfig, program_pack::Pack, pubkey::Pubkey,
-    signature::Signature,
+    signature::Signature, transaction::TransactionError,
 };
 use solana_transaction_status::{
     EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding,
@@ -32,6 +47,630 @@ pub fn get_client(url: &str) -> Result<RpcClient, Box<dyn std::error::Error>> {
     Ok(rpc_client)
 }
 
+/// SimulationResult is the part of the simulation response that matters
+/// when figuring out why a transaction would fail
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationResult {
+    pub err: Option<TransactionError>,
+    pub logs: Vec<String>,
+    pub units: Option<u64>,
+}
+
+/// Confirmation is the outcome of waiting for a tx until its blockhash
+/// expires, a tx that is not confirmed by then can no longer land
+#[derive(Debug, Clone, Serialize)]
+pub struct Confirmation {
+    pub confirmed: bool,
+    /// err is set if the tx landed but failed
+    pub err: Option<TransactionError>,
+    pub block_height: u64,
+    pub last_valid_block_height: u64,
+}
+
+impl Confirmation {
+    /// remaining_blocks is the validity window left at the last check
+    pub fn remaining_blocks(&self) -> u64 {
+        self.last_valid_block_height
+            .saturating_sub(self.block_height)
+    }
+}
+
+/// EndpointStatus is what a single endpoint reports for a signature
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum EndpointStatus {
+    Pending,
+    Confirmed,
+    /// the tx landed (at confirmed commitment) but failed
+    Failed {
+        err: TransactionError,
+    },
+    Unreachable {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuorumVerdict {
+    Confirmed,
+    Failed,
+    Pending,
+}
+
+/// tally is the verdict of the endpoint statuses, confirmed (or failed)
+/// once at least `quorum` endpoints agree on it, pending otherwise; the
+/// second value is set if the endpoints disagree on the outcome, some
+/// claiming confirmed and others failed
+pub fn tally(
+    statuses: &[EndpointStatus],
+    quorum: usize,
+) -> (QuorumVerdict, bool) {
+    let confirmed = statuses
+        .iter()
+        .filter(|status| **status == EndpointStatus::Confirmed)
+        .count();
+    let failed = statuses
+        .iter()
+        .filter(|status| matches!(status, EndpointStatus::Failed { .. }))
+        .count();
+    let disagreement = confirmed > 0 && failed > 0;
+    let verdict = if confirmed >= quorum {
+        QuorumVerdict::Confirmed
+    } else if failed >= quorum {
+        QuorumVerdict::Failed
+    } else {
+        QuorumVerdict::Pending
+    };
+    (verdict, disagreement)
+}
+
+/// QuorumConfirmation is the outcome of confirming over multiple endpoints,
+/// statuses are the last reports of the endpoints in their order
+#[derive(Debug, Clone, Serialize)]
+pub struct QuorumConfirmation {
+    pub verdict: QuorumVerdict,
+    pub quorum: usize,
+    pub statuses: Vec<(String, EndpointStatus)>,
+    pub disagreement: bool,
+    pub block_height: u64,
+    pub last_valid_block_height: u64,
+}
+
+impl QuorumConfirmation {
+    /// confirmation is the single endpoint view, err is the error the
+    /// failing endpoints agreed on
+    pub fn confirmation(&self) -> Confirmation {
+        let err = match self.verdict {
+            QuorumVerdict::Failed => {
+                self.statuses.iter().find_map(|(_, status)| match status {
+                    EndpointStatus::Failed { err } => Some(err.clone()),
+                    _ => None,
+                })
+            }
+            _ => None,
+        };
+        Confirmation {
+            confirmed: self.verdict != QuorumVerdict::Pending,
+            err,
+            block_height: self.block_height,
+            last_valid_block_height: self.last_valid_block_height,
+        }
+    }
+}
+
+/// ConfirmQuorum confirms a signature over multiple RPC endpoints and only
+/// reports it confirmed once `quorum` of them agree, against a single
+/// lagging (or lying) node
+pub struct ConfirmQuorum {
+    endpoints: Vec<(String, RpcClient)>,
+    quorum: usize,
+}
+
+impl ConfirmQuorum {
+    pub fn new(
+        urls: &[String],
+        quorum: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if quorum == 0 || quorum > urls.len() {
+            return Err(format!(
+                "quorum {} out of range for {} endpoints",
+                quorum,
+                urls.len()
+            )
+            .into());
+        }
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                (
+                    url.clone(),
+                    RpcClient::new_with_commitment(
+                        url.clone(),
+                        CommitmentConfig::confirmed(),
+                    ),
+                )
+            })
+            .collect();
+        Ok(ConfirmQuorum { endpoints, quorum })
+    }
+
+    /// from_env reads the comma separated LISTEN_CONFIRM_RPC_URLS and
+    /// LISTEN_CONFIRM_QUORUM (default majority), None if no urls are set
+    pub fn from_env() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let Ok(raw) = std::env::var("LISTEN_CONFIRM_RPC_URLS") else {
+            return Ok(None);
+        };
+        let urls = raw
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect::<Vec<String>>();
+        let quorum = match std::env::var("LISTEN_CONFIRM_QUORUM") {
+            Ok(quorum) => quorum.parse()?,
+            Err(_) => urls.len() / 2 + 1,
+        };
+        Ok(Some(ConfirmQuorum::new(&urls, quorum)?))
+    }
+
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    async fn status(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+    ) -> (EndpointStatus, Option<u64>) {
+        let (status, block_height) = tokio::join!(
+            rpc_client.get_signature_statuses(&[*signature]),
+            rpc_client.get_block_height(),
+        );
+        let status = match status {
+            Ok(response) => {
+                match response.value.into_iter().next().flatten().filter(
+                    |status| {
+                        status
+                            .satisfies_commitment(CommitmentConfig::confirmed())
+                    },
+                ) {
+                    Some(status) => match status.err {
+                        Some(err) => EndpointStatus::Failed { err },
+                        None => EndpointStatus::Confirmed,
+                    },
+                    None => EndpointStatus::Pending,
+                }
+            }
+            Err(e) => EndpointStatus::Unreachable {
+                error: e.to_string(),
+            },
+        };
+        (status, block_height.ok())
+    }
+
+    /// confirm polls all of the endpoints until a quorum agrees or the
+    /// blockhash expires; the expiry goes by the lowest block height
+    /// reported, so a node ahead of the others can't cut the wait short
+    pub async fn confirm(
+        &self,
+        signature: &Signature,
+        last_valid_block_height: u64,
+    ) -> Result<QuorumConfirmation, Box<dyn std::error::Error>> {
+        let mut warned = false;
+        loop {
+            let reports =
+                futures_util::future::join_all(self.endpoints.iter().map(
+                    |(_, rpc_client)| Self::status(rpc_client, signature),
+                ))
+                .await;
+            let block_height = reports
+                .iter()
+                .filter_map(|(_, block_height)| *block_height)
+                .min()
+                .ok_or("no endpoint returned the block height")?;
+            let statuses = reports
+                .into_iter()
+                .map(|(status, _)| status)
+                .collect::<Vec<EndpointStatus>>();
+            let (verdict, disagreement) = tally(&statuses, self.quorum);
+            let statuses = self
+                .endpoints
+                .iter()
+                .map(|(url, _)| url.clone())
+                .zip(statuses)
+                .collect::<Vec<(String, EndpointStatus)>>();
+            if disagreement && !warned {
+                warn!(
+                    "{} endpoints disagree on the outcome: {:?}",
+                    signature, statuses
+                );
+                warned = true;
+            }
+            let expired = block_height > last_valid_block_height;
+            if verdict != QuorumVerdict::Pending || expired {
+                if expired && verdict == QuorumVerdict::Pending {
+                    warn!(
+                        "{} expired at block height {} without a quorum of {}",
+                        signature, last_valid_block_height, self.quorum
+                    );
+                }
+                return Ok(QuorumConfirmation {
+                    verdict,
+                    quorum: self.quorum,
+                    statuses,
+                    disagreement: disagreement || warned,
+                    block_height,
+                    last_valid_block_height,
+                });
+            }
+            debug!(
+                "{} pending quorum, {} blocks left",
+                signature,
+                last_valid_block_height - block_height
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        }
+    }
+}
+
+/// DEFAULT_SLOT_TIME_MS is used when there are no performance samples
+pub const DEFAULT_SLOT_TIME_MS: f64 = 400.;
+/// landing probability per slot is clamped to this range, nothing is
+/// guaranteed to land and even a zero fee lands eventually
+const MIN_LANDING_PROBABILITY: f64 = 0.05;
+const MAX_LANDING_PROBABILITY: f64 = 0.95;
+
+/// ConfirmationEstimate is a rough time-to-confirmation for a priority fee
+/// (micro-lamports per CU), the fee is ranked against the minimum fees that
+/// landed in the recent slots, that rank is taken as the chance to land in
+/// any given slot, so the expected wait in slots is 1 / probability
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmationEstimate {
+    pub fee: u64,
+    /// samples: number of recent slots the fee was ranked against
+    pub samples: usize,
+    /// fee_rank: fraction of the samples with a fee at or below fee
+    pub fee_rank: f64,
+    pub landing_probability: f64,
+    pub expected_slots: f64,
+    /// slot_time_ms: average slot time from the performance samples
+    pub slot_time_ms: f64,
+    pub expected_secs: f64,
+}
+
+/// confirmation_estimate is the model behind
+/// `Provider::estimate_confirmation_time`
+pub fn confirmation_estimate(
+    fee: u64,
+    recent_fees: &[u64],
+    slot_time_ms: f64,
+) -> ConfirmationEstimate {
+    let fee_rank = if recent_fees.is_empty() {
+        1.
+    } else {
+        recent_fees.iter().filter(|recent| **recent <= fee).count() as f64
+            / recent_fees.len() as f64
+    };
+    let landing_probability =
+        fee_rank.clamp(MIN_LANDING_PROBABILITY, MAX_LANDING_PROBABILITY);
+    let expected_slots = 1. / landing_probability;
+    ConfirmationEstimate {
+        fee,
+        samples: recent_fees.len(),
+        fee_rank,
+        landing_probability,
+        expected_slots,
+        slot_time_ms,
+        expected_secs: expected_slots * slot_time_ms / 1000.,
+    }
+}
+
+/// NORMAL_FINALIZATION_GAP is the usual distance between the processed and
+/// the finalized slot of a healthy node
+pub const NORMAL_FINALIZATION_GAP: u64 = 32;
+
+/// LagCheck is the staleness check of the RPC node, without a reference
+/// endpoint the gap between the processed and finalized slots is used; if
+/// the lag exceeds `max_lag` it either warns or fails
+#[derive(Clone)]
+pub struct LagCheck {
+    pub max_lag: u64,
+    pub reference_url: Option<String>,
+    pub fail: bool,
+    pub metrics: Option<RpcLagMetrics>,
+}
+
+/// WalletBalances is the SOL (lamports) and the non-zero SPL token and
+/// Token-2022 holdings of a wallet, error is set (and the holdings left
+/// empty) if its token accounts could not be read
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WalletBalances {
+    pub wallet: String,
+    pub lamports: u64,
+    pub holdings: Vec<Holding>,
+    pub error: Option<String>,
+}
+
+/// Jitter is a random delay (uniform in min_ms..=max_ms) before sending a
+/// transaction, so that sends on a trigger don't land at a predictable
+/// offset from it; it is a light mitigation only, the transaction is
+/// still visible to the leader and anyone it gets forwarded to, use jito
+/// bundles for actual protection. It applies to the plain RPC sends only
+/// (`Provider::send_tx_with_jitter`, e.g. the pump.fun sends), bundles are
+/// never delayed. Off (0-0) by default, parses from "min-max" or "max"
+/// (milliseconds)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Jitter {
+    pub min_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Jitter {
+    pub fn new(min_ms: u64, max_ms: u64) -> Self {
+        Jitter {
+            min_ms: min_ms.min(max_ms),
+            max_ms: min_ms.max(max_ms),
+        }
+    }
+
+    /// from_env reads LISTEN_SEND_JITTER_MS, off if unset or invalid
+    pub fn from_env() -> Self {
+        util::env_or("LISTEN_SEND_JITTER_MS", Jitter::default())
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.max_ms == 0
+    }
+
+    pub fn sample(&self) -> std::time::Duration {
+        if self.is_off() {
+            return std::time::Duration::ZERO;
+        }
+        std::time::Duration::from_millis(
+            rand::thread_rng().gen_range(self.min_ms..=self.max_ms),
+        )
+    }
+
+    /// wait sleeps for a sampled delay, returns right away if off
+    pub async fn wait(&self) {
+        let delay = self.sample();
+        if !delay.is_zero() {
+            debug!("jitter: waiting {:?} before send", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl FromStr for Jitter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |raw: &str| {
+            raw.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("invalid jitter {:?}: {}", s, e))
+        };
+        match s.split_once('-') {
+            Some((min, max)) => Ok(Jitter::new(parse(min)?, parse(max)?)),
+            None => Ok(Jitter::new(0, parse(s)?)),
+        }
+    }
+}
+
+impl std::fmt::Display for Jitter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.min_ms, self.max_ms)
+    }
+}
+
+/// MintInfo is the cached part of a mint account, owner is the token
+/// program the mint belongs to
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MintInfo {
+    pub decimals: u8,
+    pub owner: Pubkey,
+}
+
+static MINT_INFO_CACHE: OnceLock<RwLock<HashMap<Pubkey, MintInfo>>> =
+    OnceLock::new();
+
+fn mint_info_cache() -> &'static RwLock<HashMap<Pubkey, MintInfo>> {
+    MINT_INFO_CACHE.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// CuSuggestion is a compute unit limit for swaps on a pool, the highest
+/// units consumed by its recent successful txs plus `margin_bps`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CuSuggestion {
+    pub cu_limit: u32,
+    pub samples: usize,
+    pub max_consumed: u64,
+    pub median_consumed: u64,
+}
+
+/// the runtime caps a tx at 1.4M compute units
+pub const MAX_CU_LIMIT: u32 = 1_400_000;
+pub const DEFAULT_CU_MARGIN_BPS: u64 = 2_000;
+/// CU_SUGGESTION_TTL: how long a pool's suggestion is served from cache
+pub const CU_SUGGESTION_TTL: std::time::Duration =
+    std::time::Duration::from_secs(600);
+
+/// suggest_cu_limit_from is the max of the consumed units plus margin_bps,
+/// rounded up to a thousand and capped at MAX_CU_LIMIT, None without units
+pub fn suggest_cu_limit_from(
+    consumed: &[u64],
+    margin_bps: u64,
+) -> Option<CuSuggestion> {
+    let mut sorted = consumed.to_vec();
+    sorted.sort_unstable();
+    let max_consumed = *sorted.last()?;
+    let with_margin = max_consumed * (10_000 + margin_bps) / 10_000;
+    let rounded = with_margin.div_ceil(1_000) * 1_000;
+    Some(CuSuggestion {
+        cu_limit: rounded.min(MAX_CU_LIMIT as u64) as u32,
+        samples: sorted.len(),
+        max_consumed,
+        median_consumed: sorted[(sorted.len() - 1) / 2],
+    })
+}
+
+/// CachedCuSuggestion is a pool's CU suggestion with when it was cached,
+/// for the TTL, and when it was learned (unix secs), kept across restarts
+type CachedCuSuggestion = (std::time::Instant, i64, CuSuggestion);
+
+static CU_SUGGESTIONS: OnceLock<RwLock<HashMap<Pubkey, CachedCuSuggestion>>> =
+    OnceLock::new();
+
+fn cu_suggestions() -> &'static RwLock<HashMap<Pubkey, CachedCuSuggestion>> {
+    CU_SUGGESTIONS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// MAX_FEE_SAMPLES: how many of the last sampled (slot, fee) pairs are kept
+const MAX_FEE_SAMPLES: usize = 150;
+
+static FEE_SAMPLES: OnceLock<RwLock<Vec<(u64, u64)>>> = OnceLock::new();
+
+fn fee_samples() -> &'static RwLock<Vec<(u64, u64)>> {
+    FEE_SAMPLES.get_or_init(|| RwLock::new(vec![]))
+}
+
+/// remember_fee_samples keeps the newest of the sampled prioritization fees
+/// (slot, fee) as the fallback when the RPC returns none
+pub fn remember_fee_samples(samples: &[(u64, u64)]) {
+    let mut kept = fee_samples().write().expect("write");
+    kept.extend_from_slice(samples);
+    kept.sort_unstable();
+    kept.dedup_by_key(|(slot, _)| *slot);
+    let excess = kept.len().saturating_sub(MAX_FEE_SAMPLES);
+    kept.drain(..excess);
+}
+
+pub fn learned_fee_samples() -> Vec<(u64, u64)> {
+    fee_samples().read().expect("read").clone()
+}
+
+/// FEE_LEARNING_VERSION is bumped on breaking changes to FeeLearning,
+/// older files are still loaded
+pub const FEE_LEARNING_VERSION: u32 = 1;
+/// FEE_LEARNING_MAX_AGE: learned state older than this is discarded on load
+pub const FEE_LEARNING_MAX_AGE: std::time::Duration =
+    std::time::Duration::from_secs(6 * 3600);
+
+/// LearnedCuLimit is a pool's CU suggestion with when it was learned (unix
+/// secs)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LearnedCuLimit {
+    pub amm_pool: String,
+    pub learned_at: i64,
+    pub suggestion: CuSuggestion,
+}
+
+/// FeeLearning is the fee tuning learned during a session, the CU limit
+/// suggestions of the pools and the recent prioritization fee samples the
+/// percentiles are taken of, saved on shutdown and loaded on startup for a
+/// warm start
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeLearning {
+    pub version: u32,
+    pub saved_at: i64,
+    pub cu_limits: Vec<LearnedCuLimit>,
+    pub fee_samples: Vec<(u64, u64)>,
+}
+
+impl FeeLearning {
+    /// snapshot is the state learned so far by this process
+    pub fn snapshot() -> Self {
+        let now = chrono::Utc::now().timestamp();
+        let cu_limits = cu_suggestions()
+            .read()
+            .expect("read")
+            .iter()
+            .map(|(amm_pool, (_, learned_at, suggestion))| LearnedCuLimit {
+                amm_pool: amm_pool.to_string(),
+                learned_at: *learned_at,
+                suggestion: *suggestion,
+            })
+            .collect();
+        FeeLearning {
+            version: FEE_LEARNING_VERSION,
+            saved_at: now,
+            cu_limits,
+            fee_samples: learned_fee_samples(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let state: FeeLearning = serde_json::from_str(json)?;
+        if state.version > FEE_LEARNING_VERSION {
+            return Err(format!(
+                "fee learning version {} is newer than {}",
+                state.version, FEE_LEARNING_VERSION
+            )
+            .into());
+        }
+        Ok(state)
+    }
+
+    /// fresh drops what was learned more than max_age before now (unix
+    /// secs), fee samples go with the whole state being too old
+    pub fn fresh(mut self, now: i64, max_age: std::time::Duration) -> Self {
+        let max_age = max_age.as_secs() as i64;
+        self.cu_limits
+            .retain(|learned| now - learned.learned_at <= max_age);
+        if now - self.saved_at > max_age {
+            self.fee_samples.clear();
+        }
+        self
+    }
+
+    pub fn save(
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_string_pretty(&FeeLearning::snapshot())?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// load restores the state saved under path unless older than max_age,
+    /// the restored CU suggestions are served for another
+    /// CU_SUGGESTION_TTL before they are re-sampled; returns how many pools
+    /// were restored
+    pub fn load(
+        path: impl AsRef<std::path::Path>,
+        max_age: std::time::Duration,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let state = FeeLearning::from_json(&std::fs::read_to_string(path)?)?
+            .fresh(chrono::Utc::now().timestamp(), max_age);
+        state.restore()
+    }
+
+    fn restore(self) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut restored = 0;
+        let mut suggestions = cu_suggestions().write().expect("write");
+        for learned in self.cu_limits {
+            let amm_pool = Pubkey::from_str(&learned.amm_pool)?;
+            suggestions.entry(amm_pool).or_insert((
+                std::time::Instant::now(),
+                learned.learned_at,
+                learned.suggestion,
+            ));
+            restored += 1;
+        }
+        drop(suggestions);
+        remember_fee_samples(&self.fee_samples);
+        info!(
+            "restored the fee learning of {} pools, {} fee samples",
+            restored,
+            self.fee_samples.len()
+        );
+        Ok(restored)
+    }
+}
+
+/// max number of accounts per getMultipleAccounts call
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+/// max number of wallets whose token accounts are read at once
+const MAX_CONCURRENT_WALLETS: usize = 8;
+
 // Provider provides the data, contains both RPC client that can
 // communicate over the REST interface and utilities like getting
 // the pricing data from Jupiter
@@ -59,6 +698,117 @@ impl Provider {
         Ok(holdings)
     }
 
+    /// portfolio_balances reads the balances of all of the wallets, SOL for
+    /// up to 100 wallets per getMultipleAccounts call and the SPL token and
+    /// Token-2022 accounts of up to MAX_CONCURRENT_WALLETS wallets at once,
+    /// a wallet whose token accounts fail is reported in its error
+    #[timed(duration(printer = "info!"))]
+    pub async fn portfolio_balances(
+        rpc_client: &RpcClient,
+        wallets: &[Pubkey],
+    ) -> Result<Vec<WalletBalances>, Box<dyn std::error::Error>> {
+        let mut lamports = Vec::with_capacity(wallets.len());
+        for chunk in wallets.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = rpc_client.get_multiple_accounts(chunk).await?;
+            lamports.extend(
+                accounts
+                    .iter()
+                    .map(|account| account.as_ref().map_or(0, |a| a.lamports)),
+            );
+        }
+        let token_accounts = |wallet, program_id| {
+            rpc_client.get_token_accounts_by_owner(
+                wallet,
+                TokenAccountsFilter::ProgramId(program_id),
+            )
+        };
+        let holdings = futures_util::stream::iter(wallets)
+            .map(|wallet| async move {
+                let (spl, token_2022) = futures_util::future::try_join(
+                    token_accounts(wallet, spl_token::id()),
+                    token_accounts(wallet, spl_token_2022::id()),
+                )
+                .await?;
+                Ok::<_, solana_client::client_error::ClientError>(
+                    spl.into_iter().chain(token_2022).collect::<Vec<_>>(),
+                )
+            })
+            .buffered(MAX_CONCURRENT_WALLETS)
+            .collect::<Vec<_>>()
+            .await;
+        let balances = wallets
+            .iter()
+            .zip(lamports)
+            .zip(holdings)
+            .map(|((wallet, lamports), atas)| match atas {
+                Ok(atas) => WalletBalances {
+                    wallet: wallet.to_string(),
+                    lamports,
+                    holdings: atas
+                        .into_iter()
+                        .filter_map(|ata| parse_holding(ata).ok())
+                        .filter(|holding| holding.amount > 0)
+                        .collect(),
+                    error: None,
+                },
+                Err(e) => {
+                    warn!("token accounts of {}: {}", wallet, e);
+                    WalletBalances {
+                        wallet: wallet.to_string(),
+                        lamports,
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    }
+                }
+            })
+            .collect::<Vec<WalletBalances>>();
+        Ok(balances)
+    }
+
+    /// mint_info returns the decimals and the token program of the mint,
+    /// fetched once per process
+    pub async fn mint_info(
+        rpc_client: &RpcClient,
+        mint: &Pubkey,
+    ) -> Result<MintInfo, Box<dyn std::error::Error>> {
+        if let Some(info) = mint_info_cache().read().expect("read").get(mint) {
+            return Ok(*info);
+        }
+        let account = rpc_client.get_account(mint).await?;
+        let state = StateWithExtensionsOwned::<Mint>::unpack(account.data)?;
+        let info = MintInfo {
+            decimals: state.base.decimals,
+            owner: account.owner,
+        };
+        mint_info_cache()
+            .write()
+            .expect("write")
+            .insert(*mint, info);
+        Ok(info)
+    }
+
+    /// warm loads the mint info of the mints ahead of trading, returns how
+    /// long it took and the mints that failed
+    #[timed(duration(printer = "info!"))]
+    pub async fn warm(
+        rpc_client: &RpcClient,
+        mints: &[Pubkey],
+    ) -> (std::time::Duration, Vec<(Pubkey, String)>) {
+        let start = std::time::Instant::now();
+        let results = futures_util::future::join_all(
+            mints.iter().map(|mint| Self::mint_info(rpc_client, mint)),
+        )
+        .await;
+        let failures = mints
+            .iter()
+            .zip(results)
+            .filter_map(|(mint, res)| {
+                res.err().map(|e| (*mint, e.to_string()))
+            })
+            .collect::<Vec<(Pubkey, String)>>();
+        (start.elapsed(), failures)
+    }
+
     #[timed(duration(printer = "info!"))]
     pub async fn get_balance(
         rpc_client: &RpcClient,
@@ -146,21 +896,31 @@ impl Provider {
         Ok(data)
     }
 
+    /// send_tx_with_jitter waits for a sampled jitter delay, then sends
+    pub async fn send_tx_with_jitter(
+        rpc_client: &RpcClient,
+        tx: &impl SerializableTransaction,
+        skip_preflight: bool,
+        jitter: &Jitter,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        jitter.wait().await;
+        Provider::send_tx(rpc_client, tx, skip_preflight).await
+    }
+
     #[timed(duration(printer = "info!"))]
     pub async fn send_tx(
         rpc_client: &RpcClient,
         tx: &impl SerializableTransaction,
-        _skip_preflight: bool,
+        skip_preflight: bool,
     ) -> Result<String, Box<dyn std::error::Error>> {
         let start = std::time::Instant::now();
         match rpc_client
-            .send_transaction(
+            .send_transaction_with_config(
                 tx,
-                // CommitmentConfig::processed(),
-                // RpcSendTransactionConfig {
-                //     skip_preflight,
-                //     ..RpcSendTransactionConfig::default()
-                // },
+                RpcSendTransactionConfig {
+                    skip_preflight,
+                    ..RpcSendTransactionConfig::default()
+                },
             )
             .await
         {
@@ -172,6 +932,355 @@ impl Provider {
         }
     }
 
+    /// wait_for_token_balance polls the token account until it holds at
+    /// least `min`, right after a buy the balance might still read zero (or
+    /// the account might not exist yet) at lower commitment levels
+    #[timed(duration(printer = "info!"))]
+    pub async fn wait_for_token_balance(
+        rpc_client: &RpcClient,
+        ata: &Pubkey,
+        min: u64,
+        timeout: std::time::Duration,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let start = std::time::Instant::now();
+        loop {
+            match rpc_client.get_token_account_balance(ata).await {
+                Ok(balance) => {
+                    let amount = balance.amount.parse::<u64>()?;
+                    if amount >= min {
+                        return Ok(amount);
+                    }
+                    debug!("{} balance {} below {}", ata, amount, min);
+                }
+                Err(e) => debug!("{} error getting balance: {}", ata, e),
+            }
+            if start.elapsed() >= timeout {
+                return Err(format!(
+                    "{} balance did not reach {} within {:?}",
+                    ata, min, timeout
+                )
+                .into());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+    }
+
+    /// estimate_confirmation_time ranks fee against the prioritization fees
+    /// of the recent slots for the given accounts (global if empty)
+    #[timed(duration(printer = "info!"))]
+    pub async fn estimate_confirmation_time(
+        rpc_client: &RpcClient,
+        fee: u64,
+        addresses: &[Pubkey],
+    ) -> Result<ConfirmationEstimate, Box<dyn std::error::Error>> {
+        let recent_fees = rpc_client
+            .get_recent_prioritization_fees(addresses)
+            .await?
+            .iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect::<Vec<u64>>();
+        let (slots, secs) = rpc_client
+            .get_recent_performance_samples(Some(10))
+            .await?
+            .iter()
+            .fold((0u64, 0u64), |(slots, secs), sample| {
+                (
+                    slots + sample.num_slots,
+                    secs + sample.sample_period_secs as u64,
+                )
+            });
+        let slot_time_ms = if slots > 0 {
+            secs as f64 * 1000. / slots as f64
+        } else {
+            DEFAULT_SLOT_TIME_MS
+        };
+        let estimate = confirmation_estimate(fee, &recent_fees, slot_time_ms);
+        debug!("{}", serde_json::to_string_pretty(&estimate)?);
+        Ok(estimate)
+    }
+
+    /// confirm_until_expiry polls the status of the tx until it is confirmed
+    /// or the block height passes last_valid_block_height of its blockhash
+    #[timed(duration(printer = "info!"))]
+    pub async fn confirm_until_expiry(
+        rpc_client: &RpcClient,
+        signature: &Signature,
+        last_valid_block_height: u64,
+    ) -> Result<Confirmation, Box<dyn std::error::Error>> {
+        loop {
+            let block_height = rpc_client.get_block_height().await?;
+            let status = rpc_client
+                .get_signature_statuses(&[*signature])
+                .await?
+                .value
+                .into_iter()
+                .next()
+                .flatten();
+            if let Some(status) = status.filter(|status| {
+                status.satisfies_commitment(CommitmentConfig::confirmed())
+            }) {
+                return Ok(Confirmation {
+                    confirmed: true,
+                    err: status.err,
+                    block_height,
+                    last_valid_block_height,
+                });
+            }
+            if block_height > last_valid_block_height {
+                warn!(
+                    "{} expired at block height {}",
+                    signature, last_valid_block_height
+                );
+                return Ok(Confirmation {
+                    confirmed: false,
+                    err: None,
+                    block_height,
+                    last_valid_block_height,
+                });
+            }
+            debug!(
+                "{} pending, {} blocks left",
+                signature,
+                last_valid_block_height - block_height
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+        }
+    }
+
+    /// verify_swap fetches the confirmed swap tx and checks that the owner's
+    /// balances moved in the direction of the trade by at least `min_out`
+    #[timed(duration(printer = "info!"))]
+    pub async fn verify_swap(
+        rpc_client: &RpcClient,
+        signature: &str,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        side: TradeSide,
+        min_out: u64,
+    ) -> Result<BalanceDelta, Box<dyn std::error::Error>> {
+        let tx = tx_parser::fetch_tx(rpc_client, signature).await?;
+        let delta = tx_parser::parse_balance_delta(&tx, owner, mint)?;
+        info!("{} balance delta: {:?}", signature, delta);
+        tx_parser::verify_balance_delta(&delta, side, min_out)?;
+        Ok(delta)
+    }
+
+    /// suggest_cu_limit samples the last `limit` successful txs of the pool
+    /// and suggests a CU limit off their computeUnitsConsumed, cached per
+    /// pool for CU_SUGGESTION_TTL; the txs may do more than the swap (ATA
+    /// creation, tips), which only errs on the safe side
+    #[timed(duration(printer = "info!"))]
+    pub async fn suggest_cu_limit(
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        limit: usize,
+    ) -> Result<CuSuggestion, Box<dyn std::error::Error>> {
+        if let Some((at, _, suggestion)) =
+            cu_suggestions().read().expect("read").get(amm_pool)
+        {
+            if at.elapsed() < CU_SUGGESTION_TTL {
+                return Ok(*suggestion);
+            }
+        }
+        let signatures = rpc_client
+            .get_signatures_for_address_with_config(
+                amm_pool,
+                GetConfirmedSignaturesForAddress2Config {
+                    limit: Some(limit),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let txs = futures_util::future::join_all(
+            signatures
+                .iter()
+                .filter(|sig| sig.err.is_none())
+                .map(|sig| tx_parser::fetch_tx(rpc_client, &sig.signature)),
+        )
+        .await;
+        let consumed = txs
+            .into_iter()
+            .filter_map(|tx| match tx {
+                Ok(tx) => tx.transaction.meta,
+                Err(e) => {
+                    debug!("suggest_cu_limit: {}", e);
+                    None
+                }
+            })
+            .filter_map(|meta| {
+                Option::<u64>::from(meta.compute_units_consumed)
+            })
+            .collect::<Vec<u64>>();
+        let suggestion =
+            suggest_cu_limit_from(&consumed, DEFAULT_CU_MARGIN_BPS)
+                .ok_or_else(|| {
+                    format!("no recent successful txs on {}", amm_pool)
+                })?;
+        info!("{} cu limit: {:?}", amm_pool, suggestion);
+        cu_suggestions().write().expect("write").insert(
+            *amm_pool,
+            (
+                std::time::Instant::now(),
+                chrono::Utc::now().timestamp(),
+                suggestion,
+            ),
+        );
+        Ok(suggestion)
+    }
+
+    /// recent_swaps returns the swaps of the last `limit` successful txs of
+    /// the pool (newest first), the side and the amounts are taken from the
+    /// balance changes of the signer, txs that did not move the token of the
+    /// pool (e.g. the pool creation) are skipped
+    #[timed(duration(printer = "info!"))]
+    pub async fn recent_swaps(
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        limit: usize,
+    ) -> Result<Vec<Swap>, Box<dyn std::error::Error>> {
+        let amm_keys = amm::utils::load_amm_keys(
+            rpc_client,
+            &constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY,
+            amm_pool,
+        )
+        .await?;
+        let token_mint =
+            if amm_keys.amm_coin_mint == constants::SOLANA_PROGRAM_ID {
+                amm_keys.amm_pc_mint
+            } else {
+                amm_keys.amm_coin_mint
+            };
+        let signatures = rpc_client
+            .get_signatures_for_address_with_config(
+                amm_pool,
+                GetConfirmedSignaturesForAddress2Config {
+                    limit: Some(limit),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let txs = futures_util::future::join_all(
+            signatures
+                .iter()
+                .filter(|sig| sig.err.is_none())
+                .map(|sig| tx_parser::fetch_tx(rpc_client, &sig.signature)),
+        )
+        .await;
+
+        let mut swaps = vec![];
+        for (sig, tx) in
+            signatures.iter().filter(|sig| sig.err.is_none()).zip(txs)
+        {
+            let tx = match tx {
+                Ok(tx) => tx,
+                Err(e) => {
+                    warn!("recent_swaps: {}", e);
+                    continue;
+                }
+            };
+            let Ok(signer) = tx_parser::parse_signer(&tx) else {
+                continue;
+            };
+            let Ok(delta) = tx_parser::parse_balance_delta(
+                &tx,
+                &Pubkey::from_str(&signer)?,
+                &token_mint,
+            ) else {
+                continue;
+            };
+            let side = match delta.token_delta.signum() {
+                1 => TradeSide::Buy,
+                -1 => TradeSide::Sell,
+                _ => continue,
+            };
+            // the fee is not part of the swapped amount
+            let lamports =
+                (delta.sol_delta + delta.fee as i128).unsigned_abs() as u64;
+            swaps.push(Swap {
+                signature: sig.signature.clone(),
+                signer,
+                side: Some(side),
+                quote_amount: lamports as f64,
+                quote_mint: constants::SOLANA_PROGRAM_ID.to_string(),
+                base_amount: delta.token_delta.unsigned_abs() as f64,
+                base_mint: token_mint.to_string(),
+                sol_amount_ui: util::lamports_to_sol(lamports),
+                transfers: vec![],
+            });
+        }
+        Ok(swaps)
+    }
+
+    /// rpc_lag returns how many slots the node is behind the reference
+    #[timed(duration(printer = "info!"))]
+    pub async fn rpc_lag(
+        rpc_client: &RpcClient,
+        reference: Option<&RpcClient>,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let slot = rpc_client
+            .get_slot_with_commitment(CommitmentConfig::processed())
+            .await?;
+        let lag = match reference {
+            Some(reference) => reference
+                .get_slot_with_commitment(CommitmentConfig::processed())
+                .await?
+                .saturating_sub(slot),
+            None => {
+                let finalized = rpc_client
+                    .get_slot_with_commitment(CommitmentConfig::finalized())
+                    .await?;
+                slot.saturating_sub(finalized)
+                    .saturating_sub(NORMAL_FINALIZATION_GAP)
+            }
+        };
+        debug!("rpc slot {} lag {}", slot, lag);
+        Ok(lag)
+    }
+
+    /// check_rpc_lag measures the lag, exports it and warns or errors if it
+    /// is above the threshold of the check
+    pub async fn check_rpc_lag(
+        rpc_client: &RpcClient,
+        check: &LagCheck,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let reference = check
+            .reference_url
+            .as_ref()
+            .map(|url| RpcClient::new(url.to_string()));
+        let lag = Self::rpc_lag(rpc_client, reference.as_ref()).await?;
+        if let Some(metrics) = &check.metrics {
+            metrics.set(lag);
+        }
+        if lag > check.max_lag {
+            let msg = format!(
+                "rpc is {} slots behind (max {}), reads might be stale",
+                lag, check.max_lag
+            );
+            if check.fail {
+                return Err(msg.into());
+            }
+            warn!("{}", msg);
+        }
+        Ok(lag)
+    }
+
+    /// simulate runs the transaction through simulateTransaction, a failing
+    /// transaction is not an error here, check the `err` of the result
+    #[timed(duration(printer = "info!"))]
+    pub async fn simulate(
+        rpc_client: &RpcClient,
+        tx: &impl SerializableTransaction,
+    ) -> Result<SimulationResult, Box<dyn std::error::Error>> {
+        let res = rpc_client.simulate_transaction(tx).await?;
+        Ok(SimulationResult {
+            err: res.value.err,
+            logs: res.value.logs.unwrap_or_default(),
+            units: res.value.units_consumed,
+        })
+    }
+
     /// sanity_check is for mint_authority and freeze_authority, for non
     /// pump.fun tokens is crucial, mint authority enables minting any amount of
     /// the token and freeze authority can renounce the ability to trade the
@@ -269,3 +1378,132 @@ pub async fn get_tx_async(
     }
     Err(format!("could not fetch {}", signature).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmation_estimate() {
+        let recent_fees = [0, 0, 100, 1_000, 10_000];
+        let estimate = confirmation_estimate(1_000, &recent_fees, 400.);
+        assert_eq!(estimate.fee_rank, 0.8);
+        assert!((estimate.expected_slots - 1.25).abs() < 1e-9);
+        assert!((estimate.expected_secs - 0.5).abs() < 1e-9);
+        // below every sample, clamped to the minimum probability
+        let estimate = confirmation_estimate(0, &[10, 20], 400.);
+        assert_eq!(estimate.landing_probability, MIN_LANDING_PROBABILITY);
+        assert!((estimate.expected_slots - 20.).abs() < 1e-9);
+        // nothing to rank against
+        let estimate = confirmation_estimate(0, &[], 400.);
+        assert_eq!(estimate.landing_probability, MAX_LANDING_PROBABILITY);
+    }
+
+    #[test]
+    fn test_jitter() {
+        assert_eq!("50-250".parse::<Jitter>().unwrap(), Jitter::new(50, 250));
+        assert_eq!("100".parse::<Jitter>().unwrap(), Jitter::new(0, 100));
+        assert_eq!(
+            "300-100".parse::<Jitter>().unwrap(),
+            Jitter::new(100, 300)
+        );
+        assert!("fast".parse::<Jitter>().is_err());
+        assert!(Jitter::default().is_off());
+        assert!(Jitter::default().sample().is_zero());
+        let jitter = Jitter::new(10, 20);
+        for _ in 0..100 {
+            let delay = jitter.sample().as_millis();
+            assert!((10..=20).contains(&delay));
+        }
+        assert_eq!(jitter.to_string().parse::<Jitter>().unwrap(), jitter);
+    }
+
+    #[test]
+    fn test_tally() {
+        let failed = EndpointStatus::Failed {
+            err: TransactionError::AccountInUse,
+        };
+        let unreachable = EndpointStatus::Unreachable {
+            error: "timeout".to_string(),
+        };
+        let statuses = [
+            EndpointStatus::Confirmed,
+            EndpointStatus::Pending,
+            EndpointStatus::Confirmed,
+        ];
+        assert_eq!(tally(&statuses, 2), (QuorumVerdict::Confirmed, false));
+        assert_eq!(tally(&statuses, 3), (QuorumVerdict::Pending, false));
+        let statuses = [
+            EndpointStatus::Confirmed,
+            failed.clone(),
+            unreachable.clone(),
+        ];
+        assert_eq!(tally(&statuses, 2), (QuorumVerdict::Pending, true));
+        let statuses = [failed.clone(), failed, unreachable];
+        assert_eq!(tally(&statuses, 2), (QuorumVerdict::Failed, false));
+    }
+
+    #[test]
+    fn test_fee_learning() {
+        let suggestion = suggest_cu_limit_from(&[50_000], 0).unwrap();
+        let learned = |amm_pool: Pubkey, learned_at| LearnedCuLimit {
+            amm_pool: amm_pool.to_string(),
+            learned_at,
+            suggestion,
+        };
+        let (fresh, stale) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let state = FeeLearning {
+            version: FEE_LEARNING_VERSION,
+            saved_at: 10_000,
+            cu_limits: vec![learned(fresh, 9_000), learned(stale, 1_000)],
+            fee_samples: vec![(1, 5_000), (2, 7_000)],
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        let loaded = FeeLearning::from_json(&json).unwrap();
+        assert_eq!(loaded, state);
+
+        let max_age = std::time::Duration::from_secs(3_600);
+        let kept = loaded.clone().fresh(10_000, max_age);
+        assert_eq!(kept.cu_limits, vec![learned(fresh, 9_000)]);
+        assert_eq!(kept.fee_samples.len(), 2);
+        // the whole state is too old
+        let expired = loaded.fresh(20_000, max_age);
+        assert!(expired.cu_limits.is_empty());
+        assert!(expired.fee_samples.is_empty());
+
+        assert_eq!(kept.restore().unwrap(), 1);
+        assert_eq!(
+            cu_suggestions()
+                .read()
+                .unwrap()
+                .get(&fresh)
+                .map(|(_, _, s)| *s),
+            Some(suggestion)
+        );
+        // a restored entry keeps when it was learned, not when it was loaded
+        let snapshot = FeeLearning::snapshot();
+        assert!(snapshot.cu_limits.contains(&learned(fresh, 9_000)));
+
+        let newer = FeeLearning {
+            version: FEE_LEARNING_VERSION + 1,
+            ..state
+        };
+        let json = serde_json::to_string(&newer).unwrap();
+        assert!(FeeLearning::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn test_suggest_cu_limit_from() {
+        let suggestion =
+            suggest_cu_limit_from(&[60_000, 45_500, 52_000], 2_000).unwrap();
+        assert_eq!(suggestion.max_consumed, 60_000);
+        assert_eq!(suggestion.median_consumed, 52_000);
+        assert_eq!(suggestion.cu_limit, 72_000);
+        // rounded up to a thousand
+        let suggestion = suggest_cu_limit_from(&[50_100], 0).unwrap();
+        assert_eq!(suggestion.cu_limit, 51_000);
+        let suggestion = suggest_cu_limit_from(&[1_300_000], 2_000).unwrap();
+        assert_eq!(suggestion.cu_limit, MAX_CU_LIMIT);
+        assert!(suggest_cu_limit_from(&[], 2_000).is_none());
+    }
+}
@@ -21,10 +21,9 @@ use serde::{Deserialize, Serialize};
 use solana_client::nonblocking::pubsub_client::PubsubClient;
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_client::rpc_config::{
-    RpcAccountInfoConfig, RpcSendTransactionConfig, RpcTransactionLogsConfig,
-    RpcTransactionLogsFilter,
+    RpcAccountInfoConfig, RpcTransactionLogsConfig, RpcTransactionLogsFilter,
 };
-use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::instruction::{AccountMeta, Instruction};
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::{EncodableKey, Signer};
@@ -39,6 +38,7 @@ use crate::get_tx_async_with_client;
 use crate::jito::{send_swap_tx_no_wait, SearcherClient};
 use crate::raydium::make_compute_budget_ixs;
 use crate::util::{env, pubkey_to_string, string_to_pubkey, string_to_u64};
+use crate::{Jitter, Provider};
 
 pub const PUMP_GLOBAL_ADDRESS: Pubkey =
     pubkey!("4wTV1YmiEkRvAtNtsSGPtUrqRYQMe5SKy2uB4Jjaxnjf");
@@ -459,27 +459,15 @@ async fn _send_tx_standard(
             &[wallet],
             rpc_client.get_latest_blockhash().await?,
         ));
-    let res = rpc_client
-        .send_transaction_with_config(
-            &transaction,
-            RpcSendTransactionConfig {
-                skip_preflight: true,
-                min_context_slot: None,
-                preflight_commitment: Some(CommitmentLevel::Processed),
-                max_retries: None,
-                encoding: None,
-            },
-        )
-        .await;
-
-    match res {
-        Ok(sig) => {
-            info!("Transaction sent: {}", sig);
-        }
-        Err(e) => {
-            return Err(e.into());
-        }
-    }
+    // a plain RPC send, it gets the send jitter (bundles never do)
+    let sig = Provider::send_tx_with_jitter(
+        rpc_client,
+        &transaction,
+        true,
+        &Jitter::from_env(),
+    )
+    .await?;
+    info!("Transaction sent: {}", sig);
 
     Ok(())
 }
@@ -510,26 +498,15 @@ pub async fn sell_pump_token(
         recent_blockhash,
     );
 
-    let res = rpc_client
-        .send_transaction_with_config(
-            &transaction,
-            RpcSendTransactionConfig {
-                skip_preflight: true,
-                min_context_slot: None,
-                preflight_commitment: Some(CommitmentLevel::Processed),
-                max_retries: None,
-                encoding: None,
-            },
-        )
-        .await;
-    match res {
-        Ok(sig) => {
-            info!("Transaction sent: {}", sig);
-        }
-        Err(e) => {
-            return Err(e.into());
-        }
-    }
+    // a plain RPC send, it gets the send jitter (bundles never do)
+    let sig = Provider::send_tx_with_jitter(
+        rpc_client,
+        &transaction,
+        true,
+        &Jitter::from_env(),
+    )
+    .await?;
+    info!("Transaction sent: {}", sig);
 
     Ok(())
 }
@@ -5,7 +5,7 @@ use log::{debug, info, warn};
 use raydium_library::amm;
 use raydium_library::amm::AmmKeys;
 use raydium_library::amm::MarketPubkeys;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use solana_account_decoder::parse_account_data::ParsedAccount;
 use solana_account_decoder::UiAccountData;
@@ -14,35 +14,53 @@ use solana_client::rpc_config::RpcSendTransactionConfig;
 use solana_client::rpc_request::TokenAccountsFilter;
 use solana_client::rpc_response::RpcKeyedAccount;
 use solana_sdk::signer::EncodableKey;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
 use spl_token::instruction::burn;
 use spl_token::state::Mint;
+use spl_token_2022::extension::StateWithExtensionsOwned;
 use std::error::Error;
 use timed::timed;
+use tokio_util::sync::CancellationToken;
 use utoipa::ToSchema;
 
+use crate::approval::{ApprovalHook, ApprovalRequest, SwapTerms};
+use crate::blocklist::Blocklist;
 use crate::jito::send_jito_tx;
+use crate::positions::PositionTracker;
+use crate::provider::{ConfirmQuorum, LagCheck, SimulationResult};
 use crate::seller_service::load_amm_keys;
+use crate::stats::SessionStats;
 use crate::{constants, Provider};
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use raydium_library::common;
 use reqwest::Client;
 use serde_json::json;
-use solana_account_decoder::UiAccountEncoding;
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
 use solana_client::rpc_config::RpcAccountInfoConfig;
 use solana_client::rpc_config::RpcProgramAccountsConfig;
+use solana_client::rpc_config::{
+    RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
+};
 use solana_client::rpc_filter::Memcmp;
 use solana_client::rpc_filter::MemcmpEncodedBytes;
 use solana_client::rpc_filter::RpcFilterType;
 use solana_sdk::instruction::Instruction;
 use solana_sdk::program_pack::Pack;
 use solana_sdk::{
-    pubkey::Pubkey, signature::Keypair, signer::Signer,
+    commitment_config::CommitmentConfig,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
     transaction::Transaction,
 };
 use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Debug, Default, Clone, Serialize, ToSchema)]
 pub struct Holding {
@@ -205,7 +223,779 @@ pub async fn download_raydium_json(
     Ok(())
 }
 
-pub struct Raydium {}
+/// RaydiumProgram is the registry of the Raydium programs the crate knows
+/// about, each pool belongs to exactly one of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RaydiumProgram {
+    AmmV4,
+    Cpmm,
+    Clmm,
+}
+
+impl RaydiumProgram {
+    pub const ALL: [RaydiumProgram; 3] = [
+        RaydiumProgram::AmmV4,
+        RaydiumProgram::Cpmm,
+        RaydiumProgram::Clmm,
+    ];
+
+    pub const fn program_id(&self) -> Pubkey {
+        match self {
+            RaydiumProgram::AmmV4 => {
+                constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY
+            }
+            RaydiumProgram::Cpmm => constants::RAYDIUM_CPMM_PROGRAM_ID,
+            RaydiumProgram::Clmm => constants::RAYDIUM_CLMM_PROGRAM_ID,
+        }
+    }
+
+    pub fn from_program_id(program_id: &Pubkey) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|program| program.program_id() == *program_id)
+    }
+
+    /// supports_swap is whether `Raydium::swap` can route through pools of
+    /// the program
+    pub const fn supports_swap(&self) -> bool {
+        matches!(self, RaydiumProgram::AmmV4)
+    }
+}
+
+/// detect_program reads the owner of the pool account to tell which of the
+/// Raydium programs the pool belongs to
+pub async fn detect_program(
+    rpc_client: &RpcClient,
+    pool_id: &Pubkey,
+) -> Result<RaydiumProgram, Box<dyn Error>> {
+    let account = rpc_client.get_account(pool_id).await?;
+    RaydiumProgram::from_program_id(&account.owner).ok_or_else(|| {
+        format!(
+            "pool {} is owned by {}, not a Raydium program",
+            pool_id, account.owner
+        )
+        .into()
+    })
+}
+
+/// FeeInfo is the swap fee of a pool as numerator / denominator, config is
+/// the fee config account of CPMM and CLMM pools (with its index), AMM v4
+/// pools carry their fees in the pool itself
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeeInfo {
+    pub program: RaydiumProgram,
+    pub numerator: u64,
+    pub denominator: u64,
+    pub config: Option<String>,
+    pub config_index: Option<u16>,
+    /// tier is the label of the fee, e.g. "0.25%"
+    pub tier: String,
+}
+
+/// fee rates of the CPMM and CLMM configs are in millionths
+pub const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+
+impl FeeInfo {
+    pub fn new(
+        program: RaydiumProgram,
+        numerator: u64,
+        denominator: u64,
+    ) -> Self {
+        let pct = if denominator > 0 {
+            numerator as f64 / denominator as f64 * 100.
+        } else {
+            0.
+        };
+        FeeInfo {
+            program,
+            numerator,
+            denominator,
+            config: None,
+            config_index: None,
+            tier: format!("{}%", (pct * 10_000.).round() / 10_000.),
+        }
+    }
+
+    /// fee is the fee as a fraction (0.0025 for 25bps)
+    pub fn fee(&self) -> f64 {
+        if self.denominator > 0 {
+            self.numerator as f64 / self.denominator as f64
+        } else {
+            0.
+        }
+    }
+
+    pub fn fee_bps(&self) -> f64 {
+        self.fee() * 10_000.
+    }
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Result<Pubkey, Box<dyn Error>> {
+    let bytes: [u8; 32] = data
+        .get(offset..offset + 32)
+        .ok_or("account too short")?
+        .try_into()?;
+    Ok(Pubkey::new_from_array(bytes))
+}
+
+fn read_le<const N: usize>(
+    data: &[u8],
+    offset: usize,
+) -> Result<[u8; N], Box<dyn Error>> {
+    Ok(data
+        .get(offset..offset + N)
+        .ok_or("account too short")?
+        .try_into()?)
+}
+
+/// the mints of an AMM v4 pool (`raydium_amm::state::AmmInfo`) and of a
+/// CPMM pool state, with the account sizes to filter the pools by
+const AMM_V4_POOL_LEN: u64 = 752;
+const AMM_V4_COIN_MINT_OFFSET: usize = 400;
+const AMM_V4_PC_MINT_OFFSET: usize = 432;
+const CPMM_POOL_LEN: u64 = 637;
+const CPMM_MINT_0_OFFSET: usize = 168;
+const CPMM_MINT_1_OFFSET: usize = 200;
+
+/// PoolMatch is a pool trading a pair, as found by `find_pools_by_mint`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PoolMatch {
+    pub program: RaydiumProgram,
+    pub pool: Pubkey,
+}
+
+/// CpmmPool is the part of a CPMM pool state needed to quote it, the fees
+/// owed to the protocol and the fund sit in the vaults but are not part of
+/// the reserves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CpmmPool {
+    pub config: Pubkey,
+    pub vault_0: Pubkey,
+    pub vault_1: Pubkey,
+    pub mint_0: Pubkey,
+    pub mint_1: Pubkey,
+    pub fees_0: u64,
+    pub fees_1: u64,
+}
+
+impl CpmmPool {
+    /// parse reads the pool state: config@8, vaults@72/104, mints@168/200,
+    /// protocol fees@341/349 and fund fees@357/365
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let fee = |offset| -> Result<u64, Box<dyn Error>> {
+            Ok(u64::from_le_bytes(read_le(data, offset)?))
+        };
+        Ok(CpmmPool {
+            config: read_pubkey(data, 8)?,
+            vault_0: read_pubkey(data, 72)?,
+            vault_1: read_pubkey(data, 104)?,
+            mint_0: read_pubkey(data, CPMM_MINT_0_OFFSET)?,
+            mint_1: read_pubkey(data, CPMM_MINT_1_OFFSET)?,
+            fees_0: fee(341)?.saturating_add(fee(357)?),
+            fees_1: fee(349)?.saturating_add(fee(365)?),
+        })
+    }
+}
+
+/// cpmm_quote is the output of a CPMM swap base in, the trade fee (rounded
+/// up, in millionths) is taken off the input before the constant product
+pub fn cpmm_quote(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+    fee_rate: u64,
+) -> u64 {
+    let fee = (amount_in as u128 * fee_rate as u128)
+        .div_ceil(FEE_RATE_DENOMINATOR as u128);
+    let amount_in = (amount_in as u128).saturating_sub(fee);
+    let denominator = reserve_in as u128 + amount_in;
+    if denominator == 0 {
+        return 0;
+    }
+    (reserve_out as u128 * amount_in / denominator) as u64
+}
+
+/// fee_config_of reads the fee config account off a CPMM or CLMM pool
+/// state, both are anchor accounts (8 byte discriminator), CLMM has its
+/// bump ahead of the config
+pub fn fee_config_of(
+    program: RaydiumProgram,
+    pool_data: &[u8],
+) -> Result<Pubkey, Box<dyn Error>> {
+    match program {
+        RaydiumProgram::Cpmm => read_pubkey(pool_data, 8),
+        RaydiumProgram::Clmm => read_pubkey(pool_data, 9),
+        RaydiumProgram::AmmV4 => {
+            Err("AMM v4 pools have no fee config account".into())
+        }
+    }
+}
+
+/// parse_fee_config reads the index and the trade fee rate of a CPMM
+/// (u64 rate after bump, disable flag and index) or CLMM (u32 rate after
+/// bump, index, owner and protocol rate) config account
+pub fn parse_fee_config(
+    program: RaydiumProgram,
+    config: &Pubkey,
+    data: &[u8],
+) -> Result<FeeInfo, Box<dyn Error>> {
+    let (index, rate) = match program {
+        RaydiumProgram::Cpmm => (
+            u16::from_le_bytes(read_le(data, 10)?),
+            u64::from_le_bytes(read_le(data, 12)?),
+        ),
+        RaydiumProgram::Clmm => (
+            u16::from_le_bytes(read_le(data, 9)?),
+            u32::from_le_bytes(read_le(data, 47)?) as u64,
+        ),
+        RaydiumProgram::AmmV4 => {
+            return Err("AMM v4 pools have no fee config account".into())
+        }
+    };
+    let mut info = FeeInfo::new(program, rate, FEE_RATE_DENOMINATOR);
+    info.config = Some(config.to_string());
+    info.config_index = Some(index);
+    Ok(info)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RaydiumError {
+    #[error("simulation failed: {:?}", .0.err)]
+    SimulationFailed(SimulationResult),
+    #[error(
+        "insufficient liquidity: {required} of the input token required in \
+         the pool to stay within slippage, {available} available"
+    )]
+    InsufficientLiquidity { required: u64, available: u64 },
+    #[error(
+        "quote drifted {drift_bps}bps before signing: quoted {quoted}, now \
+         {current}"
+    )]
+    QuoteDrift {
+        quoted: u64,
+        current: u64,
+        drift_bps: u64,
+    },
+    #[error("swap cancelled before it was sent")]
+    Cancelled,
+    #[error("swap rejected: {reason}")]
+    Rejected { reason: String },
+    #[error(
+        "swap instruction encodes {field} {encoded}, intended {intended}"
+    )]
+    InstructionMismatch {
+        field: &'static str,
+        intended: u64,
+        encoded: u64,
+    },
+    #[error("pool is associated with blocklisted {role} {account}")]
+    Blocklisted { role: &'static str, account: String },
+}
+
+/// until_cancelled runs fut unless cancel fires first, without a token it
+/// just runs fut
+async fn until_cancelled<T>(
+    cancel: Option<&CancellationToken>,
+    fut: impl std::future::Future<Output = Result<T, Box<dyn Error>>>,
+) -> Result<T, Box<dyn Error>> {
+    match cancel {
+        Some(cancel) => tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(RaydiumError::Cancelled.into()),
+            res = fut => res,
+        },
+        None => fut.await,
+    }
+}
+
+/// measure_fill reads what the owner received of output_mint and the fee
+/// off the landed swap, a SOL output is the lamport change plus the fee,
+/// unless it went to the WSOL account of a session (in_session), where it
+/// stays as WSOL
+async fn measure_fill(
+    rpc_client: &RpcClient,
+    signature: &str,
+    owner: &Pubkey,
+    output_mint: &Pubkey,
+    in_session: bool,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    let tx = crate::tx_parser::fetch_tx(rpc_client, signature).await?;
+    let delta =
+        crate::tx_parser::parse_balance_delta(&tx, owner, output_mint)?;
+    Ok(fill_of(&delta, output_mint, in_session))
+}
+
+/// fill_of is the (received, fee) of measure_fill off the balance delta
+fn fill_of(
+    delta: &crate::tx_parser::BalanceDelta,
+    output_mint: &Pubkey,
+    in_session: bool,
+) -> (u64, u64) {
+    let received =
+        if *output_mint == constants::SOLANA_PROGRAM_ID && !in_session {
+            delta.sol_delta + delta.fee as i128
+        } else {
+            delta.token_delta
+        };
+    (received.max(0) as u64, delta.fee)
+}
+
+/// check_quote_drift fails if current has degraded from quoted by more than
+/// max_drift_bps, an improved quote always passes
+pub fn check_quote_drift(
+    quoted: u64,
+    current: u64,
+    max_drift_bps: u64,
+) -> Result<(), RaydiumError> {
+    if current >= quoted {
+        return Ok(());
+    }
+    let drift_bps =
+        ((quoted - current) as u128 * 10_000 / quoted as u128) as u64;
+    if drift_bps > max_drift_bps {
+        return Err(RaydiumError::QuoteDrift {
+            quoted,
+            current,
+            drift_bps,
+        });
+    }
+    Ok(())
+}
+
+/// check_liquidity verifies that the price impact of trading `amount_in`
+/// against `reserve_in` alone stays within `max_slippage_bps`, the impact of
+/// a constant product swap is amount_in / (reserve_in + amount_in) so the
+/// pool needs at least amount_in * (10_000 - bps) / bps of the input token
+pub fn check_liquidity(
+    reserve_in: u64,
+    amount_in: u64,
+    max_slippage_bps: u64,
+) -> Result<(), RaydiumError> {
+    let required = if max_slippage_bps == 0 {
+        u64::MAX
+    } else {
+        (amount_in as u128
+            * 10_000u128.saturating_sub(max_slippage_bps as u128)
+            / max_slippage_bps as u128)
+            .min(u64::MAX as u128) as u64
+    };
+    if reserve_in < required {
+        return Err(RaydiumError::InsufficientLiquidity {
+            required,
+            available: reserve_in,
+        });
+    }
+    Ok(())
+}
+
+/// liquidity_tolerance_bps is the slippage the liquidity check allows: the
+/// slack of an explicit min_out (the max input of a swap base out) against
+/// the zero-slippage `expected_out`, otherwise the slippage, a min_out that
+/// can't be met leaves no slack
+pub fn liquidity_tolerance_bps(
+    min_out: Option<u64>,
+    expected_out: u64,
+    swap_base_in: bool,
+    slippage: u64,
+) -> u64 {
+    let Some(min_out) = min_out else {
+        return slippage;
+    };
+    if expected_out == 0 {
+        return 0;
+    }
+    let slack = if swap_base_in {
+        expected_out.saturating_sub(min_out)
+    } else {
+        min_out.saturating_sub(expected_out)
+    };
+    (slack as u128 * 10_000 / expected_out as u128).min(10_000) as u64
+}
+
+/// AUTO_CONFIRM skips the interactive confirmation of the swaps globally,
+/// so that headless deployments never block on the prompt
+static AUTO_CONFIRM: AtomicBool = AtomicBool::new(false);
+
+pub fn set_auto_confirm(auto_confirm: bool) {
+    AUTO_CONFIRM.store(auto_confirm, Ordering::Relaxed);
+}
+
+/// auto_confirm is true if set with `set_auto_confirm` or through the
+/// LISTEN_AUTO_CONFIRM env var ("1" or "true")
+pub fn auto_confirm() -> bool {
+    AUTO_CONFIRM.load(Ordering::Relaxed)
+        || std::env::var("LISTEN_AUTO_CONFIRM")
+            .is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+pub struct Raydium {
+    /// decimals_overrides replace the on-chain decimals of a mint, escape
+    /// hatch for mints that disagree with the pool and for testing
+    decimals_overrides: HashMap<Pubkey, u8>,
+    /// wsol_session is the WSOL account kept open across swaps, see
+    /// `start_session` and `finish_session`
+    wsol_session: tokio::sync::Mutex<Option<WsolSession>>,
+    /// lag_check runs before every swap if set
+    lag_check: Option<LagCheck>,
+    pool_keys_cache:
+        std::sync::RwLock<HashMap<Pubkey, (AmmKeys, Arc<MarketPubkeys>)>>,
+    swap_config: SwapConfig,
+    /// max_drift_bps: abort a swap if the output re-quoted right before
+    /// signing is this much worse than the initial quote
+    max_drift_bps: Option<u64>,
+    /// confirm_quorum: confirm the swaps over multiple endpoints instead of
+    /// only the one the tx was sent through
+    confirm_quorum: Option<Arc<ConfirmQuorum>>,
+    /// session_stats: measure the fills of the swaps and record them
+    session_stats: Option<Arc<SessionStats>>,
+    /// approval_hook: decides on every swap in place of the prompt
+    approval_hook: Option<Arc<dyn ApprovalHook>>,
+    /// pool_overrides replace the global settings for the swaps through
+    /// the pool
+    pool_overrides: HashMap<Pubkey, PoolOverrides>,
+    /// blocklist: reject the swaps through pools tied to these accounts
+    blocklist: Option<Arc<Blocklist>>,
+    /// pool_deployers caches the deployer of the pools, None if it was not
+    /// found, it never changes
+    pool_deployers: std::sync::RwLock<HashMap<Pubkey, Option<Pubkey>>>,
+    /// pool_associates_cache caches the `pool_associates` for
+    /// POOL_ASSOCIATES_TTL, the authorities and the LP owner can change
+    pool_associates_cache: std::sync::RwLock<
+        HashMap<Pubkey, (std::time::Instant, Vec<(&'static str, Pubkey)>)>,
+    >,
+    /// position_tracker: record the confirmed swaps of the wallet
+    position_tracker: Option<Arc<PositionTracker>>,
+}
+
+/// POOL_ASSOCIATES_TTL: how long the associates of a pool are reused for
+/// the blocklist checks
+pub const POOL_ASSOCIATES_TTL: std::time::Duration =
+    std::time::Duration::from_secs(60);
+
+/// WarmReport is the outcome of warming up the caches, failures are the
+/// (pool or mint, error) pairs that could not be loaded
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WarmReport {
+    pub elapsed_ms: u128,
+    pub warmed: Vec<String>,
+    pub failures: Vec<(String, String)>,
+}
+
+/// WsolSession is a temp WSOL account that is created with the first swap
+/// of a session and reused (topped up) by the following ones instead of
+/// creating and closing an account for every swap, it has to be closed with
+/// `Raydium::finish_session`. Nothing closes it otherwise: a session that
+/// is dropped (with its `Raydium`) or replaced while the account is open
+/// only logs the account, which keeps its rent and WSOL until the owner
+/// closes it (e.g. `spl-token close --address <account>`)
+pub struct WsolSession {
+    owner: Pubkey,
+    seed: String,
+    account: Pubkey,
+    opened: bool,
+}
+
+impl WsolSession {
+    pub fn new(owner: &Pubkey) -> Self {
+        let seed = Keypair::new().pubkey().to_string()[0..32].to_string();
+        let account = generate_pub_key(owner, &seed);
+        WsolSession {
+            owner: *owner,
+            seed,
+            account,
+            opened: false,
+        }
+    }
+
+    pub fn account(&self) -> Pubkey {
+        self.account
+    }
+
+    /// landed marks the account opened once the swap creating it landed
+    /// without error, an expired or failed swap did not create it
+    fn landed(&mut self, confirmation: &crate::Confirmation) {
+        if confirmation.confirmed && confirmation.err.is_none() {
+            self.opened = true;
+        }
+    }
+
+    /// snapshot copies out what a swap needs of the session
+    fn snapshot(&self) -> SessionAccount {
+        SessionAccount {
+            owner: self.owner,
+            seed: self.seed.clone(),
+            account: self.account,
+            opened: self.opened,
+        }
+    }
+}
+
+/// SessionAccount is the WSOL account of a session as a swap sees it when
+/// it starts, a copy so that the session is not locked for the whole swap
+#[derive(Debug, Clone)]
+pub struct SessionAccount {
+    owner: Pubkey,
+    seed: String,
+    account: Pubkey,
+    opened: bool,
+}
+
+impl SessionAccount {
+    /// prepare adds the instructions to either create the session account or
+    /// top it up with `amount` lamports of WSOL, there is no close
+    async fn prepare(
+        &self,
+        swap: &mut Swap,
+        rpc_client: &RpcClient,
+        amount: u64,
+    ) -> Result<Pubkey, Box<dyn Error>> {
+        if self.opened {
+            if amount > 0 {
+                swap.pre_swap_instructions.push(
+                    solana_sdk::system_instruction::transfer(
+                        &self.owner,
+                        &self.account,
+                        amount,
+                    ),
+                );
+                swap.pre_swap_instructions.push(
+                    spl_token::instruction::sync_native(
+                        &spl_token::id(),
+                        &self.account,
+                    )?,
+                );
+            }
+        } else {
+            let rent = rpc_client
+                .get_minimum_balance_for_rent_exemption(
+                    spl_token::state::Account::LEN,
+                )
+                .await?;
+            swap.pre_swap_instructions.append(&mut create_init_token(
+                &self.account,
+                &self.seed,
+                &constants::SOLANA_PROGRAM_ID,
+                &self.owner,
+                &self.owner,
+                rent + amount,
+            ));
+        }
+        Ok(self.account)
+    }
+}
+
+impl Drop for WsolSession {
+    fn drop(&mut self) {
+        if self.opened {
+            warn!(
+                "WSOL session account {} was not closed, close it manually",
+                self.account
+            );
+        }
+    }
+}
+
+/// SwapResult is the outcome of a sent swap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapResult {
+    pub signature: String,
+    /// confirmed is false if the blockhash expired before the tx landed
+    pub confirmed: bool,
+    pub last_valid_block_height: u64,
+    /// remaining_blocks: the validity window left when the wait ended
+    pub remaining_blocks: u64,
+    /// quoted_out and amount_out (what the wallet received) are only
+    /// measured with session stats on, fee_lamports is the tx fee
+    #[serde(default)]
+    pub quoted_out: Option<u64>,
+    #[serde(default)]
+    pub amount_out: Option<u64>,
+    #[serde(default)]
+    pub fee_lamports: Option<u64>,
+    /// landing_ms: from the send to the end of the confirmation wait
+    #[serde(default)]
+    pub landing_ms: u128,
+}
+
+impl SwapResult {
+    /// realized_slippage_bps is how much less than quoted was received, in
+    /// bps of the quote, negative for a better fill
+    pub fn realized_slippage_bps(&self) -> Option<f64> {
+        match (self.quoted_out, self.amount_out) {
+            (Some(quoted), Some(out)) if quoted > 0 => {
+                Some((quoted as f64 - out as f64) / quoted as f64 * 10_000.)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// SwapEvent is a step in the lifecycle of a swap, the swap itself never
+/// emits Failed, the error is its result, which callers relaying the events
+/// (e.g. `engine::SwapEngine`) turn into Failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SwapEvent {
+    Building {
+        amm_pool: String,
+    },
+    Submitted {
+        signature: String,
+    },
+    Confirmed {
+        signature: String,
+        remaining_blocks: u64,
+    },
+    /// Expired: the blockhash expired before the tx landed
+    Expired {
+        signature: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// TwapFailurePolicy decides what happens to the remaining slices once a
+/// slice fails
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum TwapFailurePolicy {
+    /// Retry: re-quote and resend the failed slice up to attempts times
+    Retry { attempts: u32 },
+    /// AbortRemaining: stop after the first failed slice
+    AbortRemaining,
+}
+
+#[derive(Debug, Clone)]
+pub struct TwapConfig {
+    pub on_failure: TwapFailurePolicy,
+    /// retry_delay: wait between the attempts of a single slice
+    pub retry_delay: std::time::Duration,
+}
+
+impl Default for TwapConfig {
+    fn default() -> Self {
+        Self {
+            on_failure: TwapFailurePolicy::Retry { attempts: 2 },
+            retry_delay: std::time::Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwapSlice {
+    pub amount_in: u64,
+    /// quoted_out: quote against the reserves right before sending
+    pub quoted_out: u64,
+    /// filled_out: what the wallet received, measured off the landed tx,
+    /// None if the slice failed or the tx could not be read
+    #[serde(default)]
+    pub filled_out: Option<u64>,
+    pub signature: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TwapResult {
+    pub slices: Vec<TwapSlice>,
+    /// total_in: sum over the filled slices, total_out: sum of what they
+    /// filled, as far as it could be measured
+    pub total_in: u64,
+    pub total_out: u64,
+    /// avg_price: input paid per unit of output received, raw amounts,
+    /// over the measured slices only
+    pub avg_price: f64,
+}
+
+impl TwapResult {
+    fn from_slices(slices: Vec<TwapSlice>) -> Self {
+        let total_in = slices
+            .iter()
+            .filter(|slice| slice.signature.is_some())
+            .map(|slice| slice.amount_in)
+            .sum();
+        let (measured_in, total_out) = slices
+            .iter()
+            .filter(|slice| slice.signature.is_some())
+            .filter_map(|slice| Some((slice.amount_in, slice.filled_out?)))
+            .fold((0u64, 0u64), |(total_in, total_out), (amount_in, out)| {
+                (total_in + amount_in, total_out + out)
+            });
+        let avg_price = if total_out == 0 {
+            0.
+        } else {
+            measured_in as f64 / total_out as f64
+        };
+        Self {
+            slices,
+            total_in,
+            total_out,
+            avg_price,
+        }
+    }
+}
+
+/// Liquidation is one sell of a `liquidate_all` batch, amount of mint sold
+/// to SOL through amm_pool
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Liquidation {
+    pub amm_pool: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+/// LiquidationConfig: shared_wsol pays every sell of the batch out to one
+/// WSOL account, created by the first sell and closed once at the end,
+/// instead of a temp WSOL account created and closed by each sell
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LiquidationConfig {
+    pub shared_wsol: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidatedSell {
+    pub mint: String,
+    pub amount: u64,
+    pub signature: Option<String>,
+    pub error: Option<String>,
+}
+
+/// LiquidationReport has the outcome of every sell, wsol_account and
+/// wsol_close are set with a shared WSOL account, wsol_close_error if it
+/// could not be closed (the account is then left for manual cleanup)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LiquidationReport {
+    pub sells: Vec<LiquidatedSell>,
+    pub wsol_account: Option<String>,
+    pub wsol_close: Option<String>,
+    pub wsol_close_error: Option<String>,
+}
+
+impl LiquidationReport {
+    pub fn failed(&self) -> usize {
+        self.sells
+            .iter()
+            .filter(|sell| sell.signature.is_none())
+            .count()
+    }
+}
+
+/// attempts at closing the shared WSOL account of a liquidation
+const WSOL_CLOSE_ATTEMPTS: u32 = 3;
+
+/// split_amount splits total into slices parts, the remainder goes to the
+/// last slice so that the parts always sum up to total
+pub fn split_amount(total: u64, slices: u32) -> Vec<u64> {
+    if slices == 0 {
+        return vec![];
+    }
+    let slices = slices as u64;
+    let part = total / slices;
+    let mut parts = vec![part; slices as usize];
+    if let Some(last) = parts.last_mut() {
+        *last += total % slices;
+    }
+    parts
+}
 
 pub struct SwapArgs {
     pub amm_pool: Pubkey,
@@ -218,6 +1008,8 @@ pub struct SwapArgs {
     pub confirmed: bool,
     /// no_sanity: skip sanity checks
     pub no_sanity: bool,
+    /// min_out: explicit minimum output, overrides the slippage
+    pub min_out: Option<u64>,
 }
 
 pub struct Swap {
@@ -229,7 +1021,7 @@ pub struct SwapContext {
     pub amm_program: Pubkey,
     pub amm_pool: Pubkey,
     pub amm_keys: amm::AmmKeys,
-    pub market_keys: amm::openbook::MarketPubkeys,
+    pub market_keys: Arc<amm::openbook::MarketPubkeys>,
     pub swap: Swap,
     pub user_source: Pubkey,
     pub user_destination: Pubkey,
@@ -237,7 +1029,182 @@ pub struct SwapContext {
     pub input_token_mint: Pubkey,
     pub output_token_mint: Pubkey,
     pub slippage: u64,
+    pub min_out: Option<u64>,
     pub swap_base_in: bool,
+    pub swap_config: SwapConfig,
+}
+
+pub const DEFAULT_CU_PRICE: u64 = 0;
+pub const DEFAULT_CU_LIMIT: u32 = 300_000;
+
+/// SwapConfig holds the fee settings of the swaps, every setting resolves
+/// as: the value set in the config, otherwise its env var, otherwise the
+/// default
+///   cu_price (micro-lamports per CU): LISTEN_CU_PRICE, DEFAULT_CU_PRICE
+///   cu_limit: LISTEN_CU_LIMIT, DEFAULT_CU_LIMIT
+///   skip_existing_ata: LISTEN_SKIP_EXISTING_ATA, false
+///   min_liquidity (lamports of SOL in the pool, 0 is no minimum):
+///     LISTEN_MIN_LIQUIDITY, 0
+///   slippage_rounding (of the min output, see apply_slippage):
+///     LISTEN_SLIPPAGE_ROUNDING ("floor" or "ceil"), floor
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SwapConfig {
+    pub cu_price: Option<u64>,
+    pub cu_limit: Option<u32>,
+    /// skip_existing_ata: look the ATA up with get_account and leave out
+    /// the (idempotent) create instruction if it exists, one read for the
+    /// compute of the create
+    pub skip_existing_ata: Option<bool>,
+    pub min_liquidity: Option<u64>,
+    pub slippage_rounding: Option<SlippageRounding>,
+}
+
+impl SwapConfig {
+    pub fn cu_price(&self) -> u64 {
+        resolve_setting(self.cu_price, "LISTEN_CU_PRICE", DEFAULT_CU_PRICE)
+    }
+
+    pub fn cu_limit(&self) -> u32 {
+        resolve_setting(self.cu_limit, "LISTEN_CU_LIMIT", DEFAULT_CU_LIMIT)
+    }
+
+    pub fn skip_existing_ata(&self) -> bool {
+        resolve_setting(
+            self.skip_existing_ata,
+            "LISTEN_SKIP_EXISTING_ATA",
+            false,
+        )
+    }
+
+    pub fn min_liquidity(&self) -> u64 {
+        resolve_setting(self.min_liquidity, "LISTEN_MIN_LIQUIDITY", 0)
+    }
+
+    pub fn slippage_rounding(&self) -> SlippageRounding {
+        resolve_setting(
+            self.slippage_rounding,
+            "LISTEN_SLIPPAGE_ROUNDING",
+            SlippageRounding::default(),
+        )
+    }
+}
+
+/// SlippageRounding is the rounding of the min output of a swap base in,
+/// floor lets the swap through at the exact slippage (the min output is at
+/// most 1 raw unit more permissive), ceil never lets it through below it
+/// (at most 1 raw unit stricter, more likely to fail)
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SlippageRounding {
+    #[default]
+    Floor,
+    Ceil,
+}
+
+impl FromStr for SlippageRounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "floor" => Ok(SlippageRounding::Floor),
+            "ceil" => Ok(SlippageRounding::Ceil),
+            _ => Err(format!("invalid slippage rounding {:?}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for SlippageRounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SlippageRounding::Floor => write!(f, "floor"),
+            SlippageRounding::Ceil => write!(f, "ceil"),
+        }
+    }
+}
+
+/// apply_slippage is the min output of a swap base in expected to return
+/// expected_out: expected_out * (10_000 - slippage_bps) / 10_000 in u128,
+/// rounded as given; a slippage of 10_000bps or more is a min output of 0
+pub fn apply_slippage(
+    expected_out: u64,
+    slippage_bps: u64,
+    rounding: SlippageRounding,
+) -> u64 {
+    let numerator =
+        expected_out as u128 * 10_000u128.saturating_sub(slippage_bps as u128);
+    match rounding {
+        SlippageRounding::Floor => (numerator / 10_000) as u64,
+        SlippageRounding::Ceil => numerator.div_ceil(10_000) as u64,
+    }
+}
+
+/// PoolOverrides are the settings of a single pool, each one set replaces
+/// the global one for the swaps through the pool: slippage_bps the slippage
+/// of the swap args (an explicit min_out still wins), cu_price and
+/// min_liquidity the ones of the `SwapConfig`
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub struct PoolOverrides {
+    pub slippage_bps: Option<u64>,
+    pub cu_price: Option<u64>,
+    pub min_liquidity: Option<u64>,
+}
+
+impl PoolOverrides {
+    pub fn slippage_bps(&self, default: u64) -> u64 {
+        self.slippage_bps.unwrap_or(default)
+    }
+
+    /// swap_config is the global config with the overrides applied
+    pub fn swap_config(&self, global: &SwapConfig) -> SwapConfig {
+        SwapConfig {
+            cu_price: self.cu_price.or(global.cu_price),
+            min_liquidity: self.min_liquidity.or(global.min_liquidity),
+            ..global.clone()
+        }
+    }
+}
+
+/// check_min_liquidity fails if the SOL side of the pool holds less than
+/// min_liquidity lamports, 0 is no minimum
+pub fn check_min_liquidity(
+    sol_reserve: u64,
+    min_liquidity: u64,
+) -> Result<(), Box<dyn Error>> {
+    if sol_reserve < min_liquidity {
+        return Err(format!(
+            "pool has {} lamports of SOL, below the min liquidity of {}",
+            sol_reserve, min_liquidity
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn resolve_setting<T: FromStr + std::fmt::Display>(
+    value: Option<T>,
+    var: &str,
+    default: T,
+) -> T {
+    if let Some(value) = value {
+        return value;
+    }
+    match std::env::var(var) {
+        Ok(raw) => match raw.parse::<T>() {
+            Ok(value) => {
+                info!("using {}={} from env", var, value);
+                value
+            }
+            Err(_) => {
+                warn!("ignoring invalid {}={}, using {}", var, raw, default);
+                default
+            }
+        },
+        Err(_) => default,
+    }
 }
 
 pub async fn get_calc_result(
@@ -304,30 +1271,351 @@ pub fn get_burn_pct(
     Ok(burn_pct)
 }
 
-pub fn calc_result_to_financials(
-    coin_mint_is_sol: bool,
-    result: amm::CalculateResult,
-    owner_balance: u64,
-) -> f64 {
-    let sol_price = 145.;
-    if coin_mint_is_sol {
-        let sol_amount = result.pool_coin_vault_amount as f64 / 1e9;
-        let usd_amount = sol_amount * sol_price;
-        let price = result.pool_coin_vault_amount as f64
-            / result.pool_pc_vault_amount as f64;
-        let owner_balance_sol = owner_balance as f64 * price / 1e9;
-        debug!(
-            "{}",
-            serde_json::to_string_pretty(&json!(
-                {
-                    "timestamp": chrono::Utc::now().to_rfc3339(),
-                    "sol_amount": sol_amount,
-                    "usd_amount": usd_amount,
-                    "price": price,
-                    "owner_balance": owner_balance,
-                    "owner_balance_sol": owner_balance_sol,
-                }
-            ))
+/// Reserves is the pc (quote) / coin (base) view of a pool used by the
+/// sizing math, fee is the swap fee as a fraction (0.0025 for 25bps)
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Reserves {
+    pub quote: u64,
+    pub base: u64,
+    pub fee: f64,
+}
+
+impl Reserves {
+    pub fn from_calc_result(result: &amm::CalculateResult) -> Self {
+        Reserves {
+            quote: result.pool_pc_vault_amount,
+            base: result.pool_coin_vault_amount,
+            fee: if result.swap_fee_denominator == 0 {
+                0.
+            } else {
+                result.swap_fee_numerator as f64
+                    / result.swap_fee_denominator as f64
+            },
+        }
+    }
+
+    /// flipped swaps the sides, for pools listed the other way around
+    pub fn flipped(&self) -> Self {
+        Reserves {
+            quote: self.base,
+            base: self.quote,
+            fee: self.fee,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArbSize {
+    pub buy_pool: String,
+    pub sell_pool: String,
+    /// input_mint is the quote token of the pair, amount_in is denominated in it
+    pub input_mint: String,
+    pub amount_in: u64,
+    pub expected_out: u64,
+    pub expected_gross_profit: u64,
+}
+
+/// optimal_arb_amount returns the quote amount to buy the base token with in
+/// the `buy` pool and sell straight into the `sell` pool, together with the
+/// quote amount received at the end; None if there is no profitable size
+///
+/// composing two constant product pools (with fees g = 1 - fee) gives
+/// out = a * dx / (b + c * dx), where
+///   a = g1 * g2 * base1 * quote2
+///   b = quote1 * base2
+///   c = g1 * (base2 + g2 * base1)
+/// profit = out - dx is maximized at d(out)/d(dx) = 1, so
+///   dx = (sqrt(a * b) - b) / c, profitable only if a > b
+pub fn optimal_arb_amount(
+    buy: &Reserves,
+    sell: &Reserves,
+) -> Option<(u64, u64)> {
+    if buy.quote == 0 || buy.base == 0 || sell.quote == 0 || sell.base == 0 {
+        return None;
+    }
+    let g1 = 1. - buy.fee;
+    let g2 = 1. - sell.fee;
+    let a = g1 * g2 * buy.base as f64 * sell.quote as f64;
+    let b = buy.quote as f64 * sell.base as f64;
+    let c = g1 * (sell.base as f64 + g2 * buy.base as f64);
+    if a <= b {
+        return None;
+    }
+    let amount_in = ((a * b).sqrt() - b) / c;
+    let amount_out = a * amount_in / (b + c * amount_in);
+    if amount_in < 1. || amount_out <= amount_in {
+        return None;
+    }
+    Some((amount_in as u64, amount_out as u64))
+}
+
+/// SlippageParams are the inputs of the slippage heuristic, sol_reserve is
+/// the SOL side of the pool, amount_in the size of the trade in the input
+/// token against reserve_in, priority_fee is in micro-lamports per CU
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SlippageParams {
+    pub reserve_in: u64,
+    pub amount_in: u64,
+    pub sol_reserve: u64,
+    pub priority_fee: u64,
+    pub pool_age_secs: u64,
+    pub fills_per_minute: f64,
+}
+
+pub const MIN_RECOMMENDED_SLIPPAGE_BPS: u64 = 50;
+pub const MAX_RECOMMENDED_SLIPPAGE_BPS: u64 = 3_000;
+
+/// recommend_slippage_bps is a heuristic for the slippage setting, on top of
+/// the price impact of the trade itself it adds a buffer for the price
+/// moving before the tx lands, the buffer grows with the pool activity, a
+/// lower priority fee (more slots to land), thinner and newer pools
+pub fn recommend_slippage_bps(params: &SlippageParams) -> u64 {
+    let impact_bps = if params.reserve_in == 0 {
+        MAX_RECOMMENDED_SLIPPAGE_BPS as u128
+    } else {
+        params.amount_in as u128 * 10_000
+            / (params.reserve_in as u128 + params.amount_in as u128)
+    } as u64;
+
+    let landing_multiplier = match params.priority_fee {
+        0..=9_999 => 2.,
+        10_000..=99_999 => 1.5,
+        _ => 1.,
+    };
+    let drift_bps =
+        (params.fills_per_minute * 5.).min(500.) * landing_multiplier;
+
+    let thin_bps = match params.sol_reserve {
+        x if x < 50 * 10u64.pow(9) => 200,
+        x if x < 500 * 10u64.pow(9) => 75,
+        _ => 0,
+    };
+    let age_bps = match params.pool_age_secs {
+        0..=3_599 => 300,
+        3_600..=86_399 => 100,
+        _ => 0,
+    };
+
+    (MIN_RECOMMENDED_SLIPPAGE_BPS
+        + impact_bps
+        + drift_bps as u64
+        + thin_bps
+        + age_bps)
+        .clamp(MIN_RECOMMENDED_SLIPPAGE_BPS, MAX_RECOMMENDED_SLIPPAGE_BPS)
+}
+
+/// RoundTrip is the outcome of simulating a buy and a sell of the token in
+/// one tx, the sell is of the quoted buy output minus
+/// ROUND_TRIP_HEADROOM_BPS, so a buy tax above that fails the sell leg;
+/// loss is what the round trip cost in lamports (pool fees, price impact
+/// and any taxes, tx fees excluded), unsold tokens valued at the buy price
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundTrip {
+    pub buy_ok: bool,
+    pub sell_ok: bool,
+    pub amount_in: u64,
+    pub quoted_tokens: u64,
+    pub tokens_sold: u64,
+    pub sol_back: Option<u64>,
+    pub loss_lamports: Option<i128>,
+    pub loss_pct: Option<f64>,
+    pub error: Option<String>,
+    pub logs: Vec<String>,
+}
+
+pub const ROUND_TRIP_HEADROOM_BPS: u64 = 2_000;
+
+/// round_trip_loss is the lamports lost of amount_in and the percentage,
+/// leftover tokens are valued at amount_in / quoted_tokens each
+pub fn round_trip_loss(
+    amount_in: u64,
+    sol_back: u64,
+    leftover_tokens: u64,
+    quoted_tokens: u64,
+) -> (i128, f64) {
+    let leftover_value = if quoted_tokens > 0 {
+        leftover_tokens as i128 * amount_in as i128 / quoted_tokens as i128
+    } else {
+        0
+    };
+    let loss = amount_in as i128 - sol_back as i128 - leftover_value;
+    let pct = if amount_in > 0 {
+        loss as f64 / amount_in as f64 * 100.
+    } else {
+        0.
+    };
+    (loss, pct)
+}
+
+fn token_amount(account: Option<&UiAccount>) -> Option<u64> {
+    let data = account?.data.decode()?;
+    spl_token::state::Account::unpack(&data)
+        .ok()
+        .map(|account| account.amount)
+}
+
+/// PoolSnapshot is the state of a pool at a point in time, the swap_*
+/// amounts are the cumulative counters of the pool since its creation
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PoolSnapshot {
+    pub amm_pool: String,
+    pub timestamp: i64,
+    pub coin_reserve: u64,
+    pub pc_reserve: u64,
+    /// price is the pc amount per coin, raw units
+    pub price: f64,
+    pub swap_coin_in_amount: u128,
+    pub swap_coin_out_amount: u128,
+    pub swap_pc_in_amount: u128,
+    pub swap_pc_out_amount: u128,
+}
+
+/// PoolDiff is the change between two snapshots, volumes are the amounts
+/// swapped in both directions in between
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PoolDiff {
+    pub elapsed_secs: i64,
+    pub coin_reserve_delta: i128,
+    pub pc_reserve_delta: i128,
+    pub price_change_pct: f64,
+    pub volume_coin: u128,
+    pub volume_pc: u128,
+}
+
+impl PoolSnapshot {
+    /// diff returns the change from `a` to `b`, both of the same pool
+    pub fn diff(a: &PoolSnapshot, b: &PoolSnapshot) -> PoolDiff {
+        let price_change_pct = if a.price > 0. {
+            (b.price - a.price) / a.price * 100.
+        } else {
+            0.
+        };
+        PoolDiff {
+            elapsed_secs: b.timestamp - a.timestamp,
+            coin_reserve_delta: b.coin_reserve as i128
+                - a.coin_reserve as i128,
+            pc_reserve_delta: b.pc_reserve as i128 - a.pc_reserve as i128,
+            price_change_pct,
+            volume_coin: b
+                .swap_coin_in_amount
+                .saturating_sub(a.swap_coin_in_amount)
+                + b.swap_coin_out_amount
+                    .saturating_sub(a.swap_coin_out_amount),
+            volume_pc: b.swap_pc_in_amount.saturating_sub(a.swap_pc_in_amount)
+                + b.swap_pc_out_amount.saturating_sub(a.swap_pc_out_amount),
+        }
+    }
+}
+
+/// PoolInvariant is the constant product of a pool, k = coin * pc reserves,
+/// alongside the outstanding LP; sqrt(k) per LP only grows with the fees
+/// kept by the pool, adds and removes of liquidity leave it as is
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct PoolInvariant {
+    pub timestamp: i64,
+    pub coin_reserve: u64,
+    pub pc_reserve: u64,
+    pub k: u128,
+    pub lp_supply: u64,
+}
+
+impl PoolInvariant {
+    pub fn new(
+        timestamp: i64,
+        coin_reserve: u64,
+        pc_reserve: u64,
+        lp_supply: u64,
+    ) -> Self {
+        PoolInvariant {
+            timestamp,
+            coin_reserve,
+            pc_reserve,
+            k: coin_reserve as u128 * pc_reserve as u128,
+            lp_supply,
+        }
+    }
+
+    /// root_k_per_lp is sqrt(k) per LP token, 0 without LP
+    pub fn root_k_per_lp(&self) -> f64 {
+        if self.lp_supply == 0 {
+            return 0.;
+        }
+        (self.k as f64).sqrt() / self.lp_supply as f64
+    }
+}
+
+/// InvariantChange is how k moved between two invariants of a pool, past
+/// `tolerance_bps` a drop of sqrt(k) per LP is an Anomaly whether the LP
+/// moved or not, liquidity taken out without burning the matching LP
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InvariantChange {
+    Unchanged,
+    FeeAccrual { growth_bps: f64 },
+    LiquidityAdded { lp_delta: u64 },
+    LiquidityRemoved { lp_delta: u64 },
+    Anomaly { drop_bps: f64, lp_delta: i128 },
+}
+
+pub fn classify_invariant_change(
+    a: &PoolInvariant,
+    b: &PoolInvariant,
+    tolerance_bps: f64,
+) -> InvariantChange {
+    let lp_delta = b.lp_supply as i128 - a.lp_supply as i128;
+    let (before, after) = (a.root_k_per_lp(), b.root_k_per_lp());
+    let change_bps = if before > 0. {
+        (after - before) / before * 10_000.
+    } else {
+        0.
+    };
+    if change_bps < -tolerance_bps {
+        return InvariantChange::Anomaly {
+            drop_bps: -change_bps,
+            lp_delta,
+        };
+    }
+    match lp_delta {
+        0 if b.k == a.k => InvariantChange::Unchanged,
+        0 => InvariantChange::FeeAccrual {
+            growth_bps: change_bps.max(0.),
+        },
+        delta if delta > 0 => InvariantChange::LiquidityAdded {
+            lp_delta: delta as u64,
+        },
+        delta => InvariantChange::LiquidityRemoved {
+            lp_delta: delta.unsigned_abs() as u64,
+        },
+    }
+}
+
+/// DEFAULT_INVARIANT_TOLERANCE_BPS absorbs the rounding of adds/removes
+pub const DEFAULT_INVARIANT_TOLERANCE_BPS: f64 = 1.;
+
+pub fn calc_result_to_financials(
+    coin_mint_is_sol: bool,
+    result: amm::CalculateResult,
+    owner_balance: u64,
+) -> f64 {
+    let sol_price = 145.;
+    if coin_mint_is_sol {
+        let sol_amount = result.pool_coin_vault_amount as f64 / 1e9;
+        let usd_amount = sol_amount * sol_price;
+        let price = result.pool_coin_vault_amount as f64
+            / result.pool_pc_vault_amount as f64;
+        let owner_balance_sol = owner_balance as f64 * price / 1e9;
+        debug!(
+            "{}",
+            serde_json::to_string_pretty(&json!(
+                {
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "sol_amount": sol_amount,
+                    "usd_amount": usd_amount,
+                    "price": price,
+                    "owner_balance": owner_balance,
+                    "owner_balance_sol": owner_balance_sol,
+                }
+            ))
             .expect("to string pretty")
         );
         sol_amount
@@ -364,9 +1652,122 @@ pub async fn make_swap_context(
     slippage: u64,
     amount: u64,
 ) -> Result<SwapContext, Box<dyn Error>> {
+    make_swap_context_in_session(
+        rpc_client,
+        amm_pool,
+        input_token_mint,
+        output_token_mint,
+        wallet,
+        slippage,
+        amount,
+        None,
+        load_pool_keys(rpc_client, &amm_pool).await?,
+        SwapConfig::default(),
+    )
+    .await
+}
+
+/// LotSizes are the OpenBook market's lot sizes, orders on the book are in
+/// whole lots, base lot in native coin units and quote lot in native pc
+/// units. They are loaded on demand (`market_lot_sizes`,
+/// `Raydium::lot_sizes`), not with the pool keys: the AMM swaps and quotes
+/// run on the vault amounts and never round to lots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LotSizes {
+    pub base_lot_size: u64,
+    pub quote_lot_size: u64,
+}
+
+/// offsets of coin_lot_size and pc_lot_size in the market state (v2),
+/// after the 5 byte "serum" head padding
+const MARKET_BASE_LOT_SIZE_OFFSET: usize = 349;
+const MARKET_QUOTE_LOT_SIZE_OFFSET: usize = 357;
+
+impl LotSizes {
+    /// parse reads the lot sizes from the raw market account
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let read = |offset: usize| -> Result<u64, Box<dyn Error>> {
+            let bytes = data
+                .get(offset..offset + 8)
+                .ok_or("market account too short")?;
+            Ok(u64::from_le_bytes(bytes.try_into()?))
+        };
+        let lot_sizes = LotSizes {
+            base_lot_size: read(MARKET_BASE_LOT_SIZE_OFFSET)?,
+            quote_lot_size: read(MARKET_QUOTE_LOT_SIZE_OFFSET)?,
+        };
+        if lot_sizes.base_lot_size == 0 || lot_sizes.quote_lot_size == 0 {
+            return Err("market has a zero lot size".into());
+        }
+        Ok(lot_sizes)
+    }
+
+    pub fn base_to_lots(&self, amount: u64) -> u64 {
+        amount / self.base_lot_size
+    }
+
+    pub fn base_lots_to_native(&self, lots: u64) -> u64 {
+        lots.saturating_mul(self.base_lot_size)
+    }
+
+    pub fn quote_to_lots(&self, amount: u64) -> u64 {
+        amount / self.quote_lot_size
+    }
+
+    pub fn quote_lots_to_native(&self, lots: u64) -> u64 {
+        lots.saturating_mul(self.quote_lot_size)
+    }
+
+    /// round_base rounds a native coin amount down to whole base lots
+    pub fn round_base(&self, amount: u64) -> u64 {
+        self.base_lots_to_native(self.base_to_lots(amount))
+    }
+
+    /// round_quote rounds a native pc amount down to whole quote lots
+    pub fn round_quote(&self, amount: u64) -> u64 {
+        self.quote_lots_to_native(self.quote_to_lots(amount))
+    }
+}
+
+static LOT_SIZES_CACHE: std::sync::OnceLock<
+    std::sync::RwLock<HashMap<Pubkey, LotSizes>>,
+> = std::sync::OnceLock::new();
+
+fn lot_sizes_cache() -> &'static std::sync::RwLock<HashMap<Pubkey, LotSizes>> {
+    LOT_SIZES_CACHE.get_or_init(|| std::sync::RwLock::new(HashMap::new()))
+}
+
+/// cached_lot_sizes returns the lot sizes of the market if already loaded
+pub fn cached_lot_sizes(market: &Pubkey) -> Option<LotSizes> {
+    lot_sizes_cache().read().expect("read").get(market).copied()
+}
+
+/// market_lot_sizes returns the lot sizes of the market, fetched once per
+/// process, the lot sizes of a market never change
+pub async fn market_lot_sizes(
+    rpc_client: &RpcClient,
+    market: &Pubkey,
+) -> Result<LotSizes, Box<dyn Error>> {
+    if let Some(lot_sizes) = cached_lot_sizes(market) {
+        return Ok(lot_sizes);
+    }
+    let lot_sizes =
+        LotSizes::parse(&rpc_client.get_account_data(market).await?)?;
+    lot_sizes_cache()
+        .write()
+        .expect("write")
+        .insert(*market, lot_sizes);
+    Ok(lot_sizes)
+}
+
+/// load_pool_keys loads the amm keys and the market keys of the pool
+pub async fn load_pool_keys(
+    rpc_client: &RpcClient,
+    amm_pool: &Pubkey,
+) -> Result<(AmmKeys, Arc<MarketPubkeys>), Box<dyn Error>> {
     let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY;
     // load amm keys
-    let amm_keys = load_amm_keys(rpc_client, &amm_program, &amm_pool).await?;
+    let amm_keys = load_amm_keys(rpc_client, &amm_program, amm_pool).await?;
     // load market keys
     let market_keys = amm::openbook::get_keys_for_market(
         rpc_client,
@@ -374,28 +1775,69 @@ pub async fn make_swap_context(
         &amm_keys.market,
     )
     .await?;
+    Ok((amm_keys, Arc::new(market_keys)))
+}
+
+/// make_swap_context_in_session is make_swap_context that uses the WSOL
+/// account of the session (if any) instead of a temp WSOL account
+#[allow(clippy::too_many_arguments)]
+pub async fn make_swap_context_in_session(
+    rpc_client: &RpcClient,
+    amm_pool: Pubkey,
+    input_token_mint: Pubkey,
+    output_token_mint: Pubkey,
+    wallet: &Keypair,
+    slippage: u64,
+    amount: u64,
+    wsol_session: Option<&SessionAccount>,
+    pool_keys: (AmmKeys, Arc<MarketPubkeys>),
+    swap_config: SwapConfig,
+) -> Result<SwapContext, Box<dyn Error>> {
+    let skip_existing_ata = swap_config.skip_existing_ata();
+    let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY;
+    let (amm_keys, market_keys) = pool_keys;
     let mut swap = Swap {
         pre_swap_instructions: vec![],
         post_swap_instructions: vec![],
     };
-    let user_source = handle_token_account(
-        &mut swap,
-        rpc_client,
-        &input_token_mint,
-        amount,
-        &wallet.pubkey(),
-        &wallet.pubkey(),
-    )
-    .await?;
-    let user_destination = handle_token_account(
-        &mut swap,
-        rpc_client,
-        &output_token_mint,
-        0,
-        &wallet.pubkey(),
-        &wallet.pubkey(),
-    )
-    .await?;
+    let user_source = match wsol_session {
+        Some(session) if input_token_mint == constants::SOLANA_PROGRAM_ID => {
+            session.prepare(&mut swap, rpc_client, amount).await?
+        }
+        _ => {
+            handle_token_account(
+                &mut swap,
+                rpc_client,
+                &input_token_mint,
+                amount,
+                &wallet.pubkey(),
+                &wallet.pubkey(),
+                &crate::ata::token_program_of(rpc_client, &input_token_mint)
+                    .await?,
+                skip_existing_ata,
+            )
+            .await?
+        }
+    };
+    let user_destination = match wsol_session {
+        Some(session) if output_token_mint == constants::SOLANA_PROGRAM_ID => {
+            session.prepare(&mut swap, rpc_client, 0).await?
+        }
+        _ => {
+            handle_token_account(
+                &mut swap,
+                rpc_client,
+                &output_token_mint,
+                0,
+                &wallet.pubkey(),
+                &wallet.pubkey(),
+                &crate::ata::token_program_of(rpc_client, &output_token_mint)
+                    .await?,
+                skip_existing_ata,
+            )
+            .await?
+        }
+    };
     Ok(SwapContext {
         amm_program,
         amm_keys,
@@ -408,10 +1850,301 @@ pub async fn make_swap_context(
         input_token_mint,
         output_token_mint,
         slippage,
+        min_out: None,
         swap_base_in: true,
+        swap_config,
+    })
+}
+
+/// ui_to_raw converts a ui amount to raw, rounded to the nearest unit, a
+/// negative amount is 0
+pub fn ui_to_raw(amount_ui: f64, decimals: u8) -> u64 {
+    (amount_ui * 10f64.powi(decimals as i32)).round() as u64
+}
+
+/// lp_token_price_sol values one LP token as its share of both sides of the
+/// pool, in a constant product pool both sides are worth the same so that
+/// is twice its share of the SOL reserve
+pub fn lp_token_price_sol(
+    sol_reserve: u64,
+    lp_supply: u64,
+    lp_decimals: u8,
+) -> Option<f64> {
+    if lp_supply == 0 {
+        return None;
+    }
+    let lp_supply = lp_supply as f64 / 10f64.powi(lp_decimals as i32);
+    let sol_reserve = sol_reserve as f64 / 1e9;
+    Some(2. * sol_reserve / lp_supply)
+}
+
+/// min_out this far below the expected output (in bps) is considered loose
+const MIN_OUT_LOOSE_BPS: u128 = 2_000;
+/// min_out this close to the expected output (in bps) is considered tight
+const MIN_OUT_TIGHT_BPS: u128 = 10;
+
+/// tags of the AMM v4 swap instructions, both are followed by two u64s
+const SWAP_BASE_IN_TAG: u8 = 9;
+const SWAP_BASE_OUT_TAG: u8 = 11;
+const SWAP_IX_DATA_LEN: usize = 17;
+
+/// MIN_OUT_ROUNDING is how far (raw units) the encoded min_out may be off
+/// the intended one
+pub const MIN_OUT_ROUNDING: u64 = 1;
+
+/// SwapIxData is the decoded data of an AMM v4 swap instruction, for a swap
+/// base in amount is the input and other_amount_threshold the min output,
+/// for a swap base out they are the output and the max input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SwapIxData {
+    pub swap_base_in: bool,
+    pub amount: u64,
+    pub other_amount_threshold: u64,
+}
+
+pub fn decode_swap_ix_data(data: &[u8]) -> Option<SwapIxData> {
+    if data.len() != SWAP_IX_DATA_LEN {
+        return None;
+    }
+    let swap_base_in = match data[0] {
+        SWAP_BASE_IN_TAG => true,
+        SWAP_BASE_OUT_TAG => false,
+        _ => return None,
+    };
+    Some(SwapIxData {
+        swap_base_in,
+        amount: u64::from_le_bytes(data[1..9].try_into().ok()?),
+        other_amount_threshold: u64::from_le_bytes(
+            data[9..17].try_into().ok()?,
+        ),
     })
 }
 
+/// validate_swap_ixs decodes the single AMM v4 swap instruction of ixs and
+/// checks that it is a swap base in of exactly amount with an explicit
+/// min_out (if any) encoded within MIN_OUT_ROUNDING, run right before
+/// signing as a guard against encoding the wrong numbers
+pub fn validate_swap_ixs(
+    ixs: &[Instruction],
+    amount: u64,
+    min_out: Option<u64>,
+) -> Result<SwapIxData, RaydiumError> {
+    let swaps = ixs
+        .iter()
+        .filter(|ix| {
+            ix.program_id == constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY
+        })
+        .collect::<Vec<&Instruction>>();
+    let [swap_ix] = swaps.as_slice() else {
+        return Err(RaydiumError::InstructionMismatch {
+            field: "swap instructions",
+            intended: 1,
+            encoded: swaps.len() as u64,
+        });
+    };
+    let data = decode_swap_ix_data(&swap_ix.data).ok_or(
+        RaydiumError::InstructionMismatch {
+            field: "data length",
+            intended: SWAP_IX_DATA_LEN as u64,
+            encoded: swap_ix.data.len() as u64,
+        },
+    )?;
+    if !data.swap_base_in {
+        return Err(RaydiumError::InstructionMismatch {
+            field: "tag",
+            intended: SWAP_BASE_IN_TAG as u64,
+            encoded: SWAP_BASE_OUT_TAG as u64,
+        });
+    }
+    if data.amount != amount {
+        return Err(RaydiumError::InstructionMismatch {
+            field: "amount_in",
+            intended: amount,
+            encoded: data.amount,
+        });
+    }
+    if let Some(min_out) = min_out {
+        if data.other_amount_threshold.abs_diff(min_out) > MIN_OUT_ROUNDING {
+            return Err(RaydiumError::InstructionMismatch {
+                field: "min_out",
+                intended: min_out,
+                encoded: data.other_amount_threshold,
+            });
+        }
+    }
+    Ok(data)
+}
+
+/// check_min_out validates an explicit min_out against the expected output
+/// of the swap, returning a warning if it looks unreasonable
+pub fn check_min_out(min_out: u64, expected_out: u64) -> Option<String> {
+    if expected_out == 0 {
+        return Some(format!(
+            "min_out {} cannot be validated, expected output is 0",
+            min_out
+        ));
+    }
+    if min_out > expected_out {
+        return Some(format!(
+            "min_out {} is above the expected output {}, swap will fail",
+            min_out, expected_out
+        ));
+    }
+    let slack_bps =
+        (expected_out - min_out) as u128 * 10_000 / expected_out as u128;
+    if slack_bps < MIN_OUT_TIGHT_BPS {
+        Some(format!(
+            "min_out {} is within {}bps of the expected output {}, tight",
+            min_out, slack_bps, expected_out
+        ))
+    } else if slack_bps > MIN_OUT_LOOSE_BPS {
+        Some(format!(
+            "min_out {} is {}bps below the expected output {}, loose",
+            min_out, slack_bps, expected_out
+        ))
+    } else {
+        None
+    }
+}
+
+/// expected_out is the output of the swap of the context against the
+/// reserves of result less slippage (the max input of a swap base out), 0
+/// if it can't be computed
+fn expected_out(
+    swap_context: &SwapContext,
+    result: &amm::CalculateResult,
+    slippage: u64,
+) -> u64 {
+    let direction = if swap_context.input_token_mint
+        == swap_context.amm_keys.amm_coin_mint
+        && swap_context.output_token_mint == swap_context.amm_keys.amm_pc_mint
+    {
+        amm::utils::SwapDirection::Coin2PC
+    } else {
+        amm::utils::SwapDirection::PC2Coin
+    };
+    amm::swap_with_slippage(
+        result.pool_pc_vault_amount,
+        result.pool_coin_vault_amount,
+        result.swap_fee_numerator,
+        result.swap_fee_denominator,
+        direction,
+        swap_context.amount,
+        swap_context.swap_base_in,
+        slippage,
+    )
+    .unwrap_or(0)
+}
+
+/// check_pool_min_liquidity checks the SOL side of the pool of the context
+/// against the configured min_liquidity, pools without a SOL side pass
+fn check_pool_min_liquidity(
+    swap_context: &SwapContext,
+    result: &amm::CalculateResult,
+) -> Result<(), Box<dyn Error>> {
+    let min_liquidity = swap_context.swap_config.min_liquidity();
+    if min_liquidity == 0 {
+        return Ok(());
+    }
+    if swap_context.market_keys.coin_mint == constants::SOLANA_PROGRAM_ID {
+        check_min_liquidity(result.pool_coin_vault_amount, min_liquidity)
+    } else if swap_context.market_keys.pc_mint == constants::SOLANA_PROGRAM_ID
+    {
+        check_min_liquidity(result.pool_pc_vault_amount, min_liquidity)
+    } else {
+        debug!("no SOL side, skipping the min liquidity check");
+        Ok(())
+    }
+}
+
+/// other_amount_threshold is the limit the swap encodes against the
+/// reserves of result: the zero-slippage threshold is the expected output,
+/// an explicit min_out is validated against it, otherwise the slippage is
+/// applied to it with the configured rounding
+fn other_amount_threshold(
+    swap_context: &SwapContext,
+    result: &amm::CalculateResult,
+) -> Result<u64, Box<dyn Error>> {
+    let expected = |slippage| expected_out(swap_context, result, slippage);
+    let threshold = match swap_context.min_out {
+        Some(min_out) => {
+            if let Some(warning) = check_min_out(min_out, expected(0)) {
+                warn!("{}", warning);
+            }
+            min_out
+        }
+        None if swap_context.swap_base_in => apply_slippage(
+            expected(0),
+            swap_context.slippage,
+            swap_context.swap_config.slippage_rounding(),
+        ),
+        // the max input of a swap base out is left to the library
+        None => expected(swap_context.slippage),
+    };
+    if threshold == 0 && swap_context.swap_base_in {
+        return Err("no minimum output, set min_out or the slippage".into());
+    }
+    Ok(threshold)
+}
+
+/// find_pool_deployer pages back through the txs of the pool to the oldest
+/// one and returns its fee payer, see `Raydium::pool_deployer`
+async fn find_pool_deployer(
+    rpc_client: &RpcClient,
+    amm_pool: &Pubkey,
+) -> Result<Option<Pubkey>, Box<dyn Error>> {
+    const PAGE: usize = 1_000;
+    const MAX_PAGES: usize = 10;
+    let mut before = None;
+    for _ in 0..MAX_PAGES {
+        let signatures = rpc_client
+            .get_signatures_for_address_with_config(
+                amm_pool,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    limit: Some(PAGE),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let Some(oldest) = signatures.last() else {
+            return Ok(None);
+        };
+        if signatures.len() < PAGE {
+            let tx = crate::tx_parser::fetch_tx(rpc_client, &oldest.signature)
+                .await?;
+            let signer = crate::tx_parser::parse_signer(&tx)?;
+            return Ok(Some(Pubkey::from_str(&signer)?));
+        }
+        before = Some(Signature::from_str(&oldest.signature)?);
+    }
+    warn!(
+        "{}: deployer not found in {} txs",
+        amm_pool,
+        PAGE * MAX_PAGES
+    );
+    Ok(None)
+}
+
+/// load_reserves reads the reserves of the pool of the context by loading
+/// the accounts, cheaper than the simulation of the full checks
+async fn load_reserves(
+    rpc_client: &RpcClient,
+    swap_context: &SwapContext,
+) -> Result<amm::CalculateResult, Box<dyn Error>> {
+    let result = amm::calculate_pool_vault_amounts(
+        rpc_client,
+        &swap_context.amm_program,
+        &swap_context.amm_pool,
+        &swap_context.amm_keys,
+        &swap_context.market_keys,
+        amm::utils::CalculateMethod::CalculateWithLoadAccount,
+    )
+    .await?;
+    Ok(result)
+}
+
 #[timed(duration(printer = "info!"))]
 pub async fn make_swap_ixs(
     rpc_client: &RpcClient,
@@ -442,26 +2175,26 @@ pub async fn make_swap_ixs(
         //     // TODO make this configurable, 7k (50 sol) is a bare threshold
         //     return Err("Pool is small, aborting swap".into());
         // }
-        let direction = if swap_context.input_token_mint
+        let reserve_in = if swap_context.input_token_mint
             == swap_context.amm_keys.amm_coin_mint
-            && swap_context.output_token_mint
-                == swap_context.amm_keys.amm_pc_mint
         {
-            amm::utils::SwapDirection::Coin2PC
+            result.pool_coin_vault_amount
         } else {
-            amm::utils::SwapDirection::PC2Coin
+            result.pool_pc_vault_amount
         };
-        let other_amount_threshold = amm::swap_with_slippage(
-            result.pool_pc_vault_amount,
-            result.pool_coin_vault_amount,
-            result.swap_fee_numerator,
-            result.swap_fee_denominator,
-            direction,
+        check_liquidity(
+            reserve_in,
             swap_context.amount,
-            swap_context.swap_base_in,
-            swap_context.slippage,
-        )
-        .unwrap_or(0);
+            liquidity_tolerance_bps(
+                swap_context.min_out,
+                expected_out(swap_context, &result, 0),
+                swap_context.swap_base_in,
+                swap_context.slippage,
+            ),
+        )?;
+        self::check_pool_min_liquidity(swap_context, &result)?;
+        let other_amount_threshold =
+            self::other_amount_threshold(swap_context, &result)?;
 
         let mint_account = rpc_client
             .get_account(&swap_context.output_token_mint)
@@ -489,8 +2222,12 @@ pub async fn make_swap_ixs(
 
         other_amount_threshold
     } else {
-        info!("Quick swap, skipping pool vault calculation");
-        0
+        info!("Quick swap, skipping the pool checks");
+        // a plain read of the reserves, the threshold is never left at 0
+        // and the min liquidity (of the pool overrides too) still applies
+        let result = load_reserves(rpc_client, swap_context).await?;
+        self::check_pool_min_liquidity(swap_context, &result)?;
+        self::other_amount_threshold(swap_context, &result)?
     };
     // let market_cap = util::lamports_to_sol(result.pool_coin_vault_amount);
     // info!("market cap: {}", market_cap);
@@ -520,7 +2257,10 @@ pub async fn make_swap_ixs(
         )?,
     );
     let ixs = [
-        make_compute_budget_ixs(0, 300_000),
+        make_compute_budget_ixs(
+            swap_context.swap_config.cu_price(),
+            swap_context.swap_config.cu_limit(),
+        ),
         swap_context.swap.pre_swap_instructions.clone(),
         vec![swap_ix],
         swap_context.swap.post_swap_instructions.clone(),
@@ -534,9 +2274,313 @@ impl Default for Raydium {
     }
 }
 
+/// RaydiumBuilder assembles the configuration of a Raydium instance, every
+/// option left unset keeps its default
+#[derive(Default)]
+pub struct RaydiumBuilder {
+    decimals_overrides: HashMap<Pubkey, u8>,
+    lag_check: Option<LagCheck>,
+    swap_config: SwapConfig,
+    max_drift_bps: Option<u64>,
+    pool_keys: HashMap<Pubkey, (AmmKeys, Arc<MarketPubkeys>)>,
+    confirm_quorum: Option<Arc<ConfirmQuorum>>,
+    session_stats: Option<Arc<SessionStats>>,
+    approval_hook: Option<Arc<dyn ApprovalHook>>,
+    pool_overrides: HashMap<Pubkey, PoolOverrides>,
+    blocklist: Option<Arc<Blocklist>>,
+    position_tracker: Option<Arc<PositionTracker>>,
+}
+
+impl RaydiumBuilder {
+    pub fn decimals_override(mut self, mint: Pubkey, decimals: u8) -> Self {
+        self.decimals_overrides.insert(mint, decimals);
+        self
+    }
+
+    pub fn lag_check(mut self, lag_check: LagCheck) -> Self {
+        self.lag_check = Some(lag_check);
+        self
+    }
+
+    pub fn swap_config(mut self, swap_config: SwapConfig) -> Self {
+        self.swap_config = swap_config;
+        self
+    }
+
+    pub fn cu_price(mut self, cu_price: u64) -> Self {
+        self.swap_config.cu_price = Some(cu_price);
+        self
+    }
+
+    pub fn cu_limit(mut self, cu_limit: u32) -> Self {
+        self.swap_config.cu_limit = Some(cu_limit);
+        self
+    }
+
+    pub fn max_drift_bps(mut self, max_drift_bps: u64) -> Self {
+        self.max_drift_bps = Some(max_drift_bps);
+        self
+    }
+
+    pub fn confirm_quorum(mut self, confirm_quorum: ConfirmQuorum) -> Self {
+        self.confirm_quorum = Some(Arc::new(confirm_quorum));
+        self
+    }
+
+    /// session_stats records every sent swap, quoting it first and reading
+    /// the received amount off the landed tx, one quote and one tx fetch
+    /// more per swap
+    pub fn session_stats(mut self, session_stats: Arc<SessionStats>) -> Self {
+        self.session_stats = Some(session_stats);
+        self
+    }
+
+    /// approval_hook reviews the `ApprovalRequest` of every swap before it is
+    /// built, even a confirmed one, and replaces the interactive prompt
+    pub fn approval_hook(
+        mut self,
+        approval_hook: impl ApprovalHook + 'static,
+    ) -> Self {
+        self.approval_hook = Some(Arc::new(approval_hook));
+        self
+    }
+
+    pub fn pool_override(
+        mut self,
+        amm_pool: Pubkey,
+        overrides: PoolOverrides,
+    ) -> Self {
+        self.pool_overrides.insert(amm_pool, overrides);
+        self
+    }
+
+    /// blocklist checks the `pool_associates` of every pool swapped
+    /// through, one more account read and LP holder lookup per swap
+    pub fn blocklist(mut self, blocklist: Arc<Blocklist>) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    /// position_tracker records every confirmed swap, parsed off the landed
+    /// tx, one tx fetch more per swap
+    pub fn position_tracker(
+        mut self,
+        position_tracker: Arc<PositionTracker>,
+    ) -> Self {
+        self.position_tracker = Some(position_tracker);
+        self
+    }
+
+    /// pool_keys seeds the pool keys cache, e.g. with keys loaded through
+    /// `load_pool_keys` ahead of time
+    pub fn pool_keys(
+        mut self,
+        amm_pool: Pubkey,
+        pool_keys: (AmmKeys, Arc<MarketPubkeys>),
+    ) -> Self {
+        self.pool_keys.insert(amm_pool, pool_keys);
+        self
+    }
+
+    pub fn build(self) -> Raydium {
+        Raydium {
+            decimals_overrides: self.decimals_overrides,
+            wsol_session: tokio::sync::Mutex::new(None),
+            lag_check: self.lag_check,
+            pool_keys_cache: std::sync::RwLock::new(self.pool_keys),
+            swap_config: self.swap_config,
+            max_drift_bps: self.max_drift_bps,
+            confirm_quorum: self.confirm_quorum,
+            session_stats: self.session_stats,
+            approval_hook: self.approval_hook,
+            pool_overrides: self.pool_overrides,
+            blocklist: self.blocklist,
+            pool_deployers: std::sync::RwLock::new(HashMap::new()),
+            pool_associates_cache: std::sync::RwLock::new(HashMap::new()),
+            position_tracker: self.position_tracker,
+        }
+    }
+}
+
 impl Raydium {
-    pub const fn new() -> Self {
-        Raydium {}
+    pub fn new() -> Self {
+        RaydiumBuilder::default().build()
+    }
+
+    pub fn builder() -> RaydiumBuilder {
+        RaydiumBuilder::default()
+    }
+
+    /// pool_overrides of the pool, none set if it has no entry
+    pub fn pool_overrides(&self, amm_pool: &Pubkey) -> PoolOverrides {
+        self.pool_overrides
+            .get(amm_pool)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// start_session enables the "keep WSOL open" mode for the swaps of
+    /// `owner`, returns the session WSOL account
+    pub async fn start_session(&self, owner: &Pubkey) -> Pubkey {
+        self.open_session(owner).await.0
+    }
+
+    /// open_session is start_session that also returns whether this call
+    /// started the session, false if owner had one open already
+    async fn open_session(&self, owner: &Pubkey) -> (Pubkey, bool) {
+        let mut wsol_session = self.wsol_session.lock().await;
+        if let Some(session) = wsol_session.as_ref() {
+            if session.owner == *owner {
+                return (session.account, false);
+            }
+            warn!(
+                "replacing WSOL session of {}, account {} left open",
+                session.owner, session.account
+            );
+        }
+        let session = WsolSession::new(owner);
+        let account = session.account;
+        *wsol_session = Some(session);
+        (account, true)
+    }
+
+    /// in_session is true if the swaps of owner go through a WSOL session
+    async fn in_session(&self, owner: &Pubkey) -> bool {
+        self.wsol_session
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|session| session.owner == *owner)
+    }
+
+    /// finish_session closes the session WSOL account, returning the rent
+    /// and the remaining WSOL to the wallet, None if nothing was open
+    pub async fn finish_session(
+        &self,
+        rpc_client: &RpcClient,
+        wallet: &Keypair,
+    ) -> Result<Option<Signature>, Box<dyn Error>> {
+        let mut wsol_session = self.wsol_session.lock().await;
+        let Some(session) = wsol_session.as_mut() else {
+            return Ok(None);
+        };
+        if !session.opened {
+            *wsol_session = None;
+            return Ok(None);
+        }
+        let tx = Transaction::new_signed_with_payer(
+            &common::close_account(
+                &session.account,
+                &session.owner,
+                &session.owner,
+            ),
+            Some(&wallet.pubkey()),
+            &[wallet],
+            rpc_client.get_latest_blockhash().await?,
+        );
+        // keep the session around on failure so that it can be retried
+        let signature = rpc_client.send_and_confirm_transaction(&tx).await?;
+        info!("closed WSOL session account {}", session.account);
+        session.opened = false;
+        *wsol_session = None;
+        Ok(Some(signature))
+    }
+
+    /// decimals_override is the override of the mint, if one is set
+    pub fn decimals_override(&self, mint: &Pubkey) -> Option<u8> {
+        let decimals = self.decimals_overrides.get(mint).copied()?;
+        warn!("decimals override active for {}: {}", mint, decimals);
+        Some(decimals)
+    }
+
+    /// decimals returns the override for the mint if one is set, otherwise
+    /// the decimals stored in the mint account
+    pub async fn decimals(
+        &self,
+        rpc_client: &RpcClient,
+        mint: &Pubkey,
+    ) -> Result<u8, Box<dyn Error>> {
+        if let Some(decimals) = self.decimals_override(mint) {
+            return Ok(decimals);
+        }
+        Ok(Provider::mint_info(rpc_client, mint).await?.decimals)
+    }
+
+    /// to_raw converts a ui amount of the mint to raw with its decimals
+    pub async fn to_raw(
+        &self,
+        rpc_client: &RpcClient,
+        mint: &Pubkey,
+        amount_ui: f64,
+    ) -> Result<u64, Box<dyn Error>> {
+        Ok(ui_to_raw(amount_ui, self.decimals(rpc_client, mint).await?))
+    }
+
+    /// pool_keys returns the amm and market keys of the pool, loaded once
+    /// and cached for the lifetime of the Raydium instance
+    pub async fn pool_keys(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<(AmmKeys, Arc<MarketPubkeys>), Box<dyn Error>> {
+        if let Some((amm_keys, market_keys)) = self
+            .pool_keys_cache
+            .read()
+            .expect("read lock")
+            .get(amm_pool)
+        {
+            return Ok((*amm_keys, market_keys.clone()));
+        }
+        let (amm_keys, market_keys) =
+            load_pool_keys(rpc_client, amm_pool).await?;
+        self.pool_keys_cache
+            .write()
+            .expect("write lock")
+            .insert(*amm_pool, (amm_keys, market_keys.clone()));
+        Ok((amm_keys, market_keys))
+    }
+
+    /// lot_sizes returns the lot sizes of the OpenBook market of the pool
+    pub async fn lot_sizes(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<LotSizes, Box<dyn Error>> {
+        let (amm_keys, _) = self.pool_keys(rpc_client, amm_pool).await?;
+        market_lot_sizes(rpc_client, &amm_keys.market).await
+    }
+
+    /// warm_cache loads the keys of the pools and the mint info of both of
+    /// their mints ahead of the first swap
+    pub async fn warm_cache(
+        &self,
+        rpc_client: &RpcClient,
+        pool_ids: &[Pubkey],
+    ) -> WarmReport {
+        let start = std::time::Instant::now();
+        let mut report = WarmReport::default();
+        for pool_id in pool_ids {
+            let res = async {
+                let (amm_keys, _) =
+                    self.pool_keys(rpc_client, pool_id).await?;
+                self.decimals(rpc_client, &amm_keys.amm_coin_mint).await?;
+                self.decimals(rpc_client, &amm_keys.amm_pc_mint).await?;
+                Ok::<(), Box<dyn Error>>(())
+            }
+            .await;
+            match res {
+                Ok(()) => report.warmed.push(pool_id.to_string()),
+                Err(e) => {
+                    report.failures.push((pool_id.to_string(), e.to_string()))
+                }
+            }
+        }
+        report.elapsed_ms = start.elapsed().as_millis();
+        info!(
+            "warm_cache: {}",
+            serde_json::to_string(&report).unwrap_or_default()
+        );
+        report
     }
 
     #[deprecated = "slow and not production required"]
@@ -581,80 +2625,1330 @@ impl Raydium {
         Pubkey::default()
     }
 
-    // swap_simple is a wrapper around swap that requires only the token mint
-    pub fn swap_simple(&self, _output_token_mint: Pubkey, _sol_amount: u64) {
-        // need to fetch amm pool by input/output first, not critical but useful
+    /// quote returns the expected output of swapping `amount` of
+    /// `input_mint` through the pool, before slippage
+    pub async fn quote(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        amount: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        let (result, _, keys) = get_calc_result(rpc_client, amm_pool).await?;
+        let direction = if *input_mint == keys.amm_coin_mint
+            && *output_mint == keys.amm_pc_mint
+        {
+            amm::utils::SwapDirection::Coin2PC
+        } else if *input_mint == keys.amm_pc_mint
+            && *output_mint == keys.amm_coin_mint
+        {
+            amm::utils::SwapDirection::PC2Coin
+        } else {
+            return Err(format!(
+                "pool {} does not trade {} -> {}",
+                amm_pool, input_mint, output_mint
+            )
+            .into());
+        };
+        let amount_out = amm::swap_with_slippage(
+            result.pool_pc_vault_amount,
+            result.pool_coin_vault_amount,
+            result.swap_fee_numerator,
+            result.swap_fee_denominator,
+            direction,
+            amount,
+            true,
+            0,
+        )?;
+        Ok(amount_out)
     }
 
-    pub async fn swap(
+    /// approval_request describes the swap of the args as the approval hook
+    /// sees it (with the overrides of the pool), off the current reserves
+    pub async fn approval_request(
         &self,
-        swap_args: SwapArgs,
-    ) -> Result<(), Box<dyn Error>> {
+        swap_args: &SwapArgs,
+    ) -> Result<ApprovalRequest, Box<dyn Error>> {
         let SwapArgs {
             amm_pool,
             input_token_mint,
             output_token_mint,
             amount,
             slippage,
-            wallet,
+            ref rpc_client,
+            min_out,
+            ..
+        } = *swap_args;
+        let slippage = self.pool_overrides(&amm_pool).slippage_bps(slippage);
+        let (result, _, keys) = get_calc_result(rpc_client, &amm_pool).await?;
+        let (direction, reserve_in) = if input_token_mint == keys.amm_coin_mint
+            && output_token_mint == keys.amm_pc_mint
+        {
+            (
+                amm::utils::SwapDirection::Coin2PC,
+                result.pool_coin_vault_amount,
+            )
+        } else if input_token_mint == keys.amm_pc_mint
+            && output_token_mint == keys.amm_coin_mint
+        {
+            (
+                amm::utils::SwapDirection::PC2Coin,
+                result.pool_pc_vault_amount,
+            )
+        } else {
+            return Err(format!(
+                "pool {} does not trade {} -> {}",
+                amm_pool, input_token_mint, output_token_mint
+            )
+            .into());
+        };
+        let expected_out = amm::swap_with_slippage(
+            result.pool_pc_vault_amount,
+            result.pool_coin_vault_amount,
+            result.swap_fee_numerator,
+            result.swap_fee_denominator,
+            direction,
+            amount,
+            true,
+            0,
+        )?;
+        let fee = FeeInfo::new(
+            RaydiumProgram::AmmV4,
+            result.swap_fee_numerator,
+            result.swap_fee_denominator,
+        );
+        let fee_amount = if fee.denominator > 0 {
+            (amount as u128 * fee.numerator as u128 / fee.denominator as u128)
+                as u64
+        } else {
+            0
+        };
+        Ok(ApprovalRequest {
+            venue: crate::router::Venue::Raydium,
+            pool: amm_pool.to_string(),
+            side: crate::approval::side_of(
+                &input_token_mint,
+                &output_token_mint,
+            ),
+            input_mint: input_token_mint.to_string(),
+            output_mint: output_token_mint.to_string(),
+            amount,
+            expected_out,
+            min_out: min_out.unwrap_or_else(|| {
+                apply_slippage(
+                    expected_out,
+                    slippage,
+                    self.pool_overrides(&amm_pool)
+                        .swap_config(&self.swap_config)
+                        .slippage_rounding(),
+                )
+            }),
+            slippage_bps: slippage,
+            price_impact_bps: crate::approval::price_impact_bps(
+                reserve_in, amount,
+            ),
+            fee,
+            fee_amount,
+        })
+    }
+
+    /// find_pools_by_mint looks up the pools of the programs (AMM v4 and
+    /// CPMM, CLMM is skipped) trading the pair, in either order of the mints
+    pub async fn find_pools_by_mint(
+        &self,
+        rpc_client: &RpcClient,
+        mint_a: &Pubkey,
+        mint_b: &Pubkey,
+        programs: &[RaydiumProgram],
+    ) -> Result<Vec<PoolMatch>, Box<dyn Error>> {
+        let mut queries = vec![];
+        for program in programs {
+            let (len, offsets) = match program {
+                RaydiumProgram::AmmV4 => (
+                    AMM_V4_POOL_LEN,
+                    (AMM_V4_COIN_MINT_OFFSET, AMM_V4_PC_MINT_OFFSET),
+                ),
+                RaydiumProgram::Cpmm => {
+                    (CPMM_POOL_LEN, (CPMM_MINT_0_OFFSET, CPMM_MINT_1_OFFSET))
+                }
+                RaydiumProgram::Clmm => {
+                    warn!("find_pools_by_mint: CLMM pools are not supported");
+                    continue;
+                }
+            };
+            for (first, second) in [(mint_a, mint_b), (mint_b, mint_a)] {
+                let config = RpcProgramAccountsConfig {
+                    filters: Some(vec![
+                        RpcFilterType::DataSize(len),
+                        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                            offsets.0,
+                            &first.to_bytes(),
+                        )),
+                        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                            offsets.1,
+                            &second.to_bytes(),
+                        )),
+                    ]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        // only the pubkeys are needed
+                        data_slice: Some(
+                            solana_account_decoder::UiDataSliceConfig {
+                                offset: 0,
+                                length: 0,
+                            },
+                        ),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                let program = *program;
+                queries.push(async move {
+                    let accounts = rpc_client
+                        .get_program_accounts_with_config(
+                            &program.program_id(),
+                            config,
+                        )
+                        .await?;
+                    Ok::<Vec<PoolMatch>, Box<dyn Error>>(
+                        accounts
+                            .into_iter()
+                            .map(|(pool, _)| PoolMatch { program, pool })
+                            .collect(),
+                    )
+                });
+            }
+        }
+        let mut pools = vec![];
+        for res in futures_util::future::join_all(queries).await {
+            pools.extend(res?);
+        }
+        debug!("pools of {} / {}: {:?}", mint_a, mint_b, pools);
+        Ok(pools)
+    }
+
+    /// quote_cpmm is `quote` for a CPMM pool, off the vault balances less
+    /// the fees owed and the trade fee rate of its config
+    pub async fn quote_cpmm(
+        &self,
+        rpc_client: &RpcClient,
+        pool: &Pubkey,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+        amount: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        let state =
+            CpmmPool::parse(&rpc_client.get_account_data(pool).await?)?;
+        let zero_for_one = if *input_mint == state.mint_0
+            && *output_mint == state.mint_1
+        {
+            true
+        } else if *input_mint == state.mint_1 && *output_mint == state.mint_0 {
+            false
+        } else {
+            return Err(format!(
+                "pool {} does not trade {} -> {}",
+                pool, input_mint, output_mint
+            )
+            .into());
+        };
+        let accounts = rpc_client
+            .get_multiple_accounts(&[
+                state.vault_0,
+                state.vault_1,
+                state.config,
+            ])
+            .await?;
+        let [Some(vault_0), Some(vault_1), Some(config)] = accounts.as_slice()
+        else {
+            return Err(format!("pool {} accounts not found", pool).into());
+        };
+        // the amount of a token account is at 64
+        let reserve_0 = u64::from_le_bytes(read_le(&vault_0.data, 64)?)
+            .saturating_sub(state.fees_0);
+        let reserve_1 = u64::from_le_bytes(read_le(&vault_1.data, 64)?)
+            .saturating_sub(state.fees_1);
+        let fee = parse_fee_config(
+            RaydiumProgram::Cpmm,
+            &state.config,
+            &config.data,
+        )?;
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (reserve_0, reserve_1)
+        } else {
+            (reserve_1, reserve_0)
+        };
+        Ok(cpmm_quote(reserve_in, reserve_out, amount, fee.numerator))
+    }
+
+    /// fee_info returns the active swap fee of the pool, read from the pool
+    /// for AMM v4 and from its fee config account for CPMM and CLMM
+    pub async fn fee_info(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<FeeInfo, Box<dyn Error>> {
+        let account = rpc_client.get_account(amm_pool).await?;
+        let program = RaydiumProgram::from_program_id(&account.owner)
+            .ok_or_else(|| {
+                format!(
+                    "pool {} is owned by {}, not a Raydium program",
+                    amm_pool, account.owner
+                )
+            })?;
+        match program {
+            RaydiumProgram::AmmV4 => {
+                let amm_info = crate::seller::unpack::<
+                    raydium_amm::state::AmmInfo,
+                >(&account.data)
+                .ok_or("unpack amm info")?;
+                Ok(FeeInfo::new(
+                    program,
+                    amm_info.fees.swap_fee_numerator,
+                    amm_info.fees.swap_fee_denominator,
+                ))
+            }
+            RaydiumProgram::Cpmm | RaydiumProgram::Clmm => {
+                let config = fee_config_of(program, &account.data)?;
+                let data = rpc_client.get_account_data(&config).await?;
+                parse_fee_config(program, &config, &data)
+            }
+        }
+    }
+
+    /// lp_token_price is the value of one LP token of a SOL pool in SOL,
+    /// the mint supply is used as the LP supply
+    pub async fn lp_token_price(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<f64, Box<dyn Error>> {
+        let (result, _, keys) = get_calc_result(rpc_client, amm_pool).await?;
+        let sol_reserve = if keys.amm_coin_mint == constants::SOLANA_PROGRAM_ID
+        {
+            result.pool_coin_vault_amount
+        } else if keys.amm_pc_mint == constants::SOLANA_PROGRAM_ID {
+            result.pool_pc_vault_amount
+        } else {
+            return Err(format!("pool {} is not a SOL pool", amm_pool).into());
+        };
+        let lp_mint = Mint::unpack(
+            &rpc_client.get_account_data(&keys.amm_lp_mint).await?,
+        )?;
+        let lp_decimals = self
+            .decimals_override(&keys.amm_lp_mint)
+            .unwrap_or(lp_mint.decimals);
+        lp_token_price_sol(sol_reserve, lp_mint.supply, lp_decimals)
+            .ok_or_else(|| {
+                format!("pool {} has no LP supply", amm_pool).into()
+            })
+    }
+
+    /// simulate_round_trip simulates buying the token for `amount` lamports
+    /// and selling it right back in a single tx, nothing is sent; if the
+    /// round trip fails the buy is simulated alone to tell which leg did
+    pub async fn simulate_round_trip(
+        &self,
+        rpc_client: &RpcClient,
+        wallet: &Keypair,
+        amm_pool: &Pubkey,
+        token_mint: &Pubkey,
+        amount: u64,
+    ) -> Result<RoundTrip, Box<dyn Error>> {
+        let sol = constants::SOLANA_PROGRAM_ID;
+        let pool_keys = self.pool_keys(rpc_client, amm_pool).await?;
+        let quoted_tokens = self
+            .quote(rpc_client, amm_pool, &sol, token_mint, amount)
+            .await?;
+        let tokens_sold = (quoted_tokens as u128
+            * (10_000 - ROUND_TRIP_HEADROOM_BPS) as u128
+            / 10_000) as u64;
+        if tokens_sold == 0 {
+            return Err(format!(
+                "{} lamports buy too little of {} to sell back",
+                amount, token_mint
+            )
+            .into());
+        }
+        let buy = make_swap_context_in_session(
             rpc_client,
-            confirmed,
-            no_sanity,
-        } = swap_args;
-        let swap_context = self::make_swap_context(
-            &rpc_client,
-            amm_pool,
-            input_token_mint,
-            output_token_mint,
-            &wallet,
-            slippage,
+            *amm_pool,
+            sol,
+            *token_mint,
+            wallet,
+            0,
             amount,
+            None,
+            pool_keys.clone(),
+            self.swap_config.clone(),
         )
         .await?;
-        let ixs = self::make_swap_ixs(
-            &rpc_client,
-            &wallet,
-            &swap_context,
-            no_sanity,
+        let mut sell = make_swap_context_in_session(
+            rpc_client,
+            *amm_pool,
+            *token_mint,
+            sol,
+            wallet,
+            0,
+            tokens_sold,
+            None,
+            pool_keys,
+            self.swap_config.clone(),
+        )
+        .await?;
+        // keep the WSOL account the sell pays out to open, its balance is
+        // what came back
+        sell.swap.post_swap_instructions.clear();
+        let ata = buy.user_destination;
+        let held = match rpc_client.get_token_account_balance(&ata).await {
+            Ok(balance) => balance.amount.parse::<u64>()?,
+            Err(_) => 0,
+        };
+        let without_budget = |ixs: Vec<Instruction>| {
+            ixs.into_iter()
+                .filter(|ix| ix.program_id != solana_sdk::compute_budget::id())
+        };
+        let mut buy_ixs = make_compute_budget_ixs(
+            self.swap_config.cu_price(),
+            self.swap_config.cu_limit().saturating_mul(2),
+        );
+        buy_ixs.extend(without_budget(
+            make_swap_ixs(rpc_client, wallet, &buy, true).await?,
+        ));
+        let mut ixs = buy_ixs.clone();
+        ixs.extend(without_budget(
+            make_swap_ixs(rpc_client, wallet, &sell, true).await?,
+        ));
+
+        let blockhash = rpc_client.get_latest_blockhash().await?;
+        let simulate = |ixs: &[Instruction], addresses: Vec<String>| {
+            let tx = Transaction::new_signed_with_payer(
+                ixs,
+                Some(&wallet.pubkey()),
+                &[wallet],
+                blockhash,
+            );
+            async move {
+                rpc_client
+                    .simulate_transaction_with_config(
+                        &tx,
+                        RpcSimulateTransactionConfig {
+                            sig_verify: false,
+                            replace_recent_blockhash: true,
+                            commitment: Some(CommitmentConfig::processed()),
+                            accounts: Some(
+                                RpcSimulateTransactionAccountsConfig {
+                                    encoding: Some(UiAccountEncoding::Base64),
+                                    addresses,
+                                },
+                            ),
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .map(|res| res.value)
+            }
+        };
+        let res = simulate(
+            &ixs,
+            vec![ata.to_string(), sell.user_destination.to_string()],
         )
         .await?;
+        let mut round_trip = RoundTrip {
+            buy_ok: true,
+            sell_ok: true,
+            amount_in: amount,
+            quoted_tokens,
+            tokens_sold,
+            sol_back: None,
+            loss_lamports: None,
+            loss_pct: None,
+            error: res.err.as_ref().map(|err| err.to_string()),
+            logs: res.logs.unwrap_or_default(),
+        };
+        if res.err.is_some() {
+            round_trip.sell_ok = false;
+            let buy_only = simulate(&buy_ixs, vec![]).await?;
+            round_trip.buy_ok = buy_only.err.is_none();
+            warn!(
+                "round trip of {} failed (buy ok: {}): {:?}",
+                token_mint, round_trip.buy_ok, round_trip.error
+            );
+            return Ok(round_trip);
+        }
+        let accounts = res.accounts.unwrap_or_default();
+        let after = |idx: usize| {
+            token_amount(
+                accounts.get(idx).and_then(|account| account.as_ref()),
+            )
+        };
+        let (Some(tokens_after), Some(sol_back)) = (after(0), after(1)) else {
+            return Err("simulation returned no post balances".into());
+        };
+        let leftover = tokens_after.saturating_sub(held);
+        let (loss, pct) =
+            round_trip_loss(amount, sol_back, leftover, quoted_tokens);
+        round_trip.sol_back = Some(sol_back);
+        round_trip.loss_lamports = Some(loss);
+        round_trip.loss_pct = Some(pct);
         info!(
-            "{}",
-            serde_json::to_string_pretty(&json!({
-                "amount": amount,
-                "input": input_token_mint.to_string(),
-                "output": output_token_mint.to_string(),
-                "funder": wallet.pubkey().to_string(),
-                "slippage": slippage,
-            }))?
+            "round trip of {}: {} lamports in, {} back, {} tokens left, \
+             loss {:.2}%",
+            token_mint, amount, sol_back, leftover, pct
         );
-        if !confirmed
-            && !dialoguer::Confirm::new()
-                .with_prompt("Go for it?")
-                .interact()?
+        Ok(round_trip)
+    }
+
+    /// pool_invariant reads the reserves and the LP of the pool, compare two
+    /// with `classify_invariant_change`
+    pub async fn pool_invariant(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<PoolInvariant, Box<dyn Error>> {
+        let (result, _, _) = get_calc_result(rpc_client, amm_pool).await?;
+        Ok(PoolInvariant::new(
+            chrono::Utc::now().timestamp(),
+            result.pool_coin_vault_amount,
+            result.pool_pc_vault_amount,
+            result.pool_lp_amount,
+        ))
+    }
+
+    /// watch_invariant polls the invariant of the pool every interval and
+    /// warns on anomalies, on_change gets every change but Unchanged and
+    /// stops the watch by returning false
+    pub async fn watch_invariant(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        interval: std::time::Duration,
+        tolerance_bps: f64,
+        mut on_change: impl FnMut(&PoolInvariant, InvariantChange) -> bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut last = self.pool_invariant(rpc_client, amm_pool).await?;
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = self.pool_invariant(rpc_client, amm_pool).await?;
+            let change =
+                classify_invariant_change(&last, &current, tolerance_bps);
+            if let InvariantChange::Anomaly { drop_bps, lp_delta } = change {
+                warn!(
+                    "pool {} k per LP dropped {:.2}bps (LP delta {})",
+                    amm_pool, drop_bps, lp_delta
+                );
+            }
+            if change != InvariantChange::Unchanged
+                && !on_change(&current, change)
+            {
+                return Ok(());
+            }
+            last = current;
+        }
+    }
+
+    /// pool_deployer is the fee payer of the oldest tx of the pool, the one
+    /// that created it, None if the pool has more history than is paged
+    /// through, cached either way
+    pub async fn pool_deployer(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<Option<Pubkey>, Box<dyn Error>> {
+        if let Some(deployer) =
+            self.pool_deployers.read().expect("read lock").get(amm_pool)
         {
-            return Ok(());
+            return Ok(*deployer);
         }
-        let tx = Transaction::new_signed_with_payer(
-            ixs.as_slice(),
-            Some(&wallet.pubkey()),
-            &[&wallet],
-            rpc_client.get_latest_blockhash().await?,
-        );
-        let sim_res = rpc_client.simulate_transaction(&tx).await?;
-        info!("Simulation: {}", serde_json::to_string_pretty(&sim_res)?);
-        send_jito_tx(tx).await?;
-        Ok(())
+        let deployer = find_pool_deployer(rpc_client, amm_pool).await?;
+        self.pool_deployers
+            .write()
+            .expect("write lock")
+            .insert(*amm_pool, deployer);
+        Ok(deployer)
     }
-}
 
-pub async fn handle_token_account(
-    swap: &mut Swap,
-    rpc_client: &RpcClient,
-    mint: &Pubkey,
-    amount: u64,
+    /// pool_associates are the accounts the pool is tied to as (role,
+    /// account): its deployer, the authorities of its non-SOL mints and the
+    /// owner of the largest LP token account, the LP owner unless the LP was
+    /// burnt, reused for POOL_ASSOCIATES_TTL
+    pub async fn pool_associates(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<Vec<(&'static str, Pubkey)>, Box<dyn Error>> {
+        if let Some((at, associates)) = self
+            .pool_associates_cache
+            .read()
+            .expect("read lock")
+            .get(amm_pool)
+        {
+            if at.elapsed() < POOL_ASSOCIATES_TTL {
+                return Ok(associates.clone());
+            }
+        }
+        let associates =
+            self.load_pool_associates(rpc_client, amm_pool).await?;
+        self.pool_associates_cache
+            .write()
+            .expect("write lock")
+            .insert(
+                *amm_pool,
+                (std::time::Instant::now(), associates.clone()),
+            );
+        Ok(associates)
+    }
+
+    async fn load_pool_associates(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<Vec<(&'static str, Pubkey)>, Box<dyn Error>> {
+        let amm_info = crate::seller::unpack::<raydium_amm::state::AmmInfo>(
+            &rpc_client.get_account_data(amm_pool).await?,
+        )
+        .ok_or("unpack amm info")?;
+        let mints = [amm_info.coin_vault_mint, amm_info.pc_vault_mint]
+            .into_iter()
+            .filter(|mint| *mint != constants::SOLANA_PROGRAM_ID)
+            .collect::<Vec<Pubkey>>();
+        let accounts = rpc_client.get_multiple_accounts(&mints).await?;
+        let mut associates = Vec::new();
+        if let Some(deployer) =
+            self.pool_deployer(rpc_client, amm_pool).await?
+        {
+            associates.push(("deployer", deployer));
+        }
+        for (mint, account) in mints.iter().zip(accounts) {
+            let account = account.ok_or(format!("mint {} not found", mint))?;
+            let state = StateWithExtensionsOwned::<
+                spl_token_2022::state::Mint,
+            >::unpack(account.data)?;
+            associates.extend(crate::blocklist::authorities(
+                state.base.mint_authority,
+                state.base.freeze_authority,
+            ));
+        }
+        let lp_holders = rpc_client
+            .get_token_largest_accounts(&amm_info.lp_mint)
+            .await?;
+        if let Some(lp_holder) = lp_holders.first() {
+            let lp_account = spl_token::state::Account::unpack(
+                &rpc_client
+                    .get_account_data(&Pubkey::from_str(&lp_holder.address)?)
+                    .await?,
+            )?;
+            associates.push(("LP owner", lp_account.owner));
+        }
+        Ok(associates)
+    }
+
+    /// check_blocklist fails with `RaydiumError::Blocklisted` if the pool is
+    /// associated with an account of the blocklist, ok without one
+    pub async fn check_blocklist(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<(), Box<dyn Error>> {
+        let Some(blocklist) = &self.blocklist else {
+            return Ok(());
+        };
+        let associates = self.pool_associates(rpc_client, amm_pool).await?;
+        if !associates.iter().any(|(role, _)| *role == "deployer") {
+            warn!(
+                "{}: deployer unknown, not checked against the blocklist",
+                amm_pool
+            );
+        }
+        match blocklist.check(&associates) {
+            Some(hit) => Err(RaydiumError::Blocklisted {
+                role: hit.role,
+                account: hit.account.to_string(),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// snapshot reads the reserves and the swap counters of the pool
+    pub async fn snapshot(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+    ) -> Result<PoolSnapshot, Box<dyn Error>> {
+        let (result, _, _) = get_calc_result(rpc_client, amm_pool).await?;
+        let amm_info = crate::seller::unpack::<raydium_amm::state::AmmInfo>(
+            &rpc_client.get_account_data(amm_pool).await?,
+        )
+        .ok_or("unpack amm info")?;
+        let price = if result.pool_coin_vault_amount > 0 {
+            result.pool_pc_vault_amount as f64
+                / result.pool_coin_vault_amount as f64
+        } else {
+            0.
+        };
+        Ok(PoolSnapshot {
+            amm_pool: amm_pool.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            coin_reserve: result.pool_coin_vault_amount,
+            pc_reserve: result.pool_pc_vault_amount,
+            price,
+            swap_coin_in_amount: amm_info.state_data.swap_coin_in_amount,
+            swap_coin_out_amount: amm_info.state_data.swap_coin_out_amount,
+            swap_pc_in_amount: amm_info.state_data.swap_pc_in_amount,
+            swap_pc_out_amount: amm_info.state_data.swap_pc_out_amount,
+        })
+    }
+
+    /// recommended_slippage_bps gathers the SlippageParams of the pool for
+    /// a trade of `amount_in` of `input_mint`, the fills are the txs that
+    /// touched the pool in the last 5 minutes
+    pub async fn recommended_slippage_bps(
+        &self,
+        rpc_client: &RpcClient,
+        amm_pool: &Pubkey,
+        input_mint: &Pubkey,
+        amount_in: u64,
+        priority_fee: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        let (result, _, keys) = get_calc_result(rpc_client, amm_pool).await?;
+        let reserve_in = if *input_mint == keys.amm_coin_mint {
+            result.pool_coin_vault_amount
+        } else {
+            result.pool_pc_vault_amount
+        };
+        let sol_reserve = if keys.amm_coin_mint == constants::SOLANA_PROGRAM_ID
+        {
+            result.pool_coin_vault_amount
+        } else {
+            result.pool_pc_vault_amount
+        };
+        let amm_info = crate::seller::unpack::<raydium_amm::state::AmmInfo>(
+            &rpc_client.get_account_data(amm_pool).await?,
+        )
+        .ok_or("unpack amm info")?;
+        let now = chrono::Utc::now().timestamp();
+        let pool_age_secs =
+            (now as u64).saturating_sub(amm_info.state_data.pool_open_time);
+        let signatures =
+            rpc_client.get_signatures_for_address(amm_pool).await?;
+        let fills = signatures
+            .iter()
+            .filter(|sig| sig.err.is_none())
+            .filter(|sig| sig.block_time.is_some_and(|t| t > now - 300))
+            .count();
+        let params = SlippageParams {
+            reserve_in,
+            amount_in,
+            sol_reserve,
+            priority_fee,
+            pool_age_secs,
+            fills_per_minute: fills as f64 / 5.,
+        };
+        let slippage_bps = recommend_slippage_bps(&params);
+        info!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "amm_pool": amm_pool.to_string(),
+                "params": params,
+                "recommended_slippage_bps": slippage_bps,
+            }))?
+        );
+        Ok(slippage_bps)
+    }
+
+    /// arb_size reads the reserves of two pools of the same pair and returns
+    /// the input size (in the pc/quote token of `pool_a`) that equalizes the
+    /// prices after fees, buying in the cheaper pool and selling in the other
+    pub async fn arb_size(
+        &self,
+        rpc_client: &RpcClient,
+        pool_a: &Pubkey,
+        pool_b: &Pubkey,
+    ) -> Result<Option<ArbSize>, Box<dyn Error>> {
+        let (result_a, _, keys_a) =
+            get_calc_result(rpc_client, pool_a).await?;
+        let (result_b, _, keys_b) =
+            get_calc_result(rpc_client, pool_b).await?;
+        let reserves_a = Reserves::from_calc_result(&result_a);
+        let reserves_b = if keys_a.amm_coin_mint == keys_b.amm_coin_mint
+            && keys_a.amm_pc_mint == keys_b.amm_pc_mint
+        {
+            Reserves::from_calc_result(&result_b)
+        } else if keys_a.amm_coin_mint == keys_b.amm_pc_mint
+            && keys_a.amm_pc_mint == keys_b.amm_coin_mint
+        {
+            Reserves::from_calc_result(&result_b).flipped()
+        } else {
+            return Err(format!(
+                "pools {} and {} are not the same pair",
+                pool_a, pool_b
+            )
+            .into());
+        };
+        debug!("reserves a: {:?}, reserves b: {:?}", reserves_a, reserves_b);
+
+        let arb =
+            |buy_pool: &Pubkey, sell_pool: &Pubkey, amounts: (u64, u64)| {
+                let (amount_in, expected_out) = amounts;
+                ArbSize {
+                    buy_pool: buy_pool.to_string(),
+                    sell_pool: sell_pool.to_string(),
+                    input_mint: keys_a.amm_pc_mint.to_string(),
+                    amount_in,
+                    expected_out,
+                    expected_gross_profit: expected_out - amount_in,
+                }
+            };
+        if let Some(amounts) = optimal_arb_amount(&reserves_a, &reserves_b) {
+            return Ok(Some(arb(pool_a, pool_b, amounts)));
+        }
+        if let Some(amounts) = optimal_arb_amount(&reserves_b, &reserves_a) {
+            return Ok(Some(arb(pool_b, pool_a, amounts)));
+        }
+        Ok(None)
+    }
+
+    // swap_simple is a wrapper around swap that requires only the token mint
+    pub fn swap_simple(&self, _output_token_mint: Pubkey, _sol_amount: u64) {
+        // need to fetch amm pool by input/output first, not critical but useful
+    }
+
+    pub async fn swap(
+        &self,
+        swap_args: SwapArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(result) = self.execute_swap(&swap_args).await? {
+            info!("{}", serde_json::to_string_pretty(&result)?);
+        }
+        Ok(())
+    }
+
+    /// execute_swap is swap that borrows the args, so that they can be
+    /// reused, waits for the tx until its blockhash expires, returns None if
+    /// the swap was declined
+    pub async fn execute_swap(
+        &self,
+        swap_args: &SwapArgs,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        self.execute_swap_with_events(swap_args, None).await
+    }
+
+    /// execute_swap_with_events is execute_swap that reports the lifecycle
+    /// of the swap to events, a closed channel does not stop the swap
+    pub async fn execute_swap_with_events(
+        &self,
+        swap_args: &SwapArgs,
+        events: Option<&tokio::sync::mpsc::UnboundedSender<SwapEvent>>,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        self.execute(swap_args, events, None).await
+    }
+
+    /// swap_async is execute_swap that aborts with
+    /// `RaydiumError::Cancelled` if cancel fires while the swap is still
+    /// being built, quoted or simulated; once sent it runs to the end. The
+    /// temp WSOL accounts only exist within the tx, so an aborted swap
+    /// leaves nothing behind. Pass `confirmed`, the prompt can't be aborted
+    pub async fn swap_async(
+        &self,
+        swap_args: &SwapArgs,
+        cancel: &CancellationToken,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        self.execute(swap_args, None, Some(cancel)).await
+    }
+
+    async fn execute(
+        &self,
+        swap_args: &SwapArgs,
+        events: Option<&tokio::sync::mpsc::UnboundedSender<SwapEvent>>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Option<SwapResult>, Box<dyn Error>> {
+        let emit = |event: SwapEvent| {
+            if let Some(events) = events {
+                let _ = events.send(event);
+            }
+        };
+        let SwapArgs {
+            amm_pool,
+            input_token_mint,
+            output_token_mint,
+            amount,
+            slippage,
+            ref wallet,
+            ref rpc_client,
+            confirmed,
+            no_sanity,
+            min_out,
+        } = *swap_args;
+        let overrides = self.pool_overrides(&amm_pool);
+        let slippage = overrides.slippage_bps(slippage);
+        let swap_config = overrides.swap_config(&self.swap_config);
+        emit(SwapEvent::Building {
+            amm_pool: amm_pool.to_string(),
+        });
+        let program = until_cancelled(cancel, async {
+            if let Some(lag_check) = &self.lag_check {
+                Provider::check_rpc_lag(rpc_client, lag_check).await?;
+            }
+            self::detect_program(rpc_client, &amm_pool).await
+        })
+        .await?;
+        debug!("pool {} belongs to {:?}", amm_pool, program);
+        match program {
+            RaydiumProgram::AmmV4 => {}
+            RaydiumProgram::Cpmm | RaydiumProgram::Clmm => {
+                return Err(format!(
+                    "swaps through {:?} pools are not supported yet",
+                    program
+                )
+                .into());
+            }
+        }
+        until_cancelled(cancel, self.check_blocklist(rpc_client, &amm_pool))
+            .await?;
+        // the session is copied out so that the swaps don't queue on its
+        // lock, only a swap that is to create the account holds on to it
+        // until done, the others would create the account again
+        let guard = self.wsol_session.lock().await;
+        let session = guard
+            .as_ref()
+            .filter(|session| session.owner == wallet.pubkey())
+            .map(WsolSession::snapshot);
+        let mut creating = match &session {
+            Some(session) if !session.opened => Some(guard),
+            _ => {
+                drop(guard);
+                None
+            }
+        };
+        // everything up to the send can be aborted
+        let prepared = until_cancelled(cancel, async {
+            let terms = SwapTerms {
+                amount,
+                slippage_bps: slippage,
+                min_out,
+            };
+            let terms = match &self.approval_hook {
+                Some(approval_hook) => {
+                    let request = self.approval_request(swap_args).await?;
+                    info!("{}", serde_json::to_string(&request)?);
+                    let approval = approval_hook.review(&request).await;
+                    info!("{}", serde_json::to_string(&approval)?);
+                    approval.resolve(terms)?
+                }
+                None => terms,
+            };
+            let SwapTerms {
+                amount,
+                slippage_bps: slippage,
+                min_out,
+            } = terms;
+            // quoted for the drift check and the realized slippage
+            let quoted = if self.max_drift_bps.is_some()
+                || self.session_stats.is_some()
+            {
+                Some(
+                    self.quote(
+                        rpc_client,
+                        &amm_pool,
+                        &input_token_mint,
+                        &output_token_mint,
+                        amount,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            let mut swap_context = self::make_swap_context_in_session(
+                rpc_client,
+                amm_pool,
+                input_token_mint,
+                output_token_mint,
+                wallet,
+                slippage,
+                amount,
+                session.as_ref(),
+                self.pool_keys(rpc_client, &amm_pool).await?,
+                swap_config.clone(),
+            )
+            .await?;
+            swap_context.min_out = min_out;
+            let ixs = self::make_swap_ixs(
+                rpc_client,
+                wallet,
+                &swap_context,
+                no_sanity,
+            )
+            .await?;
+            let encoded = self::validate_swap_ixs(&ixs, amount, min_out)?;
+            debug!("swap ix: {:?}", encoded);
+            // the mint is not fetched for a log line, the ui amount is
+            // only logged with a decimals override
+            let amount_ui = self
+                .decimals_override(&input_token_mint)
+                .map(|decimals| amount as f64 / 10f64.powi(decimals as i32));
+            info!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "amount": amount,
+                    "amount_ui": amount_ui,
+                    "input": input_token_mint.to_string(),
+                    "output": output_token_mint.to_string(),
+                    "funder": wallet.pubkey().to_string(),
+                    "slippage": slippage,
+                    "min_out": min_out,
+                }))?
+            );
+            if self.approval_hook.is_none() && !confirmed && !auto_confirm() {
+                if !std::io::stdin().is_terminal() {
+                    return Err(
+                        "swap not confirmed and there is no terminal \
+                                to prompt, pass confirmed or enable \
+                                auto-confirm"
+                            .into(),
+                    );
+                }
+                if !dialoguer::Confirm::new()
+                    .with_prompt("Go for it?")
+                    .interact()?
+                {
+                    return Ok(None);
+                }
+            }
+            if let (Some(quoted), Some(max_drift_bps)) =
+                (quoted, self.max_drift_bps)
+            {
+                let current = self
+                    .quote(
+                        rpc_client,
+                        &amm_pool,
+                        &input_token_mint,
+                        &output_token_mint,
+                        amount,
+                    )
+                    .await?;
+                check_quote_drift(quoted, current, max_drift_bps)?;
+            }
+            let (blockhash, last_valid_block_height) = rpc_client
+                .get_latest_blockhash_with_commitment(
+                    CommitmentConfig::confirmed(),
+                )
+                .await?;
+            let tx = Transaction::new_signed_with_payer(
+                ixs.as_slice(),
+                Some(&wallet.pubkey()),
+                &[wallet],
+                blockhash,
+            );
+            let simulation = Provider::simulate(rpc_client, &tx).await?;
+            info!(
+                "Simulation: {}",
+                serde_json::to_string_pretty(&simulation)?
+            );
+            if simulation.err.is_some() {
+                return Err(RaydiumError::SimulationFailed(simulation).into());
+            }
+            Ok(Some((tx, quoted, last_valid_block_height)))
+        })
+        .await?;
+        let Some((tx, quoted, last_valid_block_height)) = prepared else {
+            return Ok(None);
+        };
+        let signature = tx.signatures[0];
+        let sent_at = std::time::Instant::now();
+        send_jito_tx(tx).await?;
+        emit(SwapEvent::Submitted {
+            signature: signature.to_string(),
+        });
+        let confirmation = match &self.confirm_quorum {
+            Some(confirm_quorum) => {
+                let quorum = confirm_quorum
+                    .confirm(&signature, last_valid_block_height)
+                    .await?;
+                info!("{}", serde_json::to_string(&quorum)?);
+                quorum.confirmation()
+            }
+            None => {
+                Provider::confirm_until_expiry(
+                    rpc_client,
+                    &signature,
+                    last_valid_block_height,
+                )
+                .await?
+            }
+        };
+        if let Some(session) =
+            creating.as_mut().and_then(|guard| guard.as_mut())
+        {
+            session.landed(&confirmation);
+        }
+        drop(creating);
+        if let Some(err) = confirmation.err {
+            return Err(format!("swap {} failed: {}", signature, err).into());
+        }
+        let mut result = SwapResult {
+            signature: signature.to_string(),
+            confirmed: confirmation.confirmed,
+            last_valid_block_height,
+            remaining_blocks: confirmation.remaining_blocks(),
+            quoted_out: quoted,
+            amount_out: None,
+            fee_lamports: None,
+            landing_ms: sent_at.elapsed().as_millis(),
+        };
+        if let Some(session_stats) = &self.session_stats {
+            if result.confirmed {
+                match self::measure_fill(
+                    rpc_client,
+                    &result.signature,
+                    &wallet.pubkey(),
+                    &output_token_mint,
+                    session.is_some(),
+                )
+                .await
+                {
+                    Ok((amount_out, fee)) => {
+                        result.amount_out = Some(amount_out);
+                        result.fee_lamports = Some(fee);
+                    }
+                    Err(e) => warn!("measure fill {}: {}", signature, e),
+                }
+            }
+            session_stats.record(&result);
+        }
+        if let (Some(position_tracker), true) =
+            (&self.position_tracker, result.confirmed)
+        {
+            match crate::tx_parser::fetch_tx(rpc_client, &result.signature)
+                .await
+                .and_then(|tx| crate::tx_parser::parse_swap(&tx))
+            {
+                Ok(swap) => {
+                    position_tracker.record(&swap);
+                }
+                Err(e) => warn!("track position {}: {}", signature, e),
+            }
+        }
+        emit(if result.confirmed {
+            SwapEvent::Confirmed {
+                signature: result.signature.clone(),
+                remaining_blocks: result.remaining_blocks,
+            }
+        } else {
+            SwapEvent::Expired {
+                signature: result.signature.clone(),
+            }
+        });
+        Ok(Some(result))
+    }
+
+    /// twap_buy splits swap_args.amount into slices executed interval
+    /// apart, each slice is re-quoted against the current reserves
+    pub async fn twap_buy(
+        &self,
+        swap_args: SwapArgs,
+        slices: u32,
+        interval: std::time::Duration,
+        config: TwapConfig,
+    ) -> Result<TwapResult, Box<dyn Error>> {
+        if slices == 0 {
+            return Err("slices has to be at least 1".into());
+        }
+        if slices as u64 > swap_args.amount {
+            return Err(format!(
+                "{} slices of {} would leave empty slices",
+                slices, swap_args.amount
+            )
+            .into());
+        }
+        let attempts = match config.on_failure {
+            TwapFailurePolicy::Retry { attempts } => attempts + 1,
+            TwapFailurePolicy::AbortRemaining => 1,
+        };
+        let parts = split_amount(swap_args.amount, slices);
+        let mut args = swap_args;
+        let mut filled = Vec::with_capacity(parts.len());
+        for (i, amount_in) in parts.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(interval).await;
+            }
+            args.amount = amount_in;
+            let mut slice = TwapSlice {
+                amount_in,
+                quoted_out: 0,
+                filled_out: None,
+                signature: None,
+                error: None,
+            };
+            for attempt in 0..attempts {
+                if attempt > 0 {
+                    tokio::time::sleep(config.retry_delay).await;
+                }
+                match self.twap_slice(&args).await {
+                    Ok((quoted_out, signature, filled_out)) => {
+                        slice.quoted_out = quoted_out;
+                        slice.filled_out = filled_out;
+                        slice.signature = Some(signature);
+                        slice.error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "twap slice {}/{} attempt {}: {}",
+                            i + 1,
+                            slices,
+                            attempt + 1,
+                            e
+                        );
+                        slice.error = Some(e.to_string());
+                    }
+                }
+            }
+            let failed = slice.signature.is_none();
+            filled.push(slice);
+            if failed {
+                warn!("twap aborted after slice {}/{}", i + 1, slices);
+                break;
+            }
+        }
+        let result = TwapResult::from_slices(filled);
+        info!("{}", serde_json::to_string_pretty(&result)?);
+        Ok(result)
+    }
+
+    /// liquidate_all sells every Liquidation to SOL in sequence, with
+    /// swap_args as the template (wallet, rpc, slippage, confirmed); a
+    /// failed sell does not stop the batch. The shared WSOL account (see
+    /// LiquidationConfig) is closed at the end no matter how the sells went,
+    /// a WSOL session already open for the wallet is used and left open
+    pub async fn liquidate_all(
+        &self,
+        swap_args: SwapArgs,
+        sells: &[Liquidation],
+        config: LiquidationConfig,
+    ) -> Result<LiquidationReport, Box<dyn Error>> {
+        let owner = swap_args.wallet.pubkey();
+        let mut report = LiquidationReport::default();
+        // the account is only created by the first sell that lands, an
+        // expired one leaves it to the next, see WsolSession::landed
+        let own_session = if config.shared_wsol {
+            let (account, started) = self.open_session(&owner).await;
+            report.wsol_account = Some(account.to_string());
+            started
+        } else {
+            false
+        };
+        let mut args = swap_args;
+        args.output_token_mint = constants::SOLANA_PROGRAM_ID;
+        for (i, sell) in sells.iter().enumerate() {
+            args.amm_pool = sell.amm_pool;
+            args.input_token_mint = sell.mint;
+            args.amount = sell.amount;
+            let mut sold = LiquidatedSell {
+                mint: sell.mint.to_string(),
+                amount: sell.amount,
+                signature: None,
+                error: None,
+            };
+            match self.execute_swap(&args).await {
+                Ok(Some(result)) if result.confirmed => {
+                    sold.signature = Some(result.signature)
+                }
+                Ok(Some(result)) => {
+                    sold.error = Some(format!("{} expired", result.signature))
+                }
+                Ok(None) => sold.error = Some("declined".to_string()),
+                Err(e) => sold.error = Some(e.to_string()),
+            }
+            if let Some(error) = &sold.error {
+                warn!(
+                    "liquidation {}/{} of {}: {}",
+                    i + 1,
+                    sells.len(),
+                    sell.mint,
+                    error
+                );
+            }
+            report.sells.push(sold);
+        }
+        if own_session {
+            for attempt in 0..WSOL_CLOSE_ATTEMPTS {
+                match self.finish_session(&args.rpc_client, &args.wallet).await
+                {
+                    Ok(signature) => {
+                        report.wsol_close = signature.map(|s| s.to_string());
+                        report.wsol_close_error = None;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "closing WSOL account, attempt {}: {}",
+                            attempt + 1,
+                            e
+                        );
+                        report.wsol_close_error = Some(e.to_string());
+                        tokio::time::sleep(std::time::Duration::from_secs(1))
+                            .await;
+                    }
+                }
+            }
+        }
+        info!("{}", serde_json::to_string_pretty(&report)?);
+        Ok(report)
+    }
+
+    /// twap_slice returns the quote, the signature and what was filled
+    async fn twap_slice(
+        &self,
+        args: &SwapArgs,
+    ) -> Result<(u64, String, Option<u64>), Box<dyn Error>> {
+        let quoted_out = self
+            .quote(
+                &args.rpc_client,
+                &args.amm_pool,
+                &args.input_token_mint,
+                &args.output_token_mint,
+                args.amount,
+            )
+            .await?;
+        let result = self.execute_swap(args).await?.ok_or("slice declined")?;
+        if !result.confirmed {
+            return Err(format!("slice {} expired", result.signature).into());
+        }
+        // the session stats have measured it already if on
+        let filled_out = match result.amount_out {
+            Some(amount_out) => Some(amount_out),
+            None => match self::measure_fill(
+                &args.rpc_client,
+                &result.signature,
+                &args.wallet.pubkey(),
+                &args.output_token_mint,
+                self.in_session(&args.wallet.pubkey()).await,
+            )
+            .await
+            {
+                Ok((amount_out, _)) => Some(amount_out),
+                Err(e) => {
+                    warn!("measure slice {}: {}", result.signature, e);
+                    None
+                }
+            },
+        };
+        Ok((quoted_out, result.signature, filled_out))
+    }
+}
+
+pub async fn handle_token_account(
+    swap: &mut Swap,
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+    amount: u64,
     owner: &Pubkey,
     funding: &Pubkey,
+    token_program: &Pubkey,
+    skip_existing_ata: bool,
 ) -> Result<Pubkey, Box<dyn Error>> {
     // two cases - an account is a token account or a native account (WSOL)
     if mint.eq(&constants::SOLANA_PROGRAM_ID) {
@@ -674,13 +3968,29 @@ pub async fn handle_token_account(
         swap.post_swap_instructions.append(&mut close_ixs);
         Ok(token)
     } else {
-        let token =
-            &spl_associated_token_account::get_associated_token_address(
-                owner, mint,
-            );
-        let mut ata_ixs =
-            common::create_ata_token_or_not(funding, mint, owner);
-        swap.pre_swap_instructions.append(&mut ata_ixs);
+        let token = &get_associated_token_address_with_program_id(
+            owner,
+            mint,
+            token_program,
+        );
+        if skip_existing_ata
+            && rpc_client
+                .get_account_with_commitment(token, rpc_client.commitment())
+                .await?
+                .value
+                .is_some()
+        {
+            debug!("ata {} exists, skipping create", token);
+            return Ok(*token);
+        }
+        swap.pre_swap_instructions.push(
+            create_associated_token_account_idempotent(
+                funding,
+                owner,
+                mint,
+                token_program,
+            ),
+        );
         Ok(*token)
     }
 }
@@ -727,10 +4037,794 @@ pub fn make_compute_budget_ixs(
     ]
 }
 
-pub fn make_priority_compute_budget_ixs(
-    _provider: &Provider,
-    _addressess: &[Pubkey],
-) -> Vec<Instruction> {
-    // let res = provider.rpc_client.get_recent_prioritization_fees(addresses).unwrap();
-    vec![]
+/// PriorityFeeConfig tunes the priority fee estimation, the fee is the
+/// `percentile` of the fees paid in the last `lookback_slots` slots, scaled
+/// by `multiplier` and clamped to [min, max] (micro-lamports per CU)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityFeeConfig {
+    pub lookback_slots: usize,
+    pub percentile: u8,
+    pub min: u64,
+    pub max: u64,
+    pub multiplier: f64,
+}
+
+impl Default for PriorityFeeConfig {
+    fn default() -> Self {
+        PriorityFeeConfig {
+            lookback_slots: 20,
+            percentile: 50,
+            min: 1_000,
+            max: 1_000_000,
+            multiplier: 1.,
+        }
+    }
+}
+
+/// priority_fee_from_samples applies the config to (slot, fee) samples
+pub fn priority_fee_from_samples(
+    samples: &[(u64, u64)],
+    config: &PriorityFeeConfig,
+) -> u64 {
+    let mut samples = samples.to_vec();
+    samples.sort_by_key(|(slot, _)| std::cmp::Reverse(*slot));
+    let mut fees = samples
+        .iter()
+        .take(config.lookback_slots.max(1))
+        .map(|(_, fee)| *fee)
+        .collect::<Vec<u64>>();
+    if fees.is_empty() {
+        return config.min;
+    }
+    fees.sort_unstable();
+    let percentile = config.percentile.min(100) as usize;
+    let idx = ((fees.len() - 1) * percentile + 50) / 100;
+    let fee = (fees[idx] as f64 * config.multiplier) as u64;
+    fee.clamp(config.min, config.max.max(config.min))
+}
+
+/// estimate_priority_fee samples the recent prioritization fees of txs
+/// locking the addresses
+pub async fn estimate_priority_fee(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+    config: &PriorityFeeConfig,
+) -> Result<u64, Box<dyn Error>> {
+    let samples = rpc_client
+        .get_recent_prioritization_fees(addresses)
+        .await?
+        .iter()
+        .map(|fee| (fee.slot, fee.prioritization_fee))
+        .collect::<Vec<(u64, u64)>>();
+    let samples = if samples.is_empty() {
+        // e.g. a fresh node, fall back to the learned samples
+        crate::provider::learned_fee_samples()
+    } else {
+        crate::provider::remember_fee_samples(&samples);
+        samples
+    };
+    let fee = priority_fee_from_samples(&samples, config);
+    debug!("priority fee {} from {} samples", fee, samples.len());
+    Ok(fee)
+}
+
+pub async fn make_priority_compute_budget_ixs(
+    rpc_client: &RpcClient,
+    addresses: &[Pubkey],
+    config: &PriorityFeeConfig,
+    max_units: u32,
+) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let price = estimate_priority_fee(rpc_client, addresses, config).await?;
+    Ok(make_compute_budget_ixs(price, max_units))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arb_output(buy: &Reserves, sell: &Reserves, amount_in: f64) -> f64 {
+        let base_out = (1. - buy.fee) * buy.base as f64 * amount_in
+            / (buy.quote as f64 + (1. - buy.fee) * amount_in);
+        (1. - sell.fee) * sell.quote as f64 * base_out
+            / (sell.base as f64 + (1. - sell.fee) * base_out)
+    }
+
+    #[test]
+    fn test_optimal_arb_amount_same_price() {
+        let pool = Reserves {
+            quote: 100_000_000_000,
+            base: 1_000_000_000_000,
+            fee: 0.0025,
+        };
+        assert!(optimal_arb_amount(&pool, &pool).is_none());
+    }
+
+    #[test]
+    fn test_optimal_arb_amount_maximizes_profit() {
+        let buy = Reserves {
+            quote: 100_000_000_000,
+            base: 1_000_000_000_000,
+            fee: 0.0025,
+        };
+        let sell = Reserves {
+            quote: 110_000_000_000,
+            base: 1_000_000_000_000,
+            fee: 0.0025,
+        };
+        let (amount_in, amount_out) =
+            optimal_arb_amount(&buy, &sell).expect("profitable");
+        assert!(amount_out > amount_in);
+        // the wrong way around is never profitable
+        assert!(optimal_arb_amount(&sell, &buy).is_none());
+
+        let profit = |x: f64| arb_output(&buy, &sell, x) - x;
+        let optimum = profit(amount_in as f64);
+        assert!(optimum > profit(amount_in as f64 * 0.95));
+        assert!(optimum > profit(amount_in as f64 * 1.05));
+    }
+
+    #[test]
+    fn test_optimal_arb_amount_fees_eat_the_spread() {
+        let buy = Reserves {
+            quote: 100_000_000_000,
+            base: 1_000_000_000_000,
+            fee: 0.0025,
+        };
+        // 0.3% price difference, less than two 0.25% fees
+        let sell = Reserves {
+            quote: 100_300_000_000,
+            base: 1_000_000_000_000,
+            fee: 0.0025,
+        };
+        assert!(optimal_arb_amount(&buy, &sell).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_decimals_override_skips_rpc() {
+        let mint = Pubkey::new_unique();
+        let raydium = Raydium::builder().decimals_override(mint, 4).build();
+        // unreachable endpoint, the override must not hit the network
+        let rpc_client = RpcClient::new("http://127.0.0.1:1".to_string());
+        let decimals = raydium
+            .decimals(&rpc_client, &mint)
+            .await
+            .expect("override");
+        assert_eq!(decimals, 4);
+        let min_out = raydium
+            .to_raw(&rpc_client, &mint, 1.23456)
+            .await
+            .expect("override");
+        assert_eq!(min_out, 12_346);
+        assert_eq!(ui_to_raw(-1., 9), 0);
+    }
+
+    #[test]
+    fn test_check_min_out() {
+        assert!(check_min_out(9_500, 10_000).is_none());
+        // above expected
+        assert!(check_min_out(10_001, 10_000).is_some());
+        // 5bps of slack is tight
+        assert!(check_min_out(9_995, 10_000).is_some());
+        // 30% of slack is loose
+        assert!(check_min_out(7_000, 10_000).is_some());
+        assert!(check_min_out(1, 0).is_some());
+    }
+
+    #[test]
+    fn test_raydium_program_registry() {
+        for program in RaydiumProgram::ALL {
+            assert_eq!(
+                RaydiumProgram::from_program_id(&program.program_id()),
+                Some(program)
+            );
+        }
+        assert_eq!(
+            RaydiumProgram::from_program_id(&constants::OPENBOOK_PROGRAM_ID),
+            None
+        );
+    }
+
+    #[test]
+    fn test_recommend_slippage_bps() {
+        let deep_old_pool = SlippageParams {
+            reserve_in: 10_000 * 10u64.pow(9),
+            amount_in: 10u64.pow(9),
+            sol_reserve: 10_000 * 10u64.pow(9),
+            priority_fee: 200_000,
+            pool_age_secs: 30 * 86_400,
+            fills_per_minute: 1.,
+        };
+        let thin_new_pool = SlippageParams {
+            reserve_in: 20 * 10u64.pow(9),
+            sol_reserve: 20 * 10u64.pow(9),
+            pool_age_secs: 600,
+            ..deep_old_pool.clone()
+        };
+        let deep = recommend_slippage_bps(&deep_old_pool);
+        let thin = recommend_slippage_bps(&thin_new_pool);
+        assert!(deep < thin);
+        assert!(deep >= MIN_RECOMMENDED_SLIPPAGE_BPS);
+        assert!(thin <= MAX_RECOMMENDED_SLIPPAGE_BPS);
+
+        // a lower fee means more slots to land, more room is needed
+        let cheap = recommend_slippage_bps(&SlippageParams {
+            priority_fee: 0,
+            fills_per_minute: 20.,
+            ..deep_old_pool.clone()
+        });
+        let pricey = recommend_slippage_bps(&SlippageParams {
+            fills_per_minute: 20.,
+            ..deep_old_pool.clone()
+        });
+        assert!(cheap > pricey);
+
+        let empty = SlippageParams::default();
+        assert_eq!(
+            recommend_slippage_bps(&empty),
+            MAX_RECOMMENDED_SLIPPAGE_BPS
+        );
+    }
+
+    #[test]
+    fn test_swap_config_precedence() {
+        std::env::set_var("LISTEN_TEST_SETTING", "7");
+        // config wins over env
+        assert_eq!(resolve_setting(Some(1u64), "LISTEN_TEST_SETTING", 0), 1);
+        // env wins over default
+        assert_eq!(resolve_setting(None, "LISTEN_TEST_SETTING", 0u64), 7);
+        std::env::set_var("LISTEN_TEST_SETTING", "seven");
+        assert_eq!(resolve_setting(None, "LISTEN_TEST_SETTING", 3u64), 3);
+        std::env::remove_var("LISTEN_TEST_SETTING");
+        assert_eq!(resolve_setting(None, "LISTEN_TEST_SETTING", 3u64), 3);
+    }
+
+    #[test]
+    fn test_pool_snapshot_diff() {
+        let a = PoolSnapshot {
+            timestamp: 100,
+            coin_reserve: 1_000,
+            pc_reserve: 2_000,
+            price: 2.,
+            swap_coin_in_amount: 10,
+            swap_pc_out_amount: 20,
+            ..Default::default()
+        };
+        let b = PoolSnapshot {
+            timestamp: 160,
+            coin_reserve: 900,
+            pc_reserve: 2_250,
+            price: 2.5,
+            swap_coin_in_amount: 60,
+            swap_coin_out_amount: 100,
+            swap_pc_in_amount: 250,
+            swap_pc_out_amount: 120,
+            ..Default::default()
+        };
+        let diff = PoolSnapshot::diff(&a, &b);
+        assert_eq!(diff.elapsed_secs, 60);
+        assert_eq!(diff.coin_reserve_delta, -100);
+        assert_eq!(diff.pc_reserve_delta, 250);
+        assert!((diff.price_change_pct - 25.).abs() < 1e-9);
+        assert_eq!(diff.volume_coin, 150);
+        assert_eq!(diff.volume_pc, 350);
+    }
+
+    #[test]
+    fn test_priority_fee_from_samples() {
+        let samples = (1..=100).map(|i| (i, i * 1_000)).collect::<Vec<_>>();
+        let config = PriorityFeeConfig {
+            lookback_slots: 10,
+            percentile: 50,
+            min: 0,
+            max: u64::MAX,
+            multiplier: 1.,
+        };
+        // the last 10 slots pay 91k..=100k
+        assert_eq!(priority_fee_from_samples(&samples, &config), 96_000);
+        let p100 = PriorityFeeConfig {
+            percentile: 100,
+            ..config.clone()
+        };
+        assert_eq!(priority_fee_from_samples(&samples, &p100), 100_000);
+        let scaled = PriorityFeeConfig {
+            multiplier: 2.,
+            max: 150_000,
+            ..config.clone()
+        };
+        assert_eq!(priority_fee_from_samples(&samples, &scaled), 150_000);
+        let floor = PriorityFeeConfig {
+            min: 5_000,
+            ..config.clone()
+        };
+        assert_eq!(priority_fee_from_samples(&[], &floor), 5_000);
+    }
+
+    #[test]
+    fn test_check_liquidity() {
+        // 1% slippage needs 99x the trade size in the pool
+        assert!(check_liquidity(99_000, 1_000, 100).is_ok());
+        match check_liquidity(98_999, 1_000, 100) {
+            Err(RaydiumError::InsufficientLiquidity {
+                required,
+                available,
+            }) => {
+                assert_eq!(required, 99_000);
+                assert_eq!(available, 98_999);
+            }
+            _ => panic!("expected insufficient liquidity"),
+        }
+        assert!(check_liquidity(u64::MAX - 1, 1, 0).is_err());
+        assert!(check_liquidity(0, 1_000, 10_000).is_ok());
+    }
+
+    #[test]
+    fn test_liquidity_tolerance_bps() {
+        assert_eq!(liquidity_tolerance_bps(None, 10_000, true, 800), 800);
+        // an explicit min_out wins over a slippage of 0
+        let bps = liquidity_tolerance_bps(Some(9_900), 10_000, true, 0);
+        assert_eq!(bps, 100);
+        assert!(check_liquidity(99_000, 1_000, bps).is_ok());
+        // and over a looser default slippage
+        assert_eq!(
+            liquidity_tolerance_bps(Some(9_990), 10_000, true, 800),
+            10
+        );
+        // the max input of a swap base out
+        assert_eq!(
+            liquidity_tolerance_bps(Some(10_200), 10_000, false, 0),
+            200
+        );
+        assert_eq!(liquidity_tolerance_bps(Some(10_001), 10_000, true, 0), 0);
+        assert_eq!(liquidity_tolerance_bps(Some(1), 0, true, 800), 0);
+    }
+
+    #[test]
+    fn test_split_amount() {
+        assert_eq!(split_amount(10, 3), vec![3, 3, 4]);
+        assert_eq!(split_amount(9, 3), vec![3, 3, 3]);
+        // twap_buy rejects more slices than amount
+        assert_eq!(split_amount(2, 3), vec![0, 0, 2]);
+        assert!(split_amount(10, 0).is_empty());
+        assert_eq!(split_amount(u64::MAX, 7).iter().sum::<u64>(), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_open_session() {
+        let raydium = Raydium::new();
+        let wallet = Keypair::new();
+        let (account, started) = raydium.open_session(&wallet.pubkey()).await;
+        assert!(started);
+        assert_eq!(
+            raydium.open_session(&wallet.pubkey()).await,
+            (account, false)
+        );
+        assert_eq!(raydium.start_session(&wallet.pubkey()).await, account);
+        // no sell landed, there is nothing to close and no request is made
+        let rpc_client = RpcClient::new("http://127.0.0.1:1".to_string());
+        let closed = raydium.finish_session(&rpc_client, &wallet).await;
+        assert!(closed.unwrap().is_none());
+        assert!(!raydium.in_session(&wallet.pubkey()).await);
+    }
+
+    #[test]
+    fn test_fill_of_session_output() {
+        use crate::tx_parser::BalanceDelta;
+
+        let sol = constants::SOLANA_PROGRAM_ID;
+        // a sell to a temp WSOL account, closed into the wallet
+        let closed = BalanceDelta {
+            sol_delta: 995_000,
+            token_delta: 0,
+            fee: 5_000,
+        };
+        assert_eq!(fill_of(&closed, &sol, false), (1_000_000, 5_000));
+        // a sell to the session account, the SOL stays as WSOL
+        let kept = BalanceDelta {
+            sol_delta: -5_000,
+            token_delta: 1_000_000,
+            fee: 5_000,
+        };
+        assert_eq!(fill_of(&kept, &sol, true), (1_000_000, 5_000));
+        assert_eq!(fill_of(&kept, &sol, false), (0, 5_000));
+        let token = Pubkey::new_unique();
+        assert_eq!(fill_of(&kept, &token, false), (1_000_000, 5_000));
+    }
+
+    #[test]
+    fn test_wsol_session_opened_once_landed() {
+        use solana_sdk::transaction::TransactionError;
+        let confirmation = |confirmed, err| crate::Confirmation {
+            confirmed,
+            err,
+            block_height: 100,
+            last_valid_block_height: 150,
+        };
+        let mut session = WsolSession::new(&Pubkey::new_unique());
+        // the first swap expired, the account was never created
+        session.landed(&confirmation(false, None));
+        assert!(!session.snapshot().opened);
+        // or landed with an error, rolling back the create
+        session.landed(&confirmation(
+            true,
+            Some(TransactionError::InsufficientFundsForFee),
+        ));
+        assert!(!session.snapshot().opened);
+        session.landed(&confirmation(true, None));
+        assert!(session.snapshot().opened);
+    }
+
+    #[tokio::test]
+    async fn test_check_blocklist_cached() {
+        let (amm_pool, deployer) =
+            (Pubkey::new_unique(), Pubkey::new_unique());
+        let raydium = Raydium::builder()
+            .blocklist(Arc::new(Blocklist::new([deployer])))
+            .build();
+        raydium.pool_associates_cache.write().unwrap().insert(
+            amm_pool,
+            (std::time::Instant::now(), vec![("deployer", deployer)]),
+        );
+        // unreachable endpoint, the cached associates are used
+        let rpc_client = RpcClient::new("http://127.0.0.1:1".to_string());
+        match raydium.check_blocklist(&rpc_client, &amm_pool).await {
+            Err(e) => match e.downcast_ref::<RaydiumError>() {
+                Some(RaydiumError::Blocklisted { role, .. }) => {
+                    assert_eq!(*role, "deployer")
+                }
+                _ => panic!("expected blocklisted, got {}", e),
+            },
+            Ok(()) => panic!("expected blocklisted"),
+        }
+        // an unknown deployer is cached too
+        raydium
+            .pool_deployers
+            .write()
+            .unwrap()
+            .insert(amm_pool, None);
+        assert_eq!(
+            raydium.pool_deployer(&rpc_client, &amm_pool).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_twap_buy_rejects_empty_slices() {
+        let args = SwapArgs {
+            amm_pool: Pubkey::new_unique(),
+            input_token_mint: constants::SOLANA_PROGRAM_ID,
+            output_token_mint: Pubkey::new_unique(),
+            amount: 2,
+            slippage: 100,
+            wallet: Keypair::new(),
+            // unreachable endpoint, rejected before any request
+            rpc_client: RpcClient::new("http://127.0.0.1:1".to_string()),
+            confirmed: true,
+            no_sanity: false,
+            min_out: None,
+        };
+        let err = Raydium::new()
+            .twap_buy(
+                args,
+                3,
+                std::time::Duration::ZERO,
+                TwapConfig::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("empty slices"));
+    }
+
+    #[test]
+    fn test_twap_result_skips_failed_slices() {
+        let slice = |amount_in, filled_out: Option<u64>, filled: bool| {
+            TwapSlice {
+                amount_in,
+                // the quotes do not count, only what was filled
+                quoted_out: 1_000,
+                filled_out,
+                signature: filled.then(|| "sig".to_string()),
+                error: None,
+            }
+        };
+        let result = TwapResult::from_slices(vec![
+            slice(100, Some(50), true),
+            slice(100, Some(40), true),
+            slice(100, None, true),
+            slice(100, None, false),
+        ]);
+        assert_eq!(result.total_in, 300);
+        assert_eq!(result.total_out, 90);
+        assert!((result.avg_price - 200. / 90.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_check_quote_drift() {
+        assert!(check_quote_drift(10_000, 10_000, 0).is_ok());
+        assert!(check_quote_drift(10_000, 12_000, 0).is_ok());
+        assert!(check_quote_drift(10_000, 9_900, 100).is_ok());
+        match check_quote_drift(10_000, 9_899, 100) {
+            Err(RaydiumError::QuoteDrift { drift_bps, .. }) => {
+                assert_eq!(drift_bps, 100)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(check_quote_drift(10_000, 9_800, 100).is_err());
+    }
+
+    #[test]
+    fn test_lp_token_price_sol() {
+        // 100 SOL in the pool, 1000 LP tokens with 6 decimals
+        let price = lp_token_price_sol(100_000_000_000, 1_000_000_000, 6);
+        assert!((price.unwrap() - 0.2).abs() < 1e-12);
+        assert!(lp_token_price_sol(100, 0, 6).is_none());
+    }
+
+    #[test]
+    fn test_raydium_builder() {
+        let mint = Pubkey::new_unique();
+        let raydium = Raydium::builder()
+            .decimals_override(mint, 3)
+            .cu_price(1_000)
+            .max_drift_bps(50)
+            .build();
+        assert_eq!(raydium.decimals_overrides.get(&mint), Some(&3));
+        assert_eq!(raydium.swap_config.cu_price(), 1_000);
+        assert_eq!(raydium.max_drift_bps, Some(50));
+        assert!(raydium.lag_check.is_none());
+    }
+
+    #[test]
+    fn test_apply_slippage() {
+        use SlippageRounding::{Ceil, Floor};
+        // (expected_out, slippage_bps, floor, ceil)
+        let pinned = [
+            (1_000_000, 50, 995_000, 995_000),
+            (999_999, 50, 994_999, 995_000),
+            (12_345, 800, 11_357, 11_358),
+            (1, 100, 0, 1),
+            (1_000, 0, 1_000, 1_000),
+            (1_000, 10_000, 0, 0),
+            (1_000, 20_000, 0, 0),
+            (
+                u64::MAX,
+                1,
+                18_444_899_399_302_180_659,
+                18_444_899_399_302_180_660,
+            ),
+        ];
+        for (expected_out, slippage_bps, floor, ceil) in pinned {
+            assert_eq!(
+                apply_slippage(expected_out, slippage_bps, Floor),
+                floor,
+                "floor of {} at {}bps",
+                expected_out,
+                slippage_bps
+            );
+            assert_eq!(
+                apply_slippage(expected_out, slippage_bps, Ceil),
+                ceil,
+                "ceil of {} at {}bps",
+                expected_out,
+                slippage_bps
+            );
+        }
+        assert_eq!(SwapConfig::default().slippage_rounding(), Floor);
+        assert_eq!("Ceil".parse::<SlippageRounding>(), Ok(Ceil));
+        assert!("up".parse::<SlippageRounding>().is_err());
+    }
+
+    #[test]
+    fn test_cpmm_quote() {
+        let mut data = vec![0u8; CPMM_POOL_LEN as usize];
+        let (mint_0, mint_1) = (Pubkey::new_unique(), Pubkey::new_unique());
+        data[168..200].copy_from_slice(&mint_0.to_bytes());
+        data[200..232].copy_from_slice(&mint_1.to_bytes());
+        data[341..349].copy_from_slice(&10u64.to_le_bytes());
+        data[357..365].copy_from_slice(&5u64.to_le_bytes());
+        let pool = CpmmPool::parse(&data).unwrap();
+        assert_eq!((pool.mint_0, pool.mint_1), (mint_0, mint_1));
+        assert_eq!((pool.fees_0, pool.fees_1), (15, 0));
+
+        // 0.25% off the input, then x * y = k
+        assert_eq!(cpmm_quote(1_000_000, 2_000_000, 10_000, 2_500), 19_752);
+        // the fee rounds up
+        assert_eq!(cpmm_quote(1_000_000, 1_000_000, 1, 2_500), 0);
+        assert_eq!(cpmm_quote(0, 1_000, 0, 2_500), 0);
+    }
+
+    #[test]
+    fn test_validate_swap_ixs() {
+        let program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY;
+        let swap_ix = |tag: u8, amount: u64, threshold: u64| {
+            let mut data = vec![tag];
+            data.extend_from_slice(&amount.to_le_bytes());
+            data.extend_from_slice(&threshold.to_le_bytes());
+            Instruction::new_with_bytes(program, &data, vec![])
+        };
+        let other =
+            Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+
+        let ixs = vec![other.clone(), swap_ix(9, 1_000, 500)];
+        let data = validate_swap_ixs(&ixs, 1_000, Some(500)).unwrap();
+        assert_eq!(
+            data,
+            SwapIxData {
+                swap_base_in: true,
+                amount: 1_000,
+                other_amount_threshold: 500,
+            }
+        );
+        // within rounding of the intended min_out
+        assert!(validate_swap_ixs(&ixs, 1_000, Some(501)).is_ok());
+        assert!(validate_swap_ixs(&ixs, 1_000, None).is_ok());
+
+        let mismatch =
+            |ixs: &[Instruction], amount, min_out| match validate_swap_ixs(
+                ixs, amount, min_out,
+            ) {
+                Err(RaydiumError::InstructionMismatch { field, .. }) => field,
+                other => panic!("unexpected {:?}", other),
+            };
+        assert_eq!(mismatch(&ixs, 1_001, None), "amount_in");
+        assert_eq!(mismatch(&ixs, 1_000, Some(510)), "min_out");
+        assert_eq!(mismatch(&[swap_ix(11, 1_000, 500)], 1_000, None), "tag");
+        assert_eq!(mismatch(&[other], 1_000, None), "swap instructions");
+        assert!(decode_swap_ix_data(&[9, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_pool_overrides() {
+        let (stable, meme) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let raydium = Raydium::builder()
+            .cu_price(1_000)
+            .pool_override(
+                stable,
+                PoolOverrides {
+                    slippage_bps: Some(10),
+                    ..Default::default()
+                },
+            )
+            .pool_override(
+                meme,
+                PoolOverrides {
+                    slippage_bps: Some(1_500),
+                    cu_price: Some(50_000),
+                    min_liquidity: Some(10_000_000_000),
+                },
+            )
+            .build();
+        let overrides = raydium.pool_overrides(&stable);
+        assert_eq!(overrides.slippage_bps(100), 10);
+        assert_eq!(
+            overrides.swap_config(&raydium.swap_config).cu_price(),
+            1_000
+        );
+
+        let overrides = raydium.pool_overrides(&meme);
+        let swap_config = overrides.swap_config(&raydium.swap_config);
+        assert_eq!(swap_config.cu_price(), 50_000);
+        assert_eq!(swap_config.min_liquidity(), 10_000_000_000);
+        assert!(check_min_liquidity(9_999_999_999, 10_000_000_000).is_err());
+        assert!(check_min_liquidity(10_000_000_000, 10_000_000_000).is_ok());
+
+        // pools without overrides take the global settings
+        let other = raydium.pool_overrides(&Pubkey::new_unique());
+        assert_eq!(other, PoolOverrides::default());
+        assert_eq!(other.slippage_bps(100), 100);
+    }
+
+    #[test]
+    fn test_lot_sizes() {
+        let mut data = vec![0u8; 388];
+        data[349..357].copy_from_slice(&1_000u64.to_le_bytes());
+        data[357..365].copy_from_slice(&10u64.to_le_bytes());
+        let lot_sizes = LotSizes::parse(&data).unwrap();
+        assert_eq!(lot_sizes.base_lot_size, 1_000);
+        assert_eq!(lot_sizes.quote_lot_size, 10);
+        assert_eq!(lot_sizes.base_to_lots(12_345), 12);
+        assert_eq!(lot_sizes.round_base(12_345), 12_000);
+        assert_eq!(lot_sizes.round_quote(12_345), 12_340);
+        assert_eq!(lot_sizes.quote_lots_to_native(3), 30);
+        assert!(LotSizes::parse(&data[..360]).is_err());
+        assert!(LotSizes::parse(&[0u8; 388]).is_err());
+    }
+
+    #[test]
+    fn test_fee_info() {
+        let info = FeeInfo::new(RaydiumProgram::AmmV4, 25, 10_000);
+        assert_eq!(info.tier, "0.25%");
+        assert_eq!(info.fee_bps(), 25.);
+
+        let config = Pubkey::new_unique();
+        let mut pool = vec![0u8; 64];
+        pool[8..40].copy_from_slice(config.as_ref());
+        assert_eq!(
+            fee_config_of(RaydiumProgram::Cpmm, &pool).unwrap(),
+            config
+        );
+        let mut cpmm = vec![0u8; 64];
+        cpmm[10..12].copy_from_slice(&2u16.to_le_bytes());
+        cpmm[12..20].copy_from_slice(&2_500u64.to_le_bytes());
+        let info =
+            parse_fee_config(RaydiumProgram::Cpmm, &config, &cpmm).unwrap();
+        assert_eq!((info.numerator, info.config_index), (2_500, Some(2)));
+        assert_eq!(info.tier, "0.25%");
+
+        let mut clmm = vec![0u8; 64];
+        clmm[9..11].copy_from_slice(&1u16.to_le_bytes());
+        clmm[47..51].copy_from_slice(&100u32.to_le_bytes());
+        let info =
+            parse_fee_config(RaydiumProgram::Clmm, &config, &clmm).unwrap();
+        assert_eq!(info.tier, "0.01%");
+        assert!(parse_fee_config(RaydiumProgram::Clmm, &config, &clmm[..40])
+            .is_err());
+    }
+
+    #[test]
+    fn test_round_trip_loss() {
+        // 800 of the 1000 quoted tokens sold back, 200 left at 1000 each
+        let (loss, pct) = round_trip_loss(1_000_000, 790_000, 200, 1_000);
+        assert_eq!(loss, 10_000);
+        assert!((pct - 1.).abs() < 1e-9);
+        // a 30% sell tax
+        let (_, pct) = round_trip_loss(1_000_000, 560_000, 200, 1_000);
+        assert!((pct - 24.).abs() < 1e-9);
+        assert_eq!(round_trip_loss(0, 0, 0, 0), (0, 0.));
+    }
+
+    #[tokio::test]
+    async fn test_until_cancelled() {
+        let cancel = CancellationToken::new();
+        let res = until_cancelled(Some(&cancel), async { Ok(1) }).await;
+        assert_eq!(res.unwrap(), 1);
+        cancel.cancel();
+        let res = until_cancelled(Some(&cancel), async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            Ok(1)
+        })
+        .await;
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<RaydiumError>(),
+            Some(RaydiumError::Cancelled)
+        ));
+        // without a token the future always runs
+        assert_eq!(until_cancelled(None, async { Ok(2) }).await.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_classify_invariant_change() {
+        let a = PoolInvariant::new(0, 1_000_000, 4_000_000, 2_000_000);
+        let tolerance = DEFAULT_INVARIANT_TOLERANCE_BPS;
+        assert_eq!(
+            classify_invariant_change(&a, &a, tolerance),
+            InvariantChange::Unchanged
+        );
+        // a swap in with the fee kept by the pool, k grows
+        let swapped = PoolInvariant::new(1, 1_100_000, 3_640_000, 2_000_000);
+        assert!(matches!(
+            classify_invariant_change(&a, &swapped, tolerance),
+            InvariantChange::FeeAccrual { growth_bps } if growth_bps > 0.
+        ));
+        // doubling the reserves mints double the LP
+        let added = PoolInvariant::new(1, 2_000_000, 8_000_000, 4_000_000);
+        assert_eq!(
+            classify_invariant_change(&a, &added, tolerance),
+            InvariantChange::LiquidityAdded {
+                lp_delta: 2_000_000
+            }
+        );
+        let removed = PoolInvariant::new(2, 500_000, 2_000_000, 1_000_000);
+        assert_eq!(
+            classify_invariant_change(&a, &removed, tolerance),
+            InvariantChange::LiquidityRemoved {
+                lp_delta: 1_000_000
+            }
+        );
+        // reserves halved without any LP burnt
+        let drained = PoolInvariant::new(2, 500_000, 2_000_000, 2_000_000);
+        assert!(matches!(
+            classify_invariant_change(&a, &drained, tolerance),
+            InvariantChange::Anomaly { lp_delta: 0, .. }
+        ));
+    }
 }
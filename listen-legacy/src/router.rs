@@ -0,0 +1,445 @@
+use std::error::Error;
+use std::time::Instant;
+
+use log::{info, warn};
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::jup::{Jupiter, QuoteResponse};
+use crate::raydium::{FeeInfo, PoolMatch, Raydium, RaydiumProgram, SwapArgs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Venue {
+    Raydium,
+    Jupiter,
+}
+
+/// Quote is the output a venue offers for the trade, `pool` is set for the
+/// direct Raydium pools, latency is the time it took to fetch the quote
+#[derive(Debug, Clone, Serialize)]
+pub struct Quote {
+    pub venue: Venue,
+    pub pool: Option<String>,
+    /// program of the direct pool, None for Jupiter
+    pub program: Option<RaydiumProgram>,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub latency_ms: u128,
+    /// fee of the direct pool, None for Jupiter or if it could not be read
+    pub fee: Option<FeeInfo>,
+}
+
+/// QuoteError is a venue that failed to quote
+#[derive(Debug, Clone, Serialize)]
+pub struct QuoteError {
+    pub venue: Venue,
+    pub pool: Option<String>,
+    pub error: String,
+    pub latency_ms: u128,
+}
+
+/// RouteComparison has the quotes of all of the venues ranked by the
+/// output (best first) and the venues that failed to quote
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteComparison {
+    pub quotes: Vec<Quote>,
+    pub errors: Vec<QuoteError>,
+}
+
+impl RouteComparison {
+    pub fn best(&self) -> Option<&Quote> {
+        self.quotes.first()
+    }
+}
+
+/// best_quote fetches the quotes of the given Raydium pools and Jupiter
+/// concurrently and ranks them, errors only if no venue could quote
+pub async fn best_quote(
+    rpc_client: &RpcClient,
+    raydium: &Raydium,
+    raydium_pools: &[Pubkey],
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount: u64,
+    slippage_bps: u16,
+) -> Result<RouteComparison, Box<dyn Error>> {
+    let raydium_quotes = raydium_pools.iter().map(|pool| async move {
+        let start = Instant::now();
+        let (res, fee) = futures_util::future::join(
+            raydium.quote(rpc_client, pool, input_mint, output_mint, amount),
+            raydium.fee_info(rpc_client, pool),
+        )
+        .await;
+        let latency = start.elapsed();
+        (
+            Venue::Raydium,
+            Some(PoolMatch {
+                program: RaydiumProgram::AmmV4,
+                pool: *pool,
+            }),
+            res,
+            latency,
+            fee.ok(),
+        )
+    });
+    let jupiter_quote = async {
+        let start = Instant::now();
+        let res = Jupiter::fetch_quote(
+            &input_mint.to_string(),
+            &output_mint.to_string(),
+            amount,
+            slippage_bps,
+        )
+        .await
+        .and_then(|quote| Ok(quote.out_amount.parse::<u64>()?));
+        (Venue::Jupiter, None, res, start.elapsed(), None)
+    };
+    let (mut results, jupiter) = futures_util::future::join(
+        futures_util::future::join_all(raydium_quotes),
+        jupiter_quote,
+    )
+    .await;
+    results.push(jupiter);
+    rank(results, input_mint, output_mint, amount)
+}
+
+type QuoteResult = (
+    Venue,
+    Option<PoolMatch>,
+    Result<u64, Box<dyn Error>>,
+    std::time::Duration,
+    Option<FeeInfo>,
+);
+
+fn rank(
+    results: Vec<QuoteResult>,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount: u64,
+) -> Result<RouteComparison, Box<dyn Error>> {
+    let mut comparison = RouteComparison::default();
+    for (venue, pool, res, latency, fee) in results {
+        match res {
+            Ok(amount_out) => comparison.quotes.push(Quote {
+                venue,
+                pool: pool.map(|pool| pool.pool.to_string()),
+                program: pool.map(|pool| pool.program),
+                input_mint: input_mint.to_string(),
+                output_mint: output_mint.to_string(),
+                amount_in: amount,
+                amount_out,
+                latency_ms: latency.as_millis(),
+                fee,
+            }),
+            Err(e) => comparison.errors.push(QuoteError {
+                venue,
+                pool: pool.map(|pool| pool.pool.to_string()),
+                error: e.to_string(),
+                latency_ms: latency.as_millis(),
+            }),
+        }
+    }
+    comparison
+        .quotes
+        .sort_by(|a, b| b.amount_out.cmp(&a.amount_out));
+    info!("{}", serde_json::to_string_pretty(&comparison)?);
+    if comparison.quotes.is_empty() {
+        return Err(format!(
+            "no venue could quote {} -> {}",
+            input_mint, output_mint
+        )
+        .into());
+    }
+    Ok(comparison)
+}
+
+/// compare_programs is a dry run of the pair on every AMM v4 and CPMM pool
+/// trading it, found with `find_pools_by_mint`, the quotes are ranked like
+/// `best_quote` and carry the program of their pool, nothing is sent
+pub async fn compare_programs(
+    rpc_client: &RpcClient,
+    raydium: &Raydium,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount: u64,
+) -> Result<RouteComparison, Box<dyn Error>> {
+    let pools = raydium
+        .find_pools_by_mint(
+            rpc_client,
+            input_mint,
+            output_mint,
+            &[RaydiumProgram::AmmV4, RaydiumProgram::Cpmm],
+        )
+        .await?;
+    if pools.is_empty() {
+        return Err(format!(
+            "no AMM v4 or CPMM pool trades {} -> {}",
+            input_mint, output_mint
+        )
+        .into());
+    }
+    let quotes = pools.iter().map(|pool| async move {
+        let start = Instant::now();
+        let quote = async {
+            match pool.program {
+                RaydiumProgram::Cpmm => {
+                    raydium
+                        .quote_cpmm(
+                            rpc_client,
+                            &pool.pool,
+                            input_mint,
+                            output_mint,
+                            amount,
+                        )
+                        .await
+                }
+                _ => {
+                    raydium
+                        .quote(
+                            rpc_client,
+                            &pool.pool,
+                            input_mint,
+                            output_mint,
+                            amount,
+                        )
+                        .await
+                }
+            }
+        };
+        let (res, fee) = futures_util::future::join(
+            quote,
+            raydium.fee_info(rpc_client, &pool.pool),
+        )
+        .await;
+        (Venue::Raydium, Some(*pool), res, start.elapsed(), fee.ok())
+    });
+    let results = futures_util::future::join_all(quotes).await;
+    rank(results, input_mint, output_mint, amount)
+}
+
+/// FallbackConfig decides when a Jupiter route is not trusted and the swap
+/// goes through the direct Raydium pool instead: no route, a quote older
+/// than `max_quote_age_slots`, a price impact above `max_price_impact_pct`
+/// or an output short of the Raydium one by more than `max_shortfall_bps`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FallbackConfig {
+    pub max_shortfall_bps: u64,
+    pub max_price_impact_pct: f64,
+    pub max_quote_age_slots: u64,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        FallbackConfig {
+            max_shortfall_bps: 50,
+            max_price_impact_pct: 5.,
+            max_quote_age_slots: 150,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum FallbackReason {
+    NoRoute { error: String },
+    Stale { age_slots: u64 },
+    PriceImpact { pct: f64 },
+    Worse { shortfall_bps: u64 },
+}
+
+/// RouteDecision is the venue the swap is executed on, with the reason if
+/// Jupiter was passed over
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteDecision {
+    pub venue: Venue,
+    pub fallback: Option<FallbackReason>,
+    pub jupiter_out: Option<u64>,
+    pub raydium_out: u64,
+}
+
+/// JupiterRoute is the part of a Jupiter quote the fallback looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JupiterRoute {
+    pub amount_out: u64,
+    pub price_impact_pct: f64,
+    pub context_slot: u64,
+}
+
+impl JupiterRoute {
+    pub fn from_quote(quote: &QuoteResponse) -> Result<Self, Box<dyn Error>> {
+        Ok(JupiterRoute {
+            amount_out: quote.out_amount.parse()?,
+            // the API returns the impact as a fraction
+            price_impact_pct: quote.price_impact_pct.parse::<f64>()? * 100.,
+            context_slot: quote.context_slot,
+        })
+    }
+}
+
+/// choose_route picks Jupiter unless the config rules out its route, the
+/// checks go in order no route, stale, price impact, worse output
+pub fn choose_route(
+    jupiter: Result<JupiterRoute, String>,
+    raydium_out: u64,
+    current_slot: u64,
+    config: &FallbackConfig,
+) -> RouteDecision {
+    let decision =
+        |fallback: Option<FallbackReason>, jupiter_out| RouteDecision {
+            venue: if fallback.is_some() {
+                Venue::Raydium
+            } else {
+                Venue::Jupiter
+            },
+            fallback,
+            jupiter_out,
+            raydium_out,
+        };
+    let route = match jupiter {
+        Ok(route) => route,
+        Err(error) => {
+            return decision(Some(FallbackReason::NoRoute { error }), None)
+        }
+    };
+    let out = Some(route.amount_out);
+    if route.amount_out == 0 {
+        let error = "zero output".to_string();
+        return decision(Some(FallbackReason::NoRoute { error }), out);
+    }
+    let age_slots = current_slot.saturating_sub(route.context_slot);
+    if age_slots > config.max_quote_age_slots {
+        return decision(Some(FallbackReason::Stale { age_slots }), out);
+    }
+    if route.price_impact_pct > config.max_price_impact_pct {
+        let pct = route.price_impact_pct;
+        return decision(Some(FallbackReason::PriceImpact { pct }), out);
+    }
+    if raydium_out > route.amount_out {
+        let shortfall_bps = ((raydium_out - route.amount_out) as u128 * 10_000
+            / raydium_out as u128) as u64;
+        if shortfall_bps > config.max_shortfall_bps {
+            return decision(
+                Some(FallbackReason::Worse { shortfall_bps }),
+                out,
+            );
+        }
+    }
+    decision(None, out)
+}
+
+/// RouteOutcome is the decision and the signature of the executed swap
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteOutcome {
+    pub decision: RouteDecision,
+    pub signature: Option<String>,
+}
+
+/// swap_with_fallback quotes Jupiter and the Raydium pool of swap_args
+/// concurrently and executes on Jupiter, unless `choose_route` falls back
+/// to Raydium; the Raydium quote failing fails the swap, there would be
+/// nothing to fall back to
+pub async fn swap_with_fallback(
+    raydium: &Raydium,
+    swap_args: SwapArgs,
+    slippage_bps: u16,
+    config: &FallbackConfig,
+) -> Result<RouteOutcome, Box<dyn Error>> {
+    let rpc_client = &swap_args.rpc_client;
+    let (jupiter_quote, raydium_out, current_slot) = tokio::join!(
+        Jupiter::fetch_quote(
+            &swap_args.input_token_mint.to_string(),
+            &swap_args.output_token_mint.to_string(),
+            swap_args.amount,
+            slippage_bps,
+        ),
+        raydium.quote(
+            rpc_client,
+            &swap_args.amm_pool,
+            &swap_args.input_token_mint,
+            &swap_args.output_token_mint,
+            swap_args.amount,
+        ),
+        rpc_client.get_slot(),
+    );
+    let raydium_out = raydium_out?;
+    let (jupiter, quote) = match jupiter_quote {
+        Ok(quote) => match JupiterRoute::from_quote(&quote) {
+            Ok(route) => (Ok(route), Some(quote)),
+            Err(e) => (Err(e.to_string()), None),
+        },
+        Err(e) => (Err(e.to_string()), None),
+    };
+    let decision = choose_route(jupiter, raydium_out, current_slot?, config);
+    match &decision.fallback {
+        Some(reason) => warn!(
+            "jupiter route rejected, falling back to raydium pool {}: {:?}",
+            swap_args.amm_pool, reason
+        ),
+        None => info!(
+            "routing via jupiter: {:?} (raydium {})",
+            decision.jupiter_out, raydium_out
+        ),
+    }
+    let signature = match (decision.venue, quote) {
+        (Venue::Jupiter, Some(quote)) => {
+            Some(Jupiter::swap(quote, &swap_args.wallet).await?)
+        }
+        _ => raydium
+            .execute_swap(&swap_args)
+            .await?
+            .map(|result| result.signature),
+    };
+    Ok(RouteOutcome {
+        decision,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_route() {
+        let config = FallbackConfig::default();
+        let route = JupiterRoute {
+            amount_out: 1_000_000,
+            price_impact_pct: 0.5,
+            context_slot: 100,
+        };
+        let decision = choose_route(Ok(route), 1_002_000, 120, &config);
+        assert_eq!(decision.venue, Venue::Jupiter);
+        assert_eq!(decision.fallback, None);
+
+        let decision = choose_route(Ok(route), 1_010_000, 120, &config);
+        assert_eq!(
+            decision.fallback,
+            Some(FallbackReason::Worse { shortfall_bps: 99 })
+        );
+        assert_eq!(decision.venue, Venue::Raydium);
+
+        let decision = choose_route(Ok(route), 1_000_000, 300, &config);
+        assert_eq!(
+            decision.fallback,
+            Some(FallbackReason::Stale { age_slots: 200 })
+        );
+
+        let impact = JupiterRoute {
+            price_impact_pct: 12.,
+            ..route
+        };
+        let decision = choose_route(Ok(impact), 1_000_000, 120, &config);
+        assert_eq!(
+            decision.fallback,
+            Some(FallbackReason::PriceImpact { pct: 12. })
+        );
+
+        let decision =
+            choose_route(Err("no route".to_string()), 900_000, 120, &config);
+        assert_eq!(decision.venue, Venue::Raydium);
+        assert_eq!(decision.jupiter_out, None);
+    }
+}
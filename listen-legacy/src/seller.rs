@@ -15,7 +15,7 @@ use solana_sdk::{
 };
 use spl_token::state::Mint;
 
-use crate::constants;
+use crate::{constants, prometheus::PoolMetrics};
 
 #[derive(Debug, Default)]
 pub struct VaultState {
@@ -32,13 +32,28 @@ pub struct Pool {
 }
 
 impl Pool {
+    /// is_synced is true once both vaults have been updated in the same slot
+    pub fn is_synced(&self) -> bool {
+        self.token_vault.amount != 0
+            && self.sol_vault.amount != 0
+            && self.sol_vault.slot != 0
+            && self.token_vault.slot != 0
+            && self.sol_vault.slot == self.token_vault.slot
+    }
+
+    /// try_sol_price returns the token price denominated in SOL
+    pub fn try_sol_price(&self) -> Option<f64> {
+        if !self.is_synced() {
+            return None;
+        }
+        let token_amount = self.token_vault.amount as f64
+            / 10u64.pow(self.token_vault.decimals as u32) as f64;
+        let sol_amount = self.sol_vault.amount as f64 / 10u64.pow(9) as f64;
+        Some(sol_amount / token_amount)
+    }
+
     pub fn try_price(&self) -> Option<f64> {
-        if self.token_vault.amount == 0
-            || self.sol_vault.amount == 0
-            || self.sol_vault.slot == 0
-            || self.token_vault.slot == 0
-            || self.sol_vault.slot != self.token_vault.slot
-        {
+        if !self.is_synced() {
             return None;
         }
         // decimals hard-coded as 6 (most-common), might lead to weird errors,
@@ -80,6 +95,30 @@ pub async fn listen_price(
     amm_pool: &Pubkey,
     rpc_client: &RpcClient,
     pubsub_client: &PubsubClient,
+) -> Result<bool, Box<dyn Error>> {
+    listen_price_with_metrics(amm_pool, rpc_client, pubsub_client, None).await
+}
+
+/// listen_price_with_metrics is listen_price that also exports the reserves
+/// and the SOL price of the pool as gauges, for as long as it is listening
+pub async fn listen_price_with_metrics(
+    amm_pool: &Pubkey,
+    rpc_client: &RpcClient,
+    pubsub_client: &PubsubClient,
+    metrics: Option<&PoolMetrics>,
+) -> Result<bool, Box<dyn Error>> {
+    listen_price_with(amm_pool, rpc_client, pubsub_client, metrics, |_| true)
+        .await
+}
+
+/// listen_price_with calls on_price with the SOL price of the pool on every
+/// tick where both vaults are synced, it stops once on_price returns false
+async fn listen_price_with(
+    amm_pool: &Pubkey,
+    rpc_client: &RpcClient,
+    pubsub_client: &PubsubClient,
+    metrics: Option<&PoolMetrics>,
+    mut on_price: impl FnMut(f64) -> bool,
 ) -> Result<bool, Box<dyn Error>> {
     // load amm keys
     let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY;
@@ -132,7 +171,24 @@ pub async fn listen_price(
             ))
         })?;
 
+    let gauges = metrics.map(|metrics| metrics.track(amm_pool));
+    let mut export = |pool: &Pool| {
+        let price = pool.try_sol_price();
+        if let Some(gauges) = &gauges {
+            gauges
+                .set_reserves(pool.token_vault.amount, pool.sol_vault.amount);
+            if let Some(price) = price {
+                gauges.set_price(price);
+            }
+        }
+        match price {
+            Some(price) => on_price(price),
+            None => true,
+        }
+    };
+
     let mut pool = Pool::default();
+    pool.token_vault.decimals = get_decimals(&token_mint, rpc_client).await;
     info!("listening for price for {}", token_mint.to_string());
     loop {
         tokio::select! {
@@ -153,8 +209,11 @@ pub async fn listen_price(
                         };
                         pool.token_vault.amount = account.amount;
                         pool.token_vault.slot = token_log.context.slot;
+                        if !export(&pool) {
+                            break;
+                        }
                         if let Some(price) = pool.try_price() {
-                            info!("price: {}", price);
+                            crate::throttled!(info, "price: {}", price);
                         }
                     }
                     _ => {
@@ -165,8 +224,11 @@ pub async fn listen_price(
             Some(sol_log) = sol_stream.next() => {
                 pool.sol_vault.amount = sol_log.value.lamports;
                 pool.sol_vault.slot = sol_log.context.slot;
+                if !export(&pool) {
+                    break;
+                }
                 if let Some(price) = pool.try_price() {
-                    info!("price: {}", price);
+                    crate::throttled!(info, "price: {}", price);
                 }
             }
             _ = tokio::time::sleep(tokio::time::Duration::from_secs(3000)) => {
@@ -177,6 +239,127 @@ pub async fn listen_price(
             }
         }
     }
+    token_unsub().await;
+    sol_unsub().await;
+    Ok(false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossDirection {
+    /// Above: the price moves from below the level to above it
+    Above,
+    /// Below: the price moves from above the level to below it
+    Below,
+}
+
+type CrossCallback = Box<dyn FnMut(f64) + Send>;
+
+struct CrossTrigger {
+    level: f64,
+    direction: CrossDirection,
+    callback: CrossCallback,
+    /// armed once the price has been seen on the starting side of the level
+    armed: bool,
+    /// consecutive ticks past the level
+    ticks: u32,
+}
+
+/// PriceFeed watches the spot price of a pool and fires the registered
+/// triggers, a trigger fires once and is dropped afterwards
+pub struct PriceFeed {
+    pub amm_pool: Pubkey,
+    /// debounce_ticks: how many consecutive ticks the price has to stay past
+    /// the level before the trigger fires
+    pub debounce_ticks: u32,
+    triggers: Vec<CrossTrigger>,
+}
+
+impl PriceFeed {
+    pub fn new(amm_pool: Pubkey) -> Self {
+        Self {
+            amm_pool,
+            debounce_ticks: 3,
+            triggers: vec![],
+        }
+    }
+
+    pub fn with_debounce(mut self, ticks: u32) -> Self {
+        self.debounce_ticks = ticks.max(1);
+        self
+    }
+
+    /// on_cross registers callback to be called once with the price that
+    /// crossed level in direction
+    pub fn on_cross(
+        &mut self,
+        level: f64,
+        direction: CrossDirection,
+        callback: impl FnMut(f64) + Send + 'static,
+    ) {
+        self.triggers.push(CrossTrigger {
+            level,
+            direction,
+            callback: Box::new(callback),
+            armed: false,
+            ticks: 0,
+        });
+    }
+
+    pub fn pending(&self) -> usize {
+        self.triggers.len()
+    }
+
+    /// push feeds a single price tick to the triggers
+    pub fn push(&mut self, price: f64) {
+        let amm_pool = self.amm_pool;
+        let debounce_ticks = self.debounce_ticks;
+        self.triggers.retain_mut(|trigger| {
+            let past = match trigger.direction {
+                CrossDirection::Above => price > trigger.level,
+                CrossDirection::Below => price < trigger.level,
+            };
+            if !past {
+                trigger.armed = true;
+                trigger.ticks = 0;
+                return true;
+            }
+            if !trigger.armed {
+                return true;
+            }
+            trigger.ticks += 1;
+            if trigger.ticks < debounce_ticks {
+                return true;
+            }
+            info!(
+                "{} crossed {:?} {}: {}",
+                amm_pool, trigger.direction, trigger.level, price
+            );
+            (trigger.callback)(price);
+            false
+        });
+    }
+
+    /// run listens on the pool until all of the triggers have fired or the
+    /// price stream times out
+    pub async fn run(
+        &mut self,
+        rpc_client: &RpcClient,
+        pubsub_client: &PubsubClient,
+    ) -> Result<(), Box<dyn Error>> {
+        let amm_pool = self.amm_pool;
+        listen_price_with(
+            &amm_pool,
+            rpc_client,
+            pubsub_client,
+            None,
+            |price| {
+                self.push(price);
+                self.pending() > 0
+            },
+        )
+        .await?;
+        Ok(())
+    }
 }
 
 pub fn clear() {
@@ -419,4 +602,35 @@ mod tests {
         );
         assert!(ata.to_string() == expected_addr);
     }
+
+    #[test]
+    fn test_on_cross_fires_once_after_debounce() {
+        use std::sync::{Arc, Mutex};
+
+        use super::{CrossDirection, PriceFeed};
+
+        let fired = Arc::new(Mutex::new(vec![]));
+        let mut feed = PriceFeed::new(Pubkey::default()).with_debounce(2);
+        let sink = fired.clone();
+        feed.on_cross(1.0, CrossDirection::Above, move |price| {
+            sink.lock().unwrap().push(price)
+        });
+        // starting above the level does not count as a cross
+        feed.push(1.2);
+        feed.push(1.3);
+        assert!(fired.lock().unwrap().is_empty());
+        // noise around the level does not fire
+        for price in [0.9, 1.01, 0.99, 1.02, 0.98] {
+            feed.push(price);
+        }
+        assert!(fired.lock().unwrap().is_empty());
+        feed.push(1.05);
+        feed.push(1.1);
+        assert_eq!(*fired.lock().unwrap(), vec![1.1]);
+        assert_eq!(feed.pending(), 0);
+        feed.push(0.5);
+        feed.push(1.5);
+        feed.push(1.5);
+        assert_eq!(fired.lock().unwrap().len(), 1);
+    }
 }
@@ -8,7 +8,7 @@ use crate::{
     buyer,
     util::{env, pubkey_to_string, string_to_pubkey},
 };
-use crate::{constants, seller};
+use crate::{constants, seller, Provider};
 use actix_web::web::{self, Json};
 use actix_web::{get, post};
 use actix_web::{App, Error, HttpResponse, HttpServer};
@@ -74,7 +74,7 @@ async fn handle_sell(
         };
         let Ok(balance) = tokio::select! (
                    balance = seller::get_spl_balance_stream(&pubsub_client, &token_account) => balance,
-                   balance = seller::get_spl_balance(&rpc_client, &token_account) => balance,
+                   balance = Provider::wait_for_token_balance(&rpc_client, &token_account, 1, std::time::Duration::from_secs(30)) => balance,
         ) else {
             error!("could not fetch balance, exiting");
             return;
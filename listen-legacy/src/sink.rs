@@ -0,0 +1,374 @@
+use std::sync::{Arc, OnceLock};
+
+use futures_util::future::BoxFuture;
+use log::warn;
+use serde::Serialize;
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+
+use crate::{
+    tx_parser::{self, LiquidityEvent, NewPool},
+    CustomEvent, Swap,
+};
+
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// ListenerEvent is what the listener hands over to the sinks, published as
+/// JSON tagged with the `type` of the event
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListenerEvent {
+    NewPool {
+        amm_pool_id: String,
+        input_mint: String,
+        output_mint: String,
+        creator: String,
+    },
+    Swap(Swap),
+    LiquidityDrop {
+        amm_pool: String,
+        sol_reserve_before: u64,
+        sol_reserve_after: u64,
+    },
+    /// Liquidity: an LP deposit (bullish) or withdrawal (potential rug)
+    Liquidity(LiquidityEvent),
+    Custom(CustomEvent),
+}
+
+impl From<&NewPool> for ListenerEvent {
+    fn from(new_pool: &NewPool) -> Self {
+        ListenerEvent::NewPool {
+            amm_pool_id: new_pool.amm_pool_id.to_string(),
+            input_mint: new_pool.input_mint.to_string(),
+            output_mint: new_pool.output_mint.to_string(),
+            creator: new_pool.creator.to_string(),
+        }
+    }
+}
+
+impl ListenerEvent {
+    /// from_tx is the events of a Raydium tx: the swap, the LP deposits and
+    /// withdrawals, and a LiquidityDrop if it took at least drop_bps of the
+    /// SOL reserve of the pool out
+    pub fn from_tx(
+        tx: &EncodedConfirmedTransactionWithStatusMeta,
+        drop_bps: u64,
+    ) -> Vec<ListenerEvent> {
+        let mut events = vec![];
+        match tx_parser::parse_swap(tx) {
+            Ok(swap) if swap.base_amount > 0. => {
+                events.push(ListenerEvent::Swap(swap))
+            }
+            Ok(_) => {}
+            Err(e) => warn!("parse swap: {}", e),
+        }
+        match tx_parser::parse_liquidity_events(tx) {
+            Ok(liquidity) => events
+                .extend(liquidity.into_iter().map(ListenerEvent::Liquidity)),
+            Err(e) => warn!("parse liquidity events: {}", e),
+        }
+        match tx_parser::parse_sol_reserve(tx) {
+            Ok(Some(reserve))
+                if is_liquidity_drop(
+                    reserve.before,
+                    reserve.after,
+                    drop_bps,
+                ) =>
+            {
+                events.push(ListenerEvent::LiquidityDrop {
+                    amm_pool: reserve.amm_pool,
+                    sol_reserve_before: reserve.before,
+                    sol_reserve_after: reserve.after,
+                })
+            }
+            Ok(_) => {}
+            Err(e) => warn!("parse sol reserve: {}", e),
+        }
+        events
+    }
+}
+
+/// is_liquidity_drop is true if the reserve fell by at least drop_bps
+pub fn is_liquidity_drop(before: u64, after: u64, drop_bps: u64) -> bool {
+    after < before
+        && (before - after) as u128 * 10_000
+            >= before as u128 * drop_bps as u128
+}
+
+/// EventSink publishes the listener events to an external system
+pub trait EventSink: Send + Sync {
+    fn publish<'a>(
+        &'a self,
+        event: &'a ListenerEvent,
+    ) -> BoxFuture<'a, Result<(), SinkError>>;
+}
+
+static GLOBAL: OnceLock<Arc<dyn EventSink>> = OnceLock::new();
+
+/// set_global sets the sink of the listeners, once per process, returns
+/// false if it was already set
+pub fn set_global(sink: Arc<dyn EventSink>) -> bool {
+    GLOBAL.set(sink).is_ok()
+}
+
+pub fn global() -> Option<&'static dyn EventSink> {
+    GLOBAL.get().map(|sink| sink.as_ref())
+}
+
+/// publish hands the event to the global sink, if any, a failed publish is
+/// logged and dropped, the listener keeps going
+pub async fn publish(event: &ListenerEvent) {
+    if let Some(sink) = global() {
+        if let Err(e) = sink.publish(event).await {
+            warn!("sink: {}", e);
+        }
+    }
+}
+
+/// init_from_env sets the NATS sink (NATS_URL, see `NatsSink::from_env`) as
+/// the global one, returns whether a sink was set
+pub async fn init_from_env() -> Result<bool, SinkError> {
+    #[cfg(feature = "nats")]
+    {
+        if let Some(sink) = NatsSink::from_env().await? {
+            return Ok(set_global(Arc::new(sink)));
+        }
+    }
+    #[cfg(not(feature = "nats"))]
+    {
+        if std::env::var("NATS_URL").is_ok() {
+            warn!("NATS_URL is set but listen is built without `nats`");
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(feature = "nats")]
+pub use self::nats::NatsSink;
+
+#[cfg(feature = "nats")]
+mod nats {
+    use std::sync::Arc;
+
+    use futures_util::future::BoxFuture;
+    use log::{debug, info, warn};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{tcp::OwnedWriteHalf, TcpStream},
+        sync::Mutex,
+    };
+
+    use super::{EventSink, ListenerEvent, SinkError};
+
+    /// NatsSink publishes the events to a NATS subject over the plain text
+    /// protocol (CONNECT/PUB/PING/PONG), no auth and no TLS
+    pub struct NatsSink {
+        subject: String,
+        writer: Arc<Mutex<OwnedWriteHalf>>,
+    }
+
+    impl NatsSink {
+        /// connect takes the server as `nats://host:port` or `host:port`
+        pub async fn connect(
+            url: &str,
+            subject: &str,
+        ) -> Result<Self, SinkError> {
+            let addr = url.trim_start_matches("nats://");
+            let (reader, writer) =
+                TcpStream::connect(addr).await?.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let info =
+                lines.next_line().await?.ok_or("no INFO from server")?;
+            debug!("nats: {}", info);
+
+            let writer = Arc::new(Mutex::new(writer));
+            writer
+                .lock()
+                .await
+                .write_all(
+                    b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n",
+                )
+                .await?;
+
+            // the server drops clients that do not answer its PINGs
+            let pong_writer = writer.clone();
+            tokio::spawn(async move {
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "PING" {
+                        let mut writer = pong_writer.lock().await;
+                        if writer.write_all(b"PONG\r\n").await.is_err() {
+                            break;
+                        }
+                    } else if line.starts_with("-ERR") {
+                        warn!("nats: {}", line);
+                    }
+                }
+                warn!("nats: connection closed");
+            });
+
+            info!("nats: publishing to {} on {}", subject, addr);
+            Ok(NatsSink {
+                subject: subject.to_string(),
+                writer,
+            })
+        }
+
+        /// from_env connects to NATS_URL if set, publishing to NATS_SUBJECT
+        /// (defaults to "listen.events")
+        pub async fn from_env() -> Result<Option<Self>, SinkError> {
+            let Ok(url) = std::env::var("NATS_URL") else {
+                return Ok(None);
+            };
+            let subject = std::env::var("NATS_SUBJECT")
+                .unwrap_or_else(|_| "listen.events".to_string());
+            Ok(Some(Self::connect(&url, &subject).await?))
+        }
+    }
+
+    impl EventSink for NatsSink {
+        fn publish<'a>(
+            &'a self,
+            event: &'a ListenerEvent,
+        ) -> BoxFuture<'a, Result<(), SinkError>> {
+            Box::pin(async move {
+                let msg =
+                    pub_frame(&self.subject, &serde_json::to_vec(event)?);
+                self.writer.lock().await.write_all(&msg).await?;
+                Ok(())
+            })
+        }
+    }
+
+    /// pub_frame is the PUB message of the payload, the size is in bytes
+    fn pub_frame(subject: &str, payload: &[u8]) -> Vec<u8> {
+        let mut msg =
+            format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        msg.extend_from_slice(payload);
+        msg.extend_from_slice(b"\r\n");
+        msg
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        use super::*;
+
+        #[test]
+        fn test_pub_frame() {
+            assert_eq!(
+                pub_frame("listen.events", "{\"a\":\"é\"}".as_bytes()),
+                b"PUB listen.events 10\r\n{\"a\":\"\xc3\xa9\"}\r\n".to_vec()
+            );
+        }
+
+        #[tokio::test]
+        async fn test_nats_sink_publish() {
+            let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = server.local_addr().unwrap();
+            let accept = tokio::spawn(async move {
+                let (mut conn, _) = server.accept().await.unwrap();
+                conn.write_all(b"INFO {}\r\n").await.unwrap();
+                let mut received = vec![];
+                let mut buf = [0u8; 1024];
+                // CONNECT, then the PUB line and the payload
+                while received.windows(2).filter(|w| *w == b"\r\n").count() < 3
+                {
+                    let n = conn.read(&mut buf).await.unwrap();
+                    assert!(n > 0, "connection closed");
+                    received.extend_from_slice(&buf[..n]);
+                }
+                String::from_utf8(received).unwrap()
+            });
+
+            let sink = NatsSink::connect(&format!("nats://{}", addr), "test")
+                .await
+                .unwrap();
+            let event = ListenerEvent::LiquidityDrop {
+                amm_pool: "pool".to_string(),
+                sol_reserve_before: 100,
+                sol_reserve_after: 10,
+            };
+            sink.publish(&event).await.unwrap();
+
+            let received = accept.await.unwrap();
+            let (connect, msg) = received.split_once("\r\n").unwrap();
+            assert!(connect.starts_with("CONNECT "));
+            let payload = serde_json::to_string(&event).unwrap();
+            assert_eq!(
+                msg,
+                format!("PUB test {}\r\n{}\r\n", payload.len(), payload)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_event_json() {
+        let event = ListenerEvent::LiquidityDrop {
+            amm_pool: "pool".to_string(),
+            sol_reserve_before: 100,
+            sol_reserve_after: 10,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "type": "liquidity_drop",
+                "amm_pool": "pool",
+                "sol_reserve_before": 100,
+                "sol_reserve_after": 10,
+            })
+        );
+        let event = ListenerEvent::from(&NewPool::default());
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "new_pool");
+        assert_eq!(
+            json["creator"],
+            solana_sdk::pubkey::Pubkey::default().to_string()
+        );
+    }
+
+    #[test]
+    fn test_listener_events_from_tx() {
+        let tx = serde_json::from_reader(
+            std::fs::File::open("mock/tx.json").unwrap(),
+        )
+        .unwrap();
+        // a buy, the SOL reserve grows
+        let events = ListenerEvent::from_tx(&tx, 1);
+        assert_eq!(events.len(), 1);
+        let json = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(json["type"], "swap");
+        assert!(!json["signer"].as_str().unwrap().is_empty());
+
+        let mut raw: serde_json::Value = serde_json::from_reader(
+            std::fs::File::open("mock/tx.json").unwrap(),
+        )
+        .unwrap();
+        // the same accounts as a withdrawal from the pool
+        raw["transaction"]["message"]["instructions"][4]["data"] =
+            bs58::encode([4u8, 0, 0, 0, 0, 0, 0, 0, 0])
+                .into_string()
+                .into();
+        let tx = serde_json::from_value(raw).unwrap();
+        let events = ListenerEvent::from_tx(&tx, 1);
+        let json = serde_json::to_value(&events).unwrap();
+        assert_eq!(json[1]["type"], "liquidity");
+        assert_eq!(json[1]["kind"], "withdraw");
+        assert_eq!(
+            json[1]["amm_pool"],
+            "AMfXmVGcpmzx4ZX4bTgi2M5tfRvZquNeyvtpeaw1Ccci"
+        );
+
+        assert!(is_liquidity_drop(100, 90, 1_000));
+        assert!(!is_liquidity_drop(100, 91, 1_000));
+        assert!(!is_liquidity_drop(100, 110, 0));
+        assert!(!is_liquidity_drop(0, 0, 0));
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::raydium::SwapResult;
+
+/// Distribution summarizes a set of samples, p95 is the nearest rank
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// from_samples is None if there are no samples
+    pub fn from_samples(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = |q: f64| {
+            let idx = (q * sorted.len() as f64).ceil() as usize;
+            sorted[idx.clamp(1, sorted.len()) - 1]
+        };
+        Some(Distribution {
+            count: sorted.len(),
+            min: sorted[0],
+            median: rank(0.5),
+            p95: rank(0.95),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// SessionSummary is the summary of the swaps of a session, realized
+/// slippage is in bps of the quote (positive is a worse fill), swaps
+/// without a quote or a measured output are left out of it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SessionSummary {
+    pub swaps: usize,
+    pub confirmed: usize,
+    pub expired: usize,
+    pub total_fees_lamports: u64,
+    pub slippage_bps: Option<Distribution>,
+    pub fee_lamports: Option<Distribution>,
+    pub landing_ms: Option<Distribution>,
+}
+
+#[derive(Default)]
+struct Samples {
+    swaps: usize,
+    confirmed: usize,
+    slippage_bps: Vec<f64>,
+    fee_lamports: Vec<f64>,
+    landing_ms: Vec<f64>,
+}
+
+/// SessionStats collects the `SwapResult`s of a trading session, share it
+/// behind an Arc, e.g. through `RaydiumBuilder::session_stats`
+#[derive(Default)]
+pub struct SessionStats {
+    samples: Mutex<Samples>,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats::default()
+    }
+
+    pub fn record(&self, result: &SwapResult) {
+        let mut samples = self.samples.lock().expect("stats poisoned");
+        samples.swaps += 1;
+        if !result.confirmed {
+            return;
+        }
+        samples.confirmed += 1;
+        samples.landing_ms.push(result.landing_ms as f64);
+        if let Some(fee) = result.fee_lamports {
+            samples.fee_lamports.push(fee as f64);
+        }
+        if let Some(slippage_bps) = result.realized_slippage_bps() {
+            samples.slippage_bps.push(slippage_bps);
+        }
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        let samples = self.samples.lock().expect("stats poisoned");
+        SessionSummary {
+            swaps: samples.swaps,
+            confirmed: samples.confirmed,
+            expired: samples.swaps - samples.confirmed,
+            total_fees_lamports: samples.fee_lamports.iter().sum::<f64>()
+                as u64,
+            slippage_bps: Distribution::from_samples(&samples.slippage_bps),
+            fee_lamports: Distribution::from_samples(&samples.fee_lamports),
+            landing_ms: Distribution::from_samples(&samples.landing_ms),
+        }
+    }
+
+    pub fn reset(&self) {
+        *self.samples.lock().expect("stats poisoned") = Samples::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(
+        quoted: u64,
+        out: u64,
+        fee: u64,
+        landing_ms: u128,
+    ) -> SwapResult {
+        SwapResult {
+            signature: String::new(),
+            confirmed: true,
+            last_valid_block_height: 0,
+            remaining_blocks: 0,
+            quoted_out: Some(quoted),
+            amount_out: Some(out),
+            fee_lamports: Some(fee),
+            landing_ms,
+        }
+    }
+
+    #[test]
+    fn test_distribution() {
+        let samples = (1..=100).map(|i| i as f64).collect::<Vec<f64>>();
+        let distribution = Distribution::from_samples(&samples).unwrap();
+        assert_eq!(distribution.min, 1.);
+        assert_eq!(distribution.median, 50.);
+        assert_eq!(distribution.p95, 95.);
+        assert_eq!(distribution.max, 100.);
+        assert!(Distribution::from_samples(&[]).is_none());
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let stats = SessionStats::new();
+        stats.record(&result(10_000, 9_900, 5_000, 800));
+        stats.record(&result(10_000, 10_050, 15_000, 1_200));
+        stats.record(&SwapResult {
+            confirmed: false,
+            ..result(10_000, 0, 0, 0)
+        });
+        let summary = stats.summary();
+        assert_eq!(
+            (summary.swaps, summary.confirmed, summary.expired),
+            (3, 2, 1)
+        );
+        assert_eq!(summary.total_fees_lamports, 20_000);
+        let slippage = summary.slippage_bps.unwrap();
+        assert_eq!((slippage.min, slippage.max), (-50., 100.));
+        assert_eq!(summary.landing_ms.unwrap().median, 800.);
+    }
+}
@@ -0,0 +1,128 @@
+//! log throttling for the high-frequency log sites (every log of a
+//! subscription, every price tick), each site logs at most one in
+//! `LISTEN_LOG_SAMPLE_N` events and at most `LISTEN_LOG_MAX_PER_SEC` lines
+//! a second (0 lifts the cap), the number of lines skipped is reported with
+//! the next line that goes through
+//!
+//! only the info, debug and trace levels can be throttled, warnings and
+//! errors always go through as plain `warn!`/`error!`
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::util::env_or;
+
+pub struct LogThrottle {
+    every_n: u64,
+    per_sec: u64,
+    start: Instant,
+    seen: AtomicU64,
+    window: AtomicU64,
+    in_window: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl LogThrottle {
+    pub fn new(every_n: u64, per_sec: u64) -> Self {
+        LogThrottle {
+            every_n: every_n.max(1),
+            per_sec,
+            start: Instant::now(),
+            seen: AtomicU64::new(0),
+            window: AtomicU64::new(0),
+            in_window: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// from_env reads LISTEN_LOG_SAMPLE_N (default 1, every event) and
+    /// LISTEN_LOG_MAX_PER_SEC (default 0, no cap)
+    pub fn from_env() -> Self {
+        LogThrottle::new(
+            env_or("LISTEN_LOG_SAMPLE_N", 1),
+            env_or("LISTEN_LOG_MAX_PER_SEC", 0),
+        )
+    }
+
+    /// allow counts an event, if it is to be logged returns the number of
+    /// events suppressed since the last one that was
+    pub fn allow(&self) -> Option<u64> {
+        self.allow_at(self.start.elapsed().as_secs())
+    }
+
+    fn allow_at(&self, second: u64) -> Option<u64> {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+        let sampled = seen % self.every_n == 0;
+        let capped = self.per_sec > 0 && {
+            if self.window.swap(second, Ordering::Relaxed) != second {
+                self.in_window.store(0, Ordering::Relaxed);
+            }
+            sampled
+                && self.in_window.fetch_add(1, Ordering::Relaxed)
+                    >= self.per_sec
+        };
+        if sampled && !capped {
+            Some(self.suppressed.swap(0, Ordering::Relaxed))
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// throttled logs at info, debug or trace through a `LogThrottle` of the
+/// call site (configured from env on first use), nothing is counted if the
+/// level is disabled
+///
+/// `throttled!(info, "price: {}", price);`
+#[macro_export]
+macro_rules! throttled {
+    (info, $($arg:tt)+) => {
+        $crate::throttled!(@log info, Info, $($arg)+)
+    };
+    (debug, $($arg:tt)+) => {
+        $crate::throttled!(@log debug, Debug, $($arg)+)
+    };
+    (trace, $($arg:tt)+) => {
+        $crate::throttled!(@log trace, Trace, $($arg)+)
+    };
+    (@log $macro:ident, $level:ident, $($arg:tt)+) => {
+        if log::log_enabled!(log::Level::$level) {
+            static THROTTLE: std::sync::OnceLock<
+                $crate::throttle::LogThrottle,
+            > = std::sync::OnceLock::new();
+            let throttle = THROTTLE
+                .get_or_init($crate::throttle::LogThrottle::from_env);
+            if let Some(suppressed) = throttle.allow() {
+                if suppressed > 0 {
+                    log::$macro!("({} similar lines suppressed)", suppressed);
+                }
+                log::$macro!($($arg)+);
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_throttle() {
+        let sampled = LogThrottle::new(3, 0);
+        let allowed = (0..7)
+            .map(|_| sampled.allow_at(0))
+            .collect::<Vec<Option<u64>>>();
+        assert_eq!(
+            allowed,
+            vec![Some(0), None, None, Some(2), None, None, Some(2)]
+        );
+
+        let capped = LogThrottle::new(1, 2);
+        assert_eq!(capped.allow_at(0), Some(0));
+        assert_eq!(capped.allow_at(0), Some(0));
+        assert_eq!(capped.allow_at(0), None);
+        assert_eq!(capped.allow_at(0), None);
+        // the cap resets with the next second
+        assert_eq!(capped.allow_at(1), Some(2));
+    }
+}
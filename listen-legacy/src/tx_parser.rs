@@ -1,8 +1,15 @@
 use base64::Engine;
 use core::panic;
-use log::info;
-use std::str::FromStr;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
 
+use prometheus::{IntCounter, Registry};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{pubkey::Pubkey, transaction::Transaction};
 use solana_transaction_status::{
     option_serializer::OptionSerializer,
@@ -11,7 +18,7 @@ use solana_transaction_status::{
 };
 use timed::timed;
 
-use crate::{constants, util, Swap};
+use crate::{constants, util, Provider, Swap, TradeSide};
 
 #[derive(Debug, Default)]
 pub struct NewPool {
@@ -21,6 +28,136 @@ pub struct NewPool {
     pub creator: Pubkey,
 }
 
+/// TokenTransfer is a single SPL token transfer found in the inner
+/// instructions, amount is in the raw units of the mint
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TokenTransfer {
+    pub source: String,
+    pub destination: String,
+    pub amount: u64,
+    pub mint: String,
+}
+
+/// TxCache is an LRU cache of fetched confirmed txs keyed by signature,
+/// confirmed txs never change so entries are only ever evicted by size
+pub struct TxCache {
+    capacity: usize,
+    inner: Mutex<TxCacheInner>,
+    hits: IntCounter,
+    misses: IntCounter,
+}
+
+#[derive(Default)]
+struct TxCacheInner {
+    txs: HashMap<String, EncodedConfirmedTransactionWithStatusMeta>,
+    /// order: least recently used first
+    order: VecDeque<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TxCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl TxCache {
+    pub fn new(capacity: usize) -> Self {
+        TxCache {
+            capacity: capacity.max(1),
+            inner: Mutex::new(TxCacheInner::default()),
+            hits: IntCounter::new("tx_cache_hits", "Tx cache hits")
+                .expect("tx_cache_hits"),
+            misses: IntCounter::new("tx_cache_misses", "Tx cache misses")
+                .expect("tx_cache_misses"),
+        }
+    }
+
+    /// register exports the hit and miss counters
+    pub fn register(
+        &self,
+        registry: &Registry,
+    ) -> Result<(), prometheus::Error> {
+        registry.register(Box::new(self.hits.clone()))?;
+        registry.register(Box::new(self.misses.clone()))?;
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        signature: &str,
+    ) -> Option<EncodedConfirmedTransactionWithStatusMeta> {
+        let mut inner = self.inner.lock().expect("tx cache poisoned");
+        let Some(tx) = inner.txs.get(signature).cloned() else {
+            self.misses.inc();
+            return None;
+        };
+        if let Some(pos) = inner.order.iter().position(|s| s == signature) {
+            let signature = inner.order.remove(pos).expect("position");
+            inner.order.push_back(signature);
+        }
+        self.hits.inc();
+        Some(tx)
+    }
+
+    pub fn insert(
+        &self,
+        signature: &str,
+        tx: EncodedConfirmedTransactionWithStatusMeta,
+    ) {
+        let mut inner = self.inner.lock().expect("tx cache poisoned");
+        if inner.txs.insert(signature.to_string(), tx).is_some() {
+            return;
+        }
+        inner.order.push_back(signature.to_string());
+        while inner.order.len() > self.capacity {
+            if let Some(evicted) = inner.order.pop_front() {
+                inner.txs.remove(&evicted);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> TxCacheStats {
+        TxCacheStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            len: self.inner.lock().expect("tx cache poisoned").txs.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+static TX_CACHE: OnceLock<TxCache> = OnceLock::new();
+
+/// enable_tx_cache turns on the cache used by `fetch_tx`, the first call wins
+pub fn enable_tx_cache(capacity: usize) -> &'static TxCache {
+    TX_CACHE.get_or_init(|| TxCache::new(capacity))
+}
+
+pub fn tx_cache() -> Option<&'static TxCache> {
+    TX_CACHE.get()
+}
+
+/// fetch_tx is Provider::get_tx reading through the tx cache once enabled
+pub async fn fetch_tx(
+    rpc_client: &RpcClient,
+    signature: &str,
+) -> Result<
+    EncodedConfirmedTransactionWithStatusMeta,
+    Box<dyn std::error::Error>,
+> {
+    let Some(cache) = tx_cache() else {
+        return Provider::get_tx(rpc_client, signature).await;
+    };
+    if let Some(tx) = cache.get(signature) {
+        return Ok(tx);
+    }
+    let tx = Provider::get_tx(rpc_client, signature).await?;
+    cache.insert(signature, tx.clone());
+    Ok(tx)
+}
+
 #[timed(duration(printer = "info!"))]
 pub fn parse_mint(
     tx: &EncodedConfirmedTransactionWithStatusMeta,
@@ -74,9 +211,26 @@ pub fn parse_tmp_account(
     Ok(tmp_account)
 }
 
-pub fn parse_signer() -> Result<String, Box<dyn std::error::Error>> {
-    // TODO
-    Err("Not implemented".into())
+/// parse_signer returns the fee payer, the first signer of the tx
+pub fn parse_signer(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match &tx.transaction.transaction {
+        EncodedTransaction::Json(ui_tx) => match &ui_tx.message {
+            UiMessage::Parsed(msg) => msg
+                .account_keys
+                .iter()
+                .find(|account| account.signer)
+                .map(|account| account.pubkey.clone())
+                .ok_or("Signer not found in tx".into()),
+            UiMessage::Raw(msg) => msg
+                .account_keys
+                .first()
+                .cloned()
+                .ok_or("Signer not found in tx".into()),
+        },
+        _ => Err("Only EncodedTransaction::Json txs are supported".into()),
+    }
 }
 
 #[timed(duration(printer = "info!"))]
@@ -160,6 +314,8 @@ pub fn parse_swap(
     tx: &EncodedConfirmedTransactionWithStatusMeta,
 ) -> Result<Swap, Box<dyn std::error::Error>> {
     let mut swap = Swap::default();
+    let mints = self::parse_token_account_mints(tx);
+    let sol = constants::SOLANA_PROGRAM_ID.to_string();
     if let Some(meta) = &tx.transaction.meta {
         let all_ixs = self::deserialize(&meta.inner_instructions);
         for ixs in all_ixs {
@@ -178,21 +334,40 @@ pub fn parse_swap(
                                 .as_str()
                                 .ok_or("Failed to get string")?
                                 .parse::<f64>()?;
-                            // if the authority is raydium, it is the shitcoin, otherwise SOL
-                            if parsed_ix.parsed["info"]["authority"]
+                            let from_pool = parsed_ix.parsed["info"]
+                                ["authority"]
                                 == constants::RAYDIUM_AUTHORITY_V4_PUBKEY
-                                    .to_string()
-                            {
-                                // shitcoin == base quote, like POOP/SOL
-                                swap.base_mint = self::parse_mint(tx)?;
-                                swap.base_amount = amount;
+                                    .to_string();
+                            let mint = ["source", "destination"]
+                                .iter()
+                                .filter_map(|account| {
+                                    parsed_ix.parsed["info"][account].as_str()
+                                })
+                                .find_map(|account| mints.get(account));
+                            // without the token balances, what raydium
+                            // sends is taken for the shitcoin, otherwise SOL
+                            let is_sol = match mint {
+                                Some(mint) => *mint == sol,
+                                None => !from_pool,
+                            };
+                            // SOL in or the shitcoin out is a buy
+                            swap.side = Some(if from_pool != is_sol {
+                                TradeSide::Buy
                             } else {
+                                TradeSide::Sell
+                            });
+                            if is_sol {
                                 // TODO not sure how to support non-SOL
-                                // swaps yet also does not return the
-                                // mint token properly
-                                swap.quote_mint =
-                                    constants::SOLANA_PROGRAM_ID.to_string();
+                                // swaps yet
+                                swap.quote_mint = sol.clone();
                                 swap.quote_amount = amount;
+                            } else {
+                                // shitcoin == base quote, like POOP/SOL
+                                swap.base_mint = match mint {
+                                    Some(mint) => mint.clone(),
+                                    None => self::parse_mint(tx)?,
+                                };
+                                swap.base_amount = amount;
                             };
                         }
                     }
@@ -202,10 +377,409 @@ pub fn parse_swap(
             }
         }
     }
+    // the transfers come on top of the swap, a malformed one leaves them
+    // empty instead of failing the parse
+    swap.transfers = self::parse_token_transfers(tx).unwrap_or_else(|e| {
+        warn!("parse token transfers: {}", e);
+        vec![]
+    });
+    swap.signer = self::parse_signer(tx).unwrap_or_default();
 
     Ok(swap)
 }
 
+/// raydium amm v4 instruction tags
+const RAYDIUM_DEPOSIT_TAG: u8 = 3;
+const RAYDIUM_WITHDRAW_TAG: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityKind {
+    Deposit,
+    Withdraw,
+}
+
+/// LiquidityEvent is a deposit into or a withdrawal from a Raydium pool,
+/// amounts are raw, lp_amount is minted on deposit and burned on withdraw
+#[derive(Debug, Clone, Serialize)]
+pub struct LiquidityEvent {
+    pub signature: String,
+    pub kind: LiquidityKind,
+    pub amm_pool: String,
+    pub owner: String,
+    pub coin_mint: String,
+    pub coin_amount: u64,
+    pub pc_mint: String,
+    pub pc_amount: u64,
+    pub lp_mint: String,
+    pub lp_amount: u64,
+}
+
+/// parse_liquidity_events finds the top-level deposit/withdraw instructions
+/// of the Raydium AMM v4 program and sums their inner token movements, the
+/// accounts of both instructions start with: token program, amm, authority,
+/// open orders, target orders, lp mint, coin vault, pc vault
+#[timed(duration(printer = "info!"))]
+pub fn parse_liquidity_events(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Vec<LiquidityEvent>, Box<dyn std::error::Error>> {
+    let mut events = vec![];
+    let signature = match &tx.transaction.transaction {
+        EncodedTransaction::Json(ui_tx) => {
+            ui_tx.signatures.first().cloned().unwrap_or_default()
+        }
+        _ => {
+            return Err(
+                "Only EncodedTransaction::Json txs are supported".into()
+            )
+        }
+    };
+    let inner_instructions = match tx
+        .transaction
+        .meta
+        .as_ref()
+        .map(|meta| &meta.inner_instructions)
+    {
+        Some(OptionSerializer::Some(inner_instructions)) => {
+            inner_instructions.as_slice()
+        }
+        _ => &[],
+    };
+    let mints = self::parse_token_account_mints(tx);
+    let owner = self::parse_signer(tx).unwrap_or_default();
+    let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY.to_string();
+    for (index, ix) in self::parse_instructions(tx)?.iter().enumerate() {
+        let UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(ix)) =
+            ix
+        else {
+            continue;
+        };
+        if ix.program_id != amm_program || ix.accounts.len() < 8 {
+            continue;
+        }
+        let kind = match bs58::decode(&ix.data).into_vec()?.first() {
+            Some(&RAYDIUM_DEPOSIT_TAG) => LiquidityKind::Deposit,
+            Some(&RAYDIUM_WITHDRAW_TAG) => LiquidityKind::Withdraw,
+            _ => continue,
+        };
+        let lp_mint = &ix.accounts[5];
+        let coin_vault = &ix.accounts[6];
+        let pc_vault = &ix.accounts[7];
+        let mut event = LiquidityEvent {
+            signature: signature.clone(),
+            kind,
+            amm_pool: ix.accounts[1].clone(),
+            owner: owner.clone(),
+            coin_mint: mints.get(coin_vault).cloned().unwrap_or_default(),
+            coin_amount: 0,
+            pc_mint: mints.get(pc_vault).cloned().unwrap_or_default(),
+            pc_amount: 0,
+            lp_mint: lp_mint.clone(),
+            lp_amount: 0,
+        };
+        let inner = inner_instructions
+            .iter()
+            .filter(|ixs| ixs.index as usize == index)
+            .flat_map(|ixs| ixs.instructions.iter());
+        for inner_ix in inner {
+            let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed_ix)) =
+                inner_ix
+            else {
+                continue;
+            };
+            let info = &parsed_ix.parsed["info"];
+            let amount = || -> Result<u64, Box<dyn std::error::Error>> {
+                Ok(info["amount"]
+                    .as_str()
+                    .ok_or("Failed to get amount")?
+                    .parse::<u64>()?)
+            };
+            match (kind, parsed_ix.parsed["type"].as_str()) {
+                (LiquidityKind::Deposit, Some("mintTo"))
+                | (LiquidityKind::Withdraw, Some("burn"))
+                    if info["mint"] == *lp_mint =>
+                {
+                    event.lp_amount += amount()?;
+                }
+                (_, Some("transfer")) => {
+                    let vault = match kind {
+                        LiquidityKind::Deposit => info["destination"].as_str(),
+                        LiquidityKind::Withdraw => info["source"].as_str(),
+                    };
+                    if vault == Some(coin_vault.as_str()) {
+                        event.coin_amount += amount()?;
+                    } else if vault == Some(pc_vault.as_str()) {
+                        event.pc_amount += amount()?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// SolReserve is the SOL vault balance (raw) of the Raydium pool of a tx,
+/// before and after it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SolReserve {
+    pub amm_pool: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// parse_sol_reserve reads the SOL vault (the WSOL account of the Raydium
+/// authority) from the token balances and the pool from the first AMM v4
+/// instruction, txs touching more than one SOL vault are skipped
+pub fn parse_sol_reserve(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Option<SolReserve>, Box<dyn std::error::Error>> {
+    let meta = tx.transaction.meta.as_ref().ok_or("tx has no meta")?;
+    let authority = constants::RAYDIUM_AUTHORITY_V4_PUBKEY.to_string();
+    let sol = constants::SOLANA_PROGRAM_ID.to_string();
+    let vaults = |balances: &OptionSerializer<
+        Vec<solana_transaction_status::UiTransactionTokenBalance>,
+    >|
+     -> Result<Vec<(u8, u64)>, Box<dyn std::error::Error>> {
+        let OptionSerializer::Some(balances) = balances else {
+            return Ok(vec![]);
+        };
+        let mut vaults = vec![];
+        for balance in balances {
+            if balance.mint == sol
+                && matches!(
+                    &balance.owner,
+                    OptionSerializer::Some(o) if *o == authority
+                )
+            {
+                vaults.push((
+                    balance.account_index,
+                    balance.ui_token_amount.amount.parse::<u64>()?,
+                ));
+            }
+        }
+        Ok(vaults)
+    };
+    let pre = vaults(&meta.pre_token_balances)?;
+    let post = vaults(&meta.post_token_balances)?;
+    let ([(pre_index, before)], [(post_index, after)]) =
+        (pre.as_slice(), post.as_slice())
+    else {
+        return Ok(None);
+    };
+    if pre_index != post_index {
+        return Ok(None);
+    }
+    let amm_program = constants::RAYDIUM_LIQUIDITY_POOL_V4_PUBKEY.to_string();
+    let amm_pool =
+        self::parse_instructions(tx)?
+            .into_iter()
+            .find_map(|ix| match ix {
+                UiInstruction::Parsed(
+                    UiParsedInstruction::PartiallyDecoded(ix),
+                ) if ix.program_id == amm_program && ix.accounts.len() > 1 => {
+                    Some(ix.accounts[1].clone())
+                }
+                _ => None,
+            });
+    Ok(amm_pool.map(|amm_pool| SolReserve {
+        amm_pool,
+        before: *before,
+        after: *after,
+    }))
+}
+
+/// parse_token_transfers walks the inner instructions and collects every
+/// `transfer`/`transferChecked` of the token programs, the mint of plain
+/// transfers is resolved from the token balances of the involved accounts
+#[timed(duration(printer = "info!"))]
+pub fn parse_token_transfers(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<Vec<TokenTransfer>, Box<dyn std::error::Error>> {
+    let mut transfers = vec![];
+    let Some(meta) = &tx.transaction.meta else {
+        return Ok(transfers);
+    };
+    let OptionSerializer::Some(inner_instructions) = &meta.inner_instructions
+    else {
+        return Ok(transfers);
+    };
+    let mints = self::parse_token_account_mints(tx);
+    for ixs in inner_instructions {
+        for ix in &ixs.instructions {
+            let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed_ix)) =
+                ix
+            else {
+                continue;
+            };
+            if parsed_ix.program != "spl-token"
+                && parsed_ix.program != "spl-token-2022"
+            {
+                continue;
+            }
+            let info = &parsed_ix.parsed["info"];
+            let (amount, mint) = match parsed_ix.parsed["type"].as_str() {
+                Some("transfer") => (
+                    info["amount"].as_str().ok_or("Failed to get amount")?,
+                    None,
+                ),
+                Some("transferChecked") => (
+                    info["tokenAmount"]["amount"]
+                        .as_str()
+                        .ok_or("Failed to get amount")?,
+                    info["mint"].as_str(),
+                ),
+                _ => continue,
+            };
+            let source = info["source"]
+                .as_str()
+                .ok_or("Failed to get source")?
+                .to_string();
+            let destination = info["destination"]
+                .as_str()
+                .ok_or("Failed to get destination")?
+                .to_string();
+            let mint = mint
+                .or_else(|| mints.get(&source).map(String::as_str))
+                .or_else(|| mints.get(&destination).map(String::as_str))
+                .unwrap_or_default()
+                .to_string();
+            transfers.push(TokenTransfer {
+                source,
+                destination,
+                amount: amount.parse::<u64>()?,
+                mint,
+            });
+        }
+    }
+    Ok(transfers)
+}
+
+/// parse_token_account_mints maps the token accounts of the tx to their
+/// mints, based on the pre and post token balances
+fn parse_token_account_mints(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+) -> HashMap<String, String> {
+    let mut mints = HashMap::new();
+    let (Some(meta), EncodedTransaction::Json(ui_tx)) =
+        (&tx.transaction.meta, &tx.transaction.transaction)
+    else {
+        return mints;
+    };
+    let UiMessage::Parsed(msg) = &ui_tx.message else {
+        return mints;
+    };
+    for balances in [&meta.pre_token_balances, &meta.post_token_balances] {
+        if let OptionSerializer::Some(balances) = balances {
+            for balance in balances {
+                if let Some(account) =
+                    msg.account_keys.get(balance.account_index as usize)
+                {
+                    mints.insert(account.pubkey.clone(), balance.mint.clone());
+                }
+            }
+        }
+    }
+    mints
+}
+
+/// BalanceDelta is the change of the owner's SOL (lamports, fee included)
+/// and token (raw amount, summed over the owner's accounts of the mint)
+/// balances caused by a tx
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct BalanceDelta {
+    pub sol_delta: i128,
+    pub token_delta: i128,
+    pub fee: u64,
+}
+
+pub fn parse_balance_delta(
+    tx: &EncodedConfirmedTransactionWithStatusMeta,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<BalanceDelta, Box<dyn std::error::Error>> {
+    let meta = tx.transaction.meta.as_ref().ok_or("tx has no meta")?;
+    let EncodedTransaction::Json(ui_tx) = &tx.transaction.transaction else {
+        return Err("Only EncodedTransaction::Json txs are supported".into());
+    };
+    let UiMessage::Parsed(msg) = &ui_tx.message else {
+        return Err("Raw message not supported".into());
+    };
+    let owner_index = msg
+        .account_keys
+        .iter()
+        .position(|account| account.pubkey == owner.to_string())
+        .ok_or("owner is not part of the tx")?;
+    let sol_delta = meta.post_balances[owner_index] as i128
+        - meta.pre_balances[owner_index] as i128;
+
+    let token_balance = |balances: &OptionSerializer<
+        Vec<solana_transaction_status::UiTransactionTokenBalance>,
+    >|
+     -> Result<i128, Box<dyn std::error::Error>> {
+        let OptionSerializer::Some(balances) = balances else {
+            return Ok(0);
+        };
+        let mut total = 0;
+        for balance in balances {
+            if balance.mint == mint.to_string()
+                && matches!(
+                    &balance.owner,
+                    OptionSerializer::Some(o) if *o == owner.to_string()
+                )
+            {
+                total += balance.ui_token_amount.amount.parse::<i128>()?;
+            }
+        }
+        Ok(total)
+    };
+    let token_delta = token_balance(&meta.post_token_balances)?
+        - token_balance(&meta.pre_token_balances)?;
+
+    Ok(BalanceDelta {
+        sol_delta,
+        token_delta,
+        fee: meta.fee,
+    })
+}
+
+/// verify_balance_delta checks the deltas match the side of the trade (SOL
+/// for the token on a buy, the token for SOL on a sell) and that at least
+/// `min_out` was received, the SOL received on a sell excludes the fee
+pub fn verify_balance_delta(
+    delta: &BalanceDelta,
+    side: TradeSide,
+    min_out: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (spent, received) = match side {
+        TradeSide::Buy => (-delta.sol_delta, delta.token_delta),
+        TradeSide::Sell => {
+            (-delta.token_delta, delta.sol_delta + delta.fee as i128)
+        }
+    };
+    let msg = if spent <= 0 {
+        Some(format!("{:?} did not spend anything: {:?}", side, delta))
+    } else if received <= 0 {
+        Some(format!(
+            "{:?} spent {} but received nothing: {:?}",
+            side, spent, delta
+        ))
+    } else if received < min_out as i128 {
+        Some(format!(
+            "{:?} received {}, below the minimum of {}: {:?}",
+            side, received, min_out, delta
+        ))
+    } else {
+        None
+    };
+    if let Some(msg) = msg {
+        error!("swap verification failed: {}", msg);
+        return Err(msg.into());
+    }
+    Ok(())
+}
+
 #[timed(duration(printer = "info!"))]
 pub fn parse_instructions(
     tx: &EncodedConfirmedTransactionWithStatusMeta,
@@ -238,4 +812,90 @@ mod tests {
         let raw_tx = "AUqzoqR28ec7nh+XuPEaQ8GDEJRtpdlg+kILyL1G2L7kQ836DqwhnP3AvpqaiR6TmcQAGXwITv5vf0kXu3gzkgeAAQAMF847x3GeLV4VO7eN63MSkgp+jLbu8dka4OKEBgV8ak44ib5hiJjILmxsUCBjFgn2Y4f7QVjWdziegObSFvuzPznNlQVsnTFEmlxsOoCriIOCXTyj25c2tlOF7ZzEKjvB26bZiJ4Hp1o/26BYg9csa7uNdsxPX+gRuGhVECd45oLs/YVwGKWUV2zfHZE2qkR1ddcRpoZU2qv56tYLo+pLf/dTU0/nk/TVeZYR3wgCK+eFqp79zzNVkIzse9MiwmTYW79BkWX5JaN29q8gjSENqQ6KzYMz1INh3Dn4T0F9MzYwI4WjpKu1O+YYZBbhsgVU7zh/COag0oI/cp40afjQFBlhUcscPp0g/YRKOoHODeiXX/AHv7/KUbB30tLvBJnYDkCJDBbp4Rc5wIo5MjGjRzeuQLlliyB4Fqg1PomFbG3fBAU+rsk3XIxisOEl64y2KaBE51AzJolm/VGwofTutTUDBkZv5SEXMv/srbpyw5vnvIzlu8X3EmssQ5s6QAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABt324ddloZPZy+FGzut5rBy0he1fWzeROoz1hX7/AKkGm4hX/quBhPtof2NGGMA12sQ53BrrO1WYoPAAAAAAAQan1RcZLFxRIYzJTD1K8X9Y2u4Im6H9ROPb2YoAAAAAS9lJxDYCwz8gd5DtFqNSTKG5l1zxIaKpDP/sffi2is2MlyWPTiSJ8bs9ECkUjg2DC1oTmdr/EIQEjnvY2+n4WUFXsFgPMcX85EpiWC28+deO51lDoISjk7NQNo0iiZMI0hWQJd+mLVEHAODIJNao5niz8Efr3wgqrc/u/TZz5u15/f3z/y6DYV6qShd68DAYic45gTgcm5TTuPJ8CAeYLA0HUagoLaYTBf4pnDe5mOWEcdsRNQNzEPi+EEWmCvbu04oIcnkuTE3nX4rVkwcCBnT6Djny2yHDBDANkf2+Bz70YiUZmfT1BiEModHgTed58H5UTgPBni7NojS7m0KecAYLAAkDQUIPAAAAAAALAAUCQEIPAAwCAAF8AwAAAM47x3GeLV4VO7eN63MSkgp+jLbu8dka4OKEBgV8ak44IAAAAAAAAABGUFJScFpLOWs3Z21hOGlVc3BEd2JTaVlTUkZvVm45SvCRWqQLAAAApQAAAAAAAAAG3fbh12Whk9nL4UbO63msHLSF7V9bN5E6jPWFfv8AqQ0EAQ4ADwEBEBUNEQwPAhIDBBMOBQYHFAgVFgAJAQoaAf6421FmAAAAAAB0O6QLAAAAAADaSTtxfQwNAwEAAAEJAA==".to_string();
         super::decode_tx(raw_tx).unwrap();
     }
+
+    #[test]
+    fn test_verify_balance_delta() {
+        use super::{verify_balance_delta, BalanceDelta};
+        use crate::TradeSide;
+
+        let buy = BalanceDelta {
+            sol_delta: -1_005_000,
+            token_delta: 42_000,
+            fee: 5_000,
+        };
+        assert!(verify_balance_delta(&buy, TradeSide::Buy, 40_000).is_ok());
+        assert!(verify_balance_delta(&buy, TradeSide::Buy, 50_000).is_err());
+        assert!(verify_balance_delta(&buy, TradeSide::Sell, 0).is_err());
+
+        // spent SOL but received nothing
+        let rugged = BalanceDelta {
+            token_delta: 0,
+            ..buy
+        };
+        assert!(verify_balance_delta(&rugged, TradeSide::Buy, 0).is_err());
+
+        // the fee is not part of what the sell received
+        let sell = BalanceDelta {
+            sol_delta: 995_000,
+            token_delta: -42_000,
+            fee: 5_000,
+        };
+        assert!(
+            verify_balance_delta(&sell, TradeSide::Sell, 1_000_000).is_ok()
+        );
+        assert!(
+            verify_balance_delta(&sell, TradeSide::Sell, 1_000_001).is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_swap_with_malformed_transfer() {
+        let mut raw: serde_json::Value = serde_json::from_reader(
+            std::fs::File::open("mock/tx.json").unwrap(),
+        )
+        .unwrap();
+        // a transfer without an amount, outside of the swap instructions
+        raw["meta"]["innerInstructions"][0]["instructions"][0]["parsed"] =
+            serde_json::json!({"type": "transfer", "info": {}});
+        let tx = serde_json::from_value(raw).unwrap();
+        assert!(super::parse_token_transfers(&tx).is_err());
+        let swap = super::parse_swap(&tx).unwrap();
+        assert!(swap.transfers.is_empty());
+        assert!(swap.base_amount > 0.);
+    }
+
+    #[test]
+    fn test_parse_sol_reserve() {
+        let tx = serde_json::from_reader(
+            std::fs::File::open("mock/tx.json").unwrap(),
+        )
+        .unwrap();
+        let reserve = super::parse_sol_reserve(&tx).unwrap().unwrap();
+        assert_eq!(
+            reserve.amm_pool,
+            "AMfXmVGcpmzx4ZX4bTgi2M5tfRvZquNeyvtpeaw1Ccci"
+        );
+        // a buy, SOL goes into the vault
+        assert_eq!(reserve.before, 1_128_950_489_904);
+        assert_eq!(reserve.after, 1_130_450_489_904);
+    }
+
+    #[test]
+    fn test_tx_cache_evicts_least_recently_used() {
+        let tx: solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta =
+            serde_json::from_reader(
+                std::fs::File::open("mock/tx.json").unwrap(),
+            )
+            .unwrap();
+        let cache = super::TxCache::new(2);
+        cache.insert("a", tx.clone());
+        cache.insert("b", tx.clone());
+        assert!(cache.get("a").is_some());
+        cache.insert("c", tx);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.len), (3, 1, 2));
+    }
 }
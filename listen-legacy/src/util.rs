@@ -7,6 +7,14 @@ pub fn env(var: &str) -> String {
     std::env::var(var).unwrap_or_else(|_| panic!("{} env var not set", var))
 }
 
+/// env_or parses the env var, default if it is unset or does not parse
+pub fn env_or<T: FromStr>(var: &str, default: T) -> T {
+    std::env::var(var)
+        .ok()
+        .and_then(|raw| raw.parse().ok())
+        .unwrap_or(default)
+}
+
 pub fn lamports_to_sol(lamports: u64) -> f64 {
     lamports as f64 / 1000000000.0
 }
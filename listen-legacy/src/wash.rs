@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{Provider, Swap, TradeSide};
+
+/// min number of swaps for a signer to be considered for wash trading
+const MIN_SWAPS: usize = 2;
+/// min ratio of the smaller to the larger side of the signer's volume, a
+/// signer that buys and sells about the same amount ends up flat
+const MIN_BALANCE_RATIO: f64 = 0.5;
+
+/// WashReport is the wash trading estimate of a set of swaps, score is the
+/// fraction of the SOL volume that is likely wash (0 to 1)
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WashReport {
+    pub score: f64,
+    pub wash_volume_sol: f64,
+    pub total_volume_sol: f64,
+    pub suspects: Vec<String>,
+}
+
+#[derive(Default)]
+struct SignerFlow {
+    swaps: usize,
+    flips: usize,
+    last_side: Option<TradeSide>,
+    bought_sol: f64,
+    sold_sol: f64,
+}
+
+/// detect_wash_trading looks for signers trading back and forth, a signer
+/// is a suspect if it switched sides at least once and its buy and sell
+/// volumes roughly cancel out, the matched part of the volume (twice the
+/// smaller side) counts as wash; swaps are expected in chronological order
+/// (reverse of what `Provider::recent_swaps` returns) for the flip count
+pub fn detect_wash_trading(swaps: &[Swap]) -> WashReport {
+    let mut flows: HashMap<&str, SignerFlow> = HashMap::new();
+    let mut total_volume_sol = 0.;
+    for swap in swaps {
+        let Some(side) = swap.side else {
+            continue;
+        };
+        total_volume_sol += swap.sol_amount_ui;
+        let flow = flows.entry(swap.signer.as_str()).or_default();
+        flow.swaps += 1;
+        if flow.last_side.is_some_and(|last| last != side) {
+            flow.flips += 1;
+        }
+        flow.last_side = Some(side);
+        match side {
+            TradeSide::Buy => flow.bought_sol += swap.sol_amount_ui,
+            TradeSide::Sell => flow.sold_sol += swap.sol_amount_ui,
+        }
+    }
+
+    let mut wash_volume_sol = 0.;
+    let mut suspects = vec![];
+    for (signer, flow) in flows {
+        let smaller = flow.bought_sol.min(flow.sold_sol);
+        let larger = flow.bought_sol.max(flow.sold_sol);
+        if flow.swaps < MIN_SWAPS
+            || flow.flips == 0
+            || larger == 0.
+            || smaller / larger < MIN_BALANCE_RATIO
+        {
+            continue;
+        }
+        wash_volume_sol += 2. * smaller;
+        suspects.push(signer.to_string());
+    }
+    suspects.sort();
+
+    let score = if total_volume_sol > 0. {
+        (wash_volume_sol / total_volume_sol).min(1.)
+    } else {
+        0.
+    };
+    WashReport {
+        score,
+        wash_volume_sol,
+        total_volume_sol,
+        suspects,
+    }
+}
+
+/// analyze_pool runs the detection over the last `limit` swaps of the pool
+pub async fn analyze_pool(
+    rpc_client: &RpcClient,
+    amm_pool: &Pubkey,
+    limit: usize,
+) -> Result<WashReport, Box<dyn std::error::Error>> {
+    let mut swaps =
+        Provider::recent_swaps(rpc_client, amm_pool, limit).await?;
+    swaps.reverse();
+    Ok(detect_wash_trading(&swaps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap(signer: &str, side: TradeSide, sol: f64) -> Swap {
+        Swap {
+            signer: signer.to_string(),
+            side: Some(side),
+            sol_amount_ui: sol,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detect_wash_trading() {
+        let swaps = vec![
+            swap("washer", TradeSide::Buy, 1.),
+            swap("holder", TradeSide::Buy, 2.),
+            swap("washer", TradeSide::Sell, 1.),
+            swap("washer", TradeSide::Buy, 1.),
+            swap("washer", TradeSide::Sell, 1.),
+            // took profits on a part, not wash
+            swap("holder", TradeSide::Sell, 0.5),
+        ];
+        let report = detect_wash_trading(&swaps);
+        assert_eq!(report.suspects, vec!["washer".to_string()]);
+        assert!((report.total_volume_sol - 6.5).abs() < 1e-9);
+        assert!((report.wash_volume_sol - 4.).abs() < 1e-9);
+        assert!((report.score - 4. / 6.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_detect_wash_trading_organic() {
+        let swaps = vec![
+            swap("a", TradeSide::Buy, 1.),
+            swap("b", TradeSide::Buy, 1.),
+            swap("c", TradeSide::Sell, 1.),
+        ];
+        let report = detect_wash_trading(&swaps);
+        assert!(report.suspects.is_empty());
+        assert_eq!(report.score, 0.);
+        assert_eq!(detect_wash_trading(&[]).score, 0.);
+    }
+}